@@ -0,0 +1,122 @@
+//! Typed attribute columns for the table based output formats.
+//!
+//! GeoJSON properties are free-form, so the type of each column is inferred from the
+//! values of all features: integers, reals and booleans keep their type, a mix of integers
+//! and reals is real, and anything else is text.
+
+use geojson::Feature;
+use serde_json::Value;
+
+/// The type of an attribute column.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ColumnType {
+    Integer,
+    Real,
+    Boolean,
+    Text,
+}
+
+/// An attribute column, named after the property it holds.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Column {
+    pub(crate) name: String,
+    pub(crate) column_type: ColumnType,
+}
+
+/// Returns the type a single value needs, or `None` for null.
+fn value_type(value: &Value) -> Option<ColumnType> {
+    match value {
+        Value::Null => None,
+        Value::Bool(_) => Some(ColumnType::Boolean),
+        Value::Number(number) if number.is_i64() => Some(ColumnType::Integer),
+        Value::Number(_) => Some(ColumnType::Real),
+        _ => Some(ColumnType::Text),
+    }
+}
+
+/// Returns a type that holds values of both types.
+fn widen(a: ColumnType, b: ColumnType) -> ColumnType {
+    match (a, b) {
+        (a, b) if a == b => a,
+        (ColumnType::Integer, ColumnType::Real) | (ColumnType::Real, ColumnType::Integer) => {
+            ColumnType::Real
+        }
+        _ => ColumnType::Text,
+    }
+}
+
+/// Lists the columns needed for the properties of the features, in order of first
+/// appearance. Columns without any non-null value are text.
+pub(crate) fn columns(features: &[Feature]) -> Vec<Column> {
+    let mut columns: Vec<(String, Option<ColumnType>)> = Vec::new();
+    for properties in features.iter().filter_map(|f| f.properties.as_ref()) {
+        for (name, value) in properties {
+            let index = match columns.iter().position(|(n, _)| n == name) {
+                Some(index) => index,
+                None => {
+                    columns.push((name.clone(), None));
+                    columns.len() - 1
+                }
+            };
+            let column_type = &mut columns[index].1;
+            *column_type = match (*column_type, value_type(value)) {
+                (Some(a), Some(b)) => Some(widen(a, b)),
+                (a, b) => a.or(b),
+            };
+        }
+    }
+    columns
+        .into_iter()
+        .map(|(name, column_type)| Column {
+            name,
+            column_type: column_type.unwrap_or(ColumnType::Text),
+        })
+        .collect()
+}
+
+/// Formats a value for a text column. Strings are written without quotes, arrays and
+/// objects as JSON.
+pub(crate) fn text_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn feature(properties: Value) -> Feature {
+        Feature {
+            properties: properties.as_object().cloned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_columns() {
+        let features = [
+            feature(json!({"count": 1, "area": 2, "valid": true, "name": "a", "note": null})),
+            feature(json!({"count": 3, "area": 2.5, "valid": "yes", "codes": [1, 2]})),
+        ];
+        let types: Vec<(String, ColumnType)> = columns(&features)
+            .into_iter()
+            .map(|c| (c.name, c.column_type))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("area".to_string(), ColumnType::Real),
+                ("count".to_string(), ColumnType::Integer),
+                ("name".to_string(), ColumnType::Text),
+                ("note".to_string(), ColumnType::Text),
+                ("valid".to_string(), ColumnType::Text),
+                ("codes".to_string(), ColumnType::Text),
+            ]
+        );
+        assert_eq!(text_value(&json!([1, 2])), "[1,2]");
+        assert_eq!(text_value(&json!("a")), "a");
+    }
+}
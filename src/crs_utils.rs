@@ -1,7 +1,9 @@
+use std::ffi::{CStr, CString};
 use std::path::Path;
 
-use las::{Point, Reader};
+use las::{Bounds, Point, Reader};
 use log::debug;
+use proj::Proj;
 use rand::Rng;
 use thiserror::Error;
 
@@ -29,8 +31,14 @@ pub enum CrsError {
     MissingCrs,
     #[error("Unable to guess CRS from points")]
     UnableToGuessCrs,
+    #[error("Coordinates do not match the CRS: {0}")]
+    ExtentMismatch(String),
 }
 
+/// How far (in degrees) transformed bounds may fall outside the CRS area of use
+/// before the file is treated as a mismatch.
+const CRS_EXTENT_TOLERANCE: f64 = 1.0;
+
 pub fn extract_crs(file_path: &str) -> Result<Option<Crs>, CrsError> {
     let reader = Reader::from_path(file_path)?;
 
@@ -112,25 +120,40 @@ fn grab_first_n_points(mut reader: Reader, mut num_points: usize) -> Result<Vec<
     Ok(points)
 }
 
-fn grab_random_points(mut reader: Reader, num_points: usize) -> Result<Vec<Point>, CrsError> {
+fn grab_random_points(reader: Reader, num_points: usize) -> Result<Vec<Point>, CrsError> {
+    Ok(grab_random_runs(reader, num_points, 1)?
+        .into_iter()
+        .flatten()
+        .collect())
+}
+
+/// Reads `num_runs` runs of up to `run_length` consecutive points, each starting at a
+/// random point. Files that hold no more points than the runs are read whole, as a
+/// single run.
+pub(crate) fn grab_random_runs(
+    mut reader: Reader,
+    num_runs: usize,
+    run_length: usize,
+) -> Result<Vec<Vec<Point>>, CrsError> {
     let total_points = reader.header().number_of_points();
-    let mut points = Vec::with_capacity(num_points);
-    if num_points >= total_points as usize {
+    if (num_runs * run_length) as u64 >= total_points {
+        let mut points = Vec::with_capacity(total_points as usize);
         for point in reader.points() {
             points.push(point?);
         }
-        Ok(points)
+        Ok(vec![points])
     } else {
         let mut rng = rand::thread_rng();
-        for _ in 0..num_points {
+        let mut runs = Vec::with_capacity(num_runs);
+        for _ in 0..num_runs {
             let random_index = rng.gen_range(0..total_points);
 
             reader.seek(random_index)?;
-            if let Some(point) = reader.read_point()? {
-                points.push(point);
-            }
+            let mut run = Vec::with_capacity(run_length);
+            reader.read_points_into(run_length as u64, &mut run)?;
+            runs.push(run);
         }
-        Ok(points)
+        Ok(runs)
     }
 }
 
@@ -168,6 +191,66 @@ fn guess_crs_from_points(points: Vec<Point>) -> Result<String, CrsError> {
     Err(CrsError::UnableToGuessCrs)
 }
 
+/// Checks that the header bounds make sense for the resolved CRS.
+///
+/// The corners of `bounds` are transformed with `to_epsg4326` and compared against
+/// valid geographic coordinates and, when PROJ knows it, the area of use of `crs`.
+pub fn check_crs_extent(crs: &str, bounds: &Bounds, to_epsg4326: &Proj) -> Result<(), CrsError> {
+    let corners = [
+        (bounds.min.x, bounds.min.y),
+        (bounds.max.x, bounds.min.y),
+        (bounds.max.x, bounds.max.y),
+        (bounds.min.x, bounds.max.y),
+    ];
+    let mut transformed = Vec::with_capacity(corners.len());
+    for corner in corners {
+        match to_epsg4326.convert(corner) {
+            Ok((lon, lat)) if lon.is_finite() && lat.is_finite() => transformed.push((lon, lat)),
+            _ => {
+                return Err(CrsError::ExtentMismatch(format!(
+                    "unable to transform ({}, {}) to EPSG:4326",
+                    corner.0, corner.1
+                )))
+            }
+        }
+    }
+
+    for &(lon, lat) in &transformed {
+        if !(-180.0..=180.0).contains(&lon) || !(-90.0..=90.0).contains(&lat) {
+            return Err(CrsError::ExtentMismatch(format!(
+                "({}, {}) is not a valid longitude/latitude",
+                lon, lat
+            )));
+        }
+    }
+
+    let area = Proj::new(crs)
+        .ok()
+        .and_then(|proj| proj.area_of_use().ok())
+        .and_then(|(area, _)| area);
+    if let Some(area) = area {
+        debug!("Area of use for {}: {:?}", crs, area);
+        for &(lon, lat) in &transformed {
+            let in_lon = if area.west <= area.east {
+                lon >= area.west - CRS_EXTENT_TOLERANCE && lon <= area.east + CRS_EXTENT_TOLERANCE
+            } else {
+                // area of use crosses the antimeridian
+                lon >= area.west - CRS_EXTENT_TOLERANCE || lon <= area.east + CRS_EXTENT_TOLERANCE
+            };
+            let in_lat = lat >= area.south - CRS_EXTENT_TOLERANCE
+                && lat <= area.north + CRS_EXTENT_TOLERANCE;
+            if !in_lon || !in_lat {
+                return Err(CrsError::ExtentMismatch(format!(
+                    "({}, {}) is outside the area of use ({}, {}, {}, {})",
+                    lon, lat, area.west, area.south, area.east, area.north
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub fn extract_crs_from_geotiff(
     geo_key_directory: &[u8],
     geo_double_params: Option<&[u8]>,
@@ -191,13 +274,13 @@ pub fn extract_crs_from_geotiff(
             2048 => {
                 // GeographicTypeGeoKey
                 if value_offset != 32767 && value_offset != 65535 {
-                    proj_string = format!("EPSG:{} ", value_offset);
+                    proj_string = format!("EPSG:{}", value_offset);
                 }
             }
             3072 => {
                 // ProjectedCSTypeGeoKey
                 if value_offset != 32767 && value_offset != 65535 {
-                    proj_string = format!("EPSG:{} ", value_offset);
+                    proj_string = format!("EPSG:{}", value_offset);
                 }
             }
 
@@ -230,6 +313,34 @@ pub fn extract_crs_from_geotiff(
     Ok(proj_string.trim().to_string())
 }
 
+/// Returns the WKT of a CRS given in any form PROJ understands, such as `EPSG:2193`, as
+/// the GDAL flavour of WKT 1 that PDAL and GDAL write. The `proj` crate has no WKT export,
+/// so this goes through the PROJ C API.
+pub(crate) fn crs_wkt(crs: &str) -> Option<String> {
+    let definition = CString::new(crs.trim()).ok()?;
+    // SAFETY: the context and object are created and destroyed here, and the WKT is
+    // copied out before the object that owns it is destroyed
+    unsafe {
+        let context = proj_sys::proj_context_create();
+        let object = proj_sys::proj_create(context, definition.as_ptr());
+        let mut wkt = None;
+        if !object.is_null() {
+            let text = proj_sys::proj_as_wkt(
+                context,
+                object,
+                proj_sys::PJ_WKT_TYPE_PJ_WKT1_GDAL,
+                std::ptr::null(),
+            );
+            if !text.is_null() {
+                wkt = Some(CStr::from_ptr(text).to_string_lossy().into_owned());
+            }
+            proj_sys::proj_destroy(object);
+        }
+        proj_sys::proj_context_destroy(context);
+        wkt
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use test_log::test;
@@ -419,10 +530,50 @@ mod tests {
             // Check if proj accepts the GeoTIFF data
             let crs_string =
                 extract_crs_from_geotiff(&data1, data2.as_deref(), data3.as_deref()).unwrap();
+            assert_eq!(crs_string, crs_string.trim());
             let proj = Proj::new_known_crs(&crs_string, "EPSG:4326", None);
             assert!(proj.is_ok());
         } else {
             panic!("Expected CRS information in VLRs");
         }
     }
+
+    fn bounds(min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Bounds {
+        Bounds {
+            min: las::Vector {
+                x: min_x,
+                y: min_y,
+                z: 0.0,
+            },
+            max: las::Vector {
+                x: max_x,
+                y: max_y,
+                z: 0.0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_check_crs_extent_projected_coordinates_in_geographic_crs() {
+        let to_epsg4326 = Proj::new_known_crs("EPSG:4326", "EPSG:4326", None).unwrap();
+        let bounds = bounds(1_750_000.0, 5_900_000.0, 1_751_000.0, 5_901_000.0);
+        let result = check_crs_extent("EPSG:4326", &bounds, &to_epsg4326);
+        assert!(matches!(result, Err(CrsError::ExtentMismatch(_))));
+    }
+
+    #[test]
+    fn test_check_crs_extent_matching_crs() {
+        let to_epsg4326 = Proj::new_known_crs("EPSG:2193", "EPSG:4326", None).unwrap();
+        let bounds = bounds(1_750_000.0, 5_900_000.0, 1_751_000.0, 5_901_000.0);
+        assert!(check_crs_extent("EPSG:2193", &bounds, &to_epsg4326).is_ok());
+    }
+
+    #[test]
+    fn test_check_crs_extent_wrong_zone() {
+        // NZTM coordinates labelled as UTM zone 33N land far outside the zone
+        let to_epsg4326 = Proj::new_known_crs("EPSG:32633", "EPSG:4326", None).unwrap();
+        let bounds = bounds(1_750_000.0, 5_900_000.0, 1_751_000.0, 5_901_000.0);
+        let result = check_crs_extent("EPSG:32633", &bounds, &to_epsg4326);
+        assert!(matches!(result, Err(CrsError::ExtentMismatch(_))));
+    }
 }
@@ -0,0 +1,88 @@
+use std::fs::{File, Metadata};
+use std::io::{self, Read};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::Xxh3;
+
+/// Size of the buffer files are hashed in.
+const HASH_BUFFER_SIZE: usize = 1 << 20;
+
+/// Content hashes that can be recorded for each source file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum HashAlgorithm {
+    /// SHA-256, for comparing against checksums from other tools.
+    Sha256,
+    /// 64 bit XXH3, much faster than SHA-256 but not cryptographic.
+    Xxh3,
+}
+
+/// Hashes the content of a file, returning the hash as `<algorithm>:<hex digest>`.
+pub(crate) fn hash_file(file_path: &str, algorithm: HashAlgorithm) -> io::Result<String> {
+    let mut file = File::open(file_path)?;
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+    match algorithm {
+        HashAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            loop {
+                let read = file.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+            }
+            let digest: String = hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            Ok(format!("sha256:{}", digest))
+        }
+        HashAlgorithm::Xxh3 => {
+            let mut hasher = Xxh3::new();
+            loop {
+                let read = file.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..read]);
+            }
+            Ok(format!("xxh3:{:016x}", hasher.digest()))
+        }
+    }
+}
+
+/// Returns the modification time of a file as a UTC ISO-8601 timestamp.
+pub(crate) fn modified_time(metadata: &Metadata) -> Option<String> {
+    let modified: DateTime<Utc> = metadata.modified().ok()?.into();
+    Some(modified.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// Returns the creation time of a file as a UTC ISO-8601 timestamp, on file systems that
+/// record it.
+pub(crate) fn created_time(metadata: &Metadata) -> Option<String> {
+    let created: DateTime<Utc> = metadata.created().ok()?.into();
+    Some(created.to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_hash_file() {
+        let temp_dir = tempdir().unwrap();
+        let file_path = temp_dir.path().join("abc.las");
+        std::fs::write(&file_path, b"abc").unwrap();
+        let file_path = file_path.to_str().unwrap();
+
+        assert_eq!(
+            hash_file(file_path, HashAlgorithm::Sha256).unwrap(),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let xxh3 = hash_file(file_path, HashAlgorithm::Xxh3).unwrap();
+        assert!(xxh3.starts_with("xxh3:"));
+        assert_eq!(xxh3.len(), 21);
+    }
+}
@@ -0,0 +1,493 @@
+//! Writing the outlines to FlatGeobuf.
+//!
+//! A FlatGeobuf file holds a header, a packed Hilbert R-tree over the feature bounding
+//! boxes and the features, each header and feature encoded as a size prefixed FlatBuffer.
+//! Clients read the header and the index, then only the features in the bbox they query,
+//! e.g. with HTTP range requests.
+//!
+//! The FlatBuffers are encoded here rather than with the flatbuffers crate: each table is
+//! written front to back, its vtable first and the strings, vectors and tables it refers to
+//! after it, since offsets to them must point forward.
+
+use std::cmp::Reverse;
+use std::io::{self, Write};
+
+use geojson::{Feature, Value};
+
+use crate::columns::{columns, text_value, Column, ColumnType};
+use crate::hilbert::sort_by_hilbert;
+use crate::wkb::geometry_bbox;
+
+const MAGIC: [u8; 8] = [b'f', b'g', b'b', 3, b'f', b'g', b'b', 0];
+/// Number of children of each node of the R-tree.
+const INDEX_NODE_SIZE: u16 = 16;
+
+const GEOMETRY_UNKNOWN: u8 = 0;
+const GEOMETRY_POLYGON: u8 = 3;
+const GEOMETRY_MULTIPOLYGON: u8 = 6;
+
+const COLUMN_BOOL: u8 = 2;
+const COLUMN_LONG: u8 = 7;
+const COLUMN_DOUBLE: u8 = 10;
+const COLUMN_STRING: u8 = 11;
+
+/// A FlatBuffer table field.
+enum Field {
+    UByte(u8),
+    UShort(u16),
+    Int(i32),
+    ULong(u64),
+    String(String),
+    Doubles(Vec<f64>),
+    UInts(Vec<u32>),
+    UBytes(Vec<u8>),
+    Table(Table),
+    Tables(Vec<Table>),
+}
+
+impl Field {
+    /// Size of the field inside its table. Strings, vectors and tables are stored as
+    /// offsets.
+    fn inline_size(&self) -> usize {
+        match self {
+            Field::UByte(_) => 1,
+            Field::UShort(_) => 2,
+            Field::ULong(_) => 8,
+            _ => 4,
+        }
+    }
+}
+
+/// A FlatBuffer table, as its fields and their slots in the schema.
+#[derive(Default)]
+struct Table {
+    fields: Vec<(usize, Field)>,
+}
+
+impl Table {
+    fn with(mut self, slot: usize, field: Field) -> Self {
+        self.fields.push((slot, field));
+        self
+    }
+}
+
+/// Pads the buffer with zeros until `extra` more bytes would end on a multiple of `align`.
+fn pad(buf: &mut Vec<u8>, align: usize, extra: usize) {
+    let padding = (align - (buf.len() + extra) % align) % align;
+    buf.resize(buf.len() + padding, 0);
+}
+
+fn put_u32(buf: &mut [u8], pos: usize, value: u32) {
+    buf[pos..pos + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Encodes a table as a size prefixed FlatBuffer. Alignment is relative to the start of
+/// the size prefix, as in buffers from the flatbuffers builders.
+fn size_prefixed(table: &Table) -> Vec<u8> {
+    let mut buf = vec![0; 8];
+    let root = write_table(&mut buf, table);
+    put_u32(&mut buf, 4, (root - 4) as u32);
+    let size = buf.len() - 4;
+    put_u32(&mut buf, 0, size as u32);
+    buf
+}
+
+/// Writes a table and everything it refers to, returning the position of the table.
+fn write_table(buf: &mut Vec<u8>, table: &Table) -> usize {
+    let slots = table
+        .fields
+        .iter()
+        .map(|(slot, _)| slot + 1)
+        .max()
+        .unwrap_or(0);
+    let vtable_len = 4 + 2 * slots;
+
+    // Fields are laid out largest first after the vtable offset, so starting the table on
+    // a multiple of 8 aligns all of them
+    let mut order: Vec<&(usize, Field)> = table.fields.iter().collect();
+    order.sort_by_key(|(_, field)| Reverse(field.inline_size()));
+    let mut offsets = vec![0u16; slots];
+    let mut table_len: usize = 4;
+    for (slot, field) in order {
+        let size = field.inline_size();
+        table_len = table_len.next_multiple_of(size);
+        offsets[*slot] = table_len as u16;
+        table_len += size;
+    }
+
+    pad(buf, 8, vtable_len);
+    let vtable = buf.len();
+    buf.extend_from_slice(&(vtable_len as u16).to_le_bytes());
+    buf.extend_from_slice(&(table_len as u16).to_le_bytes());
+    for offset in &offsets {
+        buf.extend_from_slice(&offset.to_le_bytes());
+    }
+    let start = buf.len();
+    // The vtable is found at the table position minus this offset
+    buf.extend_from_slice(&((start - vtable) as i32).to_le_bytes());
+    buf.resize(start + table_len, 0);
+
+    let mut references = Vec::new();
+    for (slot, field) in &table.fields {
+        let pos = start + offsets[*slot] as usize;
+        match field {
+            Field::UByte(value) => buf[pos] = *value,
+            Field::UShort(value) => buf[pos..pos + 2].copy_from_slice(&value.to_le_bytes()),
+            Field::Int(value) => buf[pos..pos + 4].copy_from_slice(&value.to_le_bytes()),
+            Field::ULong(value) => buf[pos..pos + 8].copy_from_slice(&value.to_le_bytes()),
+            reference => references.push((pos, reference)),
+        }
+    }
+    for (pos, field) in references {
+        let target = write_reference(buf, field);
+        put_u32(buf, pos, (target - pos) as u32);
+    }
+    start
+}
+
+/// Writes a string, vector or table, returning its position.
+fn write_reference(buf: &mut Vec<u8>, field: &Field) -> usize {
+    let start_vector = |buf: &mut Vec<u8>, element_size: usize, len: usize| {
+        // The length comes before the elements, which are aligned to their size
+        pad(buf, element_size.max(4), 4);
+        let pos = buf.len();
+        buf.extend_from_slice(&(len as u32).to_le_bytes());
+        pos
+    };
+    match field {
+        Field::String(value) => {
+            let pos = start_vector(buf, 1, value.len());
+            buf.extend_from_slice(value.as_bytes());
+            buf.push(0);
+            pos
+        }
+        Field::Doubles(values) => {
+            let pos = start_vector(buf, 8, values.len());
+            values
+                .iter()
+                .for_each(|v| buf.extend_from_slice(&v.to_le_bytes()));
+            pos
+        }
+        Field::UInts(values) => {
+            let pos = start_vector(buf, 4, values.len());
+            values
+                .iter()
+                .for_each(|v| buf.extend_from_slice(&v.to_le_bytes()));
+            pos
+        }
+        Field::UBytes(values) => {
+            let pos = start_vector(buf, 1, values.len());
+            buf.extend_from_slice(values);
+            pos
+        }
+        Field::Table(table) => write_table(buf, table),
+        Field::Tables(tables) => {
+            let pos = start_vector(buf, 4, tables.len());
+            let first = buf.len();
+            buf.resize(first + 4 * tables.len(), 0);
+            for (i, table) in tables.iter().enumerate() {
+                let target = write_table(buf, table);
+                let slot = first + 4 * i;
+                put_u32(buf, slot, (target - slot) as u32);
+            }
+            pos
+        }
+        Field::UByte(_) | Field::UShort(_) | Field::Int(_) | Field::ULong(_) => {
+            unreachable!("scalars are stored in their table")
+        }
+    }
+}
+
+/// Builds the FlatGeobuf Geometry table of a Polygon or MultiPolygon.
+fn geometry_table(value: &Value) -> Option<Table> {
+    match value {
+        Value::Polygon(rings) => {
+            let mut xy = Vec::new();
+            let mut ends = Vec::new();
+            for ring in rings {
+                for position in ring {
+                    xy.extend_from_slice(&position[..2]);
+                }
+                ends.push((xy.len() / 2) as u32);
+            }
+            let mut table = Table::default();
+            // Ring ends are only needed with holes
+            if ends.len() > 1 {
+                table = table.with(0, Field::UInts(ends));
+            }
+            Some(
+                table
+                    .with(1, Field::Doubles(xy))
+                    .with(6, Field::UByte(GEOMETRY_POLYGON)),
+            )
+        }
+        Value::MultiPolygon(polygons) => {
+            let parts = polygons
+                .iter()
+                .filter_map(|rings| geometry_table(&Value::Polygon(rings.clone())))
+                .collect();
+            Some(
+                Table::default()
+                    .with(6, Field::UByte(GEOMETRY_MULTIPOLYGON))
+                    .with(7, Field::Tables(parts)),
+            )
+        }
+        _ => None,
+    }
+}
+
+/// Encodes the properties of a feature as a column index followed by the value for each
+/// non-null property.
+fn encode_properties(feature: &Feature, columns: &[Column]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let Some(properties) = &feature.properties else {
+        return bytes;
+    };
+    for (index, column) in columns.iter().enumerate() {
+        let value = match properties.get(&column.name) {
+            None | Some(serde_json::Value::Null) => continue,
+            Some(value) => value,
+        };
+        bytes.extend_from_slice(&(index as u16).to_le_bytes());
+        match column.column_type {
+            ColumnType::Integer => {
+                bytes.extend_from_slice(&value.as_i64().unwrap_or_default().to_le_bytes())
+            }
+            ColumnType::Real => {
+                bytes.extend_from_slice(&value.as_f64().unwrap_or_default().to_le_bytes())
+            }
+            ColumnType::Boolean => bytes.push(u8::from(value.as_bool().unwrap_or_default())),
+            ColumnType::Text => {
+                let text = text_value(value);
+                bytes.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(text.as_bytes());
+            }
+        }
+    }
+    bytes
+}
+
+/// An R-tree node: the bounding box of its children, and the position of its first child
+/// in the index, or for leaves the byte offset of the feature in the feature data.
+#[derive(Clone, Copy)]
+struct NodeItem {
+    bbox: [f64; 4],
+    offset: u64,
+}
+
+impl NodeItem {
+    fn expand(&mut self, bbox: [f64; 4]) {
+        self.bbox = [
+            self.bbox[0].min(bbox[0]),
+            self.bbox[1].min(bbox[1]),
+            self.bbox[2].max(bbox[2]),
+            self.bbox[3].max(bbox[3]),
+        ];
+    }
+}
+
+/// Returns the position of each level of a packed R-tree in its node array, leaves first.
+/// The root is stored first and the leaves last.
+fn level_bounds(num_items: usize, node_size: usize) -> Vec<(usize, usize)> {
+    let mut level_num_nodes = vec![num_items];
+    let mut n = num_items;
+    loop {
+        n = n.div_ceil(node_size);
+        level_num_nodes.push(n);
+        if n == 1 {
+            break;
+        }
+    }
+    let mut end: usize = level_num_nodes.iter().sum();
+    level_num_nodes
+        .iter()
+        .map(|&count| {
+            end -= count;
+            (end, end + count)
+        })
+        .collect()
+}
+
+/// Builds a packed R-tree over leaves in Hilbert order, returning all nodes root first.
+fn packed_rtree(leaves: &[NodeItem], node_size: usize) -> Vec<NodeItem> {
+    let levels = level_bounds(leaves.len(), node_size);
+    let empty = NodeItem {
+        bbox: [
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ],
+        offset: 0,
+    };
+    // The leaves are stored last, so they end at the node count
+    let mut nodes = vec![empty; levels[0].1];
+    nodes[levels[0].0..levels[0].1].copy_from_slice(leaves);
+    for level in 0..levels.len() - 1 {
+        let (mut pos, end) = levels[level];
+        let mut parent = levels[level + 1].0;
+        while pos < end {
+            let mut node = NodeItem {
+                offset: pos as u64,
+                ..empty
+            };
+            for child in &nodes[pos..end.min(pos + node_size)] {
+                node.expand(child.bbox);
+            }
+            pos = end.min(pos + node_size);
+            nodes[parent] = node;
+            parent += 1;
+        }
+    }
+    nodes
+}
+
+/// Writes features as a FlatGeobuf layer in EPSG:4326 with a spatial index.
+///
+/// The features are written in Hilbert order, which the packed R-tree requires. Property
+/// types are inferred as for the other table formats, see the `columns` module. Features
+/// without a Polygon or MultiPolygon are skipped.
+pub(crate) fn write_flatgeobuf<W: Write>(
+    writer: &mut W,
+    name: &str,
+    features: &[Feature],
+) -> io::Result<()> {
+    let columns = columns(features);
+    let mut items: Vec<(&Feature, &Value, [f64; 4])> = features
+        .iter()
+        .filter_map(|feature| {
+            let value = &feature.geometry.as_ref()?.value;
+            Some((feature, value, geometry_bbox(value)?))
+        })
+        .collect();
+    let extent = sort_by_hilbert(&mut items, |item| Some(item.2));
+
+    let mut geometry_types = items.iter().map(|item| match item.1 {
+        Value::MultiPolygon(_) => GEOMETRY_MULTIPOLYGON,
+        _ => GEOMETRY_POLYGON,
+    });
+    let geometry_type = match geometry_types.next() {
+        Some(first) if geometry_types.all(|t| t == first) => first,
+        _ => GEOMETRY_UNKNOWN,
+    };
+    let column_tables = columns
+        .iter()
+        .map(|column| {
+            let column_type = match column.column_type {
+                ColumnType::Integer => COLUMN_LONG,
+                ColumnType::Real => COLUMN_DOUBLE,
+                ColumnType::Boolean => COLUMN_BOOL,
+                ColumnType::Text => COLUMN_STRING,
+            };
+            Table::default()
+                .with(0, Field::String(column.name.clone()))
+                .with(1, Field::UByte(column_type))
+        })
+        .collect();
+    let crs = Table::default()
+        .with(0, Field::String("EPSG".to_string()))
+        .with(1, Field::Int(4326));
+    let index_node_size = if items.is_empty() { 0 } else { INDEX_NODE_SIZE };
+    let mut header = Table::default()
+        .with(0, Field::String(name.to_string()))
+        .with(2, Field::UByte(geometry_type))
+        .with(7, Field::Tables(column_tables))
+        .with(8, Field::ULong(items.len() as u64))
+        .with(9, Field::UShort(index_node_size))
+        .with(10, Field::Table(crs));
+    if let Some(extent) = extent {
+        header = header.with(1, Field::Doubles(extent.to_vec()));
+    }
+
+    let mut leaves = Vec::with_capacity(items.len());
+    let mut encoded = Vec::with_capacity(items.len());
+    let mut offset = 0;
+    for (feature, value, bbox) in &items {
+        let mut table =
+            Table::default().with(1, Field::UBytes(encode_properties(feature, &columns)));
+        if let Some(geometry) = geometry_table(value) {
+            table = table.with(0, Field::Table(geometry));
+        }
+        let bytes = size_prefixed(&table);
+        leaves.push(NodeItem {
+            bbox: *bbox,
+            offset,
+        });
+        offset += bytes.len() as u64;
+        encoded.push(bytes);
+    }
+
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&size_prefixed(&header))?;
+    if !leaves.is_empty() {
+        for node in packed_rtree(&leaves, INDEX_NODE_SIZE as usize) {
+            for value in node.bbox {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            writer.write_all(&node.offset.to_le_bytes())?;
+        }
+    }
+    for bytes in encoded {
+        writer.write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_bounds() {
+        assert_eq!(level_bounds(1, 16), vec![(1, 2), (0, 1)]);
+        assert_eq!(level_bounds(20, 16), vec![(3, 23), (1, 3), (0, 1)]);
+    }
+
+    #[test]
+    fn test_packed_rtree() {
+        let leaves: Vec<NodeItem> = (0..20)
+            .map(|i| NodeItem {
+                bbox: [i as f64, 0.0, i as f64 + 1.0, 1.0],
+                offset: i * 100,
+            })
+            .collect();
+        let nodes = packed_rtree(&leaves, 16);
+        assert_eq!(nodes.len(), 23);
+        assert_eq!(nodes[0].bbox, [0.0, 0.0, 20.0, 1.0]);
+        assert_eq!(nodes[0].offset, 1);
+        assert_eq!(nodes[1].bbox, [0.0, 0.0, 16.0, 1.0]);
+        assert_eq!(nodes[1].offset, 3);
+        assert_eq!(nodes[2].bbox, [16.0, 0.0, 20.0, 1.0]);
+        assert_eq!(nodes[2].offset, 19);
+        assert_eq!(nodes[22].offset, 1900);
+    }
+
+    #[test]
+    fn test_size_prefixed_table() {
+        let table = Table::default()
+            .with(0, Field::String("a".to_string()))
+            .with(1, Field::ULong(7));
+        let buf = size_prefixed(&table);
+        let size = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
+        assert_eq!(size, buf.len() - 4);
+        let root = 4 + u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+        let vtable = root - i32::from_le_bytes(buf[root..root + 4].try_into().unwrap()) as usize;
+        let field_offset = |slot: usize| {
+            u16::from_le_bytes(
+                buf[vtable + 4 + 2 * slot..vtable + 6 + 2 * slot]
+                    .try_into()
+                    .unwrap(),
+            ) as usize
+        };
+        let long = root + field_offset(1);
+        assert_eq!(long % 8, 0);
+        assert_eq!(
+            u64::from_le_bytes(buf[long..long + 8].try_into().unwrap()),
+            7
+        );
+        let string_field = root + field_offset(0);
+        let string = string_field
+            + u32::from_le_bytes(buf[string_field..string_field + 4].try_into().unwrap()) as usize;
+        assert_eq!(&buf[string..string + 6], &[1, 0, 0, 0, b'a', 0]);
+    }
+}
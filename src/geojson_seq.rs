@@ -0,0 +1,78 @@
+//! Streaming the outlines to a GeoJSON text sequence as they are made.
+//!
+//! Each feature is written on its own line and flushed, so that a run that stops early
+//! still leaves the outlines of the files processed so far. Files named `.geojsons` get the
+//! RFC 8142 record separator before each feature; other names are newline delimited
+//! GeoJSON, as read by GDAL's GeoJSONSeq driver, `jq` and most streaming tools.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use geojson::Feature;
+
+const RECORD_SEPARATOR: u8 = 0x1E;
+
+/// Writes features to a GeoJSON text sequence, one per line.
+pub(crate) struct GeoJsonSeqWriter<W: Write> {
+    writer: W,
+    record_separator: bool,
+}
+
+impl GeoJsonSeqWriter<BufWriter<File>> {
+    /// Creates the file, using record separators when its extension is `.geojsons`.
+    pub(crate) fn create(path: &str) -> io::Result<Self> {
+        let record_separator = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("geojsons"));
+        Ok(GeoJsonSeqWriter::new(
+            BufWriter::new(File::create(path)?),
+            record_separator,
+        ))
+    }
+}
+
+impl<W: Write> GeoJsonSeqWriter<W> {
+    pub(crate) fn new(writer: W, record_separator: bool) -> Self {
+        GeoJsonSeqWriter {
+            writer,
+            record_separator,
+        }
+    }
+
+    /// Writes a feature and flushes it.
+    pub(crate) fn write_feature(&mut self, feature: &Feature) -> io::Result<()> {
+        if self.record_separator {
+            self.writer.write_all(&[RECORD_SEPARATOR])?;
+        }
+        serde_json::to_writer(&mut self.writer, feature)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_write_feature() {
+        let feature = Feature {
+            properties: json!({ "SourceFile": "a.las" }).as_object().cloned(),
+            ..Default::default()
+        };
+        let mut seq = GeoJsonSeqWriter::new(Vec::new(), false);
+        seq.write_feature(&feature).unwrap();
+        seq.write_feature(&feature).unwrap();
+        let text = String::from_utf8(seq.writer).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].parse::<Feature>().unwrap(), feature);
+
+        let mut seq = GeoJsonSeqWriter::new(Vec::new(), true);
+        seq.write_feature(&feature).unwrap();
+        assert_eq!(seq.writer[0], RECORD_SEPARATOR);
+        assert_eq!(seq.writer.last(), Some(&b'\n'));
+    }
+}
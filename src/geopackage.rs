@@ -0,0 +1,318 @@
+//! Writing the outlines to a GeoPackage.
+//!
+//! The file is written with SQLite directly rather than through GDAL: the core tables of
+//! the GeoPackage 1.2 specification, one feature table per layer with a column per
+//! property, and an R-tree spatial index on each layer. Geometries are stored in
+//! EPSG:4326, like the GeoJSON output.
+
+use std::fs;
+use std::path::Path;
+
+use geojson::Feature;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection};
+use serde_json::Value;
+
+use crate::columns::{columns, text_value, Column, ColumnType};
+use crate::wkb::{geometry_bbox, geometry_type_name, geometry_wkb};
+use crate::LasPolyError;
+
+/// `GPKG` as a big endian integer, the SQLite application id of GeoPackages.
+const APPLICATION_ID: i32 = 0x4750_4B47;
+/// GeoPackage version 1.2.0.
+const USER_VERSION: i32 = 10200;
+const SRS_ID: i32 = 4326;
+const GEOMETRY_COLUMN: &str = "geom";
+
+const EPSG_4326_WKT: &str = "GEOGCS[\"WGS 84\",DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563,AUTHORITY[\"EPSG\",\"7030\"]],AUTHORITY[\"EPSG\",\"6326\"]],PRIMEM[\"Greenwich\",0,AUTHORITY[\"EPSG\",\"8901\"]],UNIT[\"degree\",0.0174532925199433,AUTHORITY[\"EPSG\",\"9122\"]],AUTHORITY[\"EPSG\",\"4326\"]]";
+
+/// The core tables and required spatial reference systems of a GeoPackage.
+const CORE_TABLES: &str = "
+CREATE TABLE gpkg_spatial_ref_sys (
+    srs_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL PRIMARY KEY,
+    organization TEXT NOT NULL,
+    organization_coordsys_id INTEGER NOT NULL,
+    definition TEXT NOT NULL,
+    description TEXT
+);
+CREATE TABLE gpkg_contents (
+    table_name TEXT NOT NULL PRIMARY KEY,
+    data_type TEXT NOT NULL,
+    identifier TEXT UNIQUE,
+    description TEXT DEFAULT '',
+    last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    min_x DOUBLE,
+    min_y DOUBLE,
+    max_x DOUBLE,
+    max_y DOUBLE,
+    srs_id INTEGER,
+    CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+CREATE TABLE gpkg_geometry_columns (
+    table_name TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    geometry_type_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL,
+    z TINYINT NOT NULL,
+    m TINYINT NOT NULL,
+    CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name),
+    CONSTRAINT uk_gc_table_name UNIQUE (table_name),
+    CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
+    CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys (srs_id)
+);
+CREATE TABLE gpkg_extensions (
+    table_name TEXT,
+    column_name TEXT,
+    extension_name TEXT NOT NULL,
+    definition TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    CONSTRAINT ge_tce UNIQUE (table_name, column_name, extension_name)
+);
+INSERT INTO gpkg_spatial_ref_sys VALUES
+    ('Undefined cartesian SRS', -1, 'NONE', -1, 'undefined', 'undefined cartesian coordinate reference system'),
+    ('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', 'undefined geographic coordinate reference system');
+";
+
+/// Writes layers of features to a new GeoPackage, replacing any existing file.
+///
+/// Each layer becomes a feature table with an integer `fid`, a `geom` column and a typed
+/// column per property, see the `columns` module. Layers are registered in
+/// `gpkg_contents` with their bounds and get an R-tree spatial index.
+pub(crate) fn save_geopackage(
+    output_file_name: &str,
+    layers: &[(&str, &[Feature])],
+) -> Result<(), LasPolyError> {
+    if Path::new(output_file_name).exists() {
+        fs::remove_file(output_file_name)?;
+    }
+    let mut connection = Connection::open(output_file_name)?;
+    connection.execute_batch(&format!(
+        "PRAGMA application_id = {}; PRAGMA user_version = {};",
+        APPLICATION_ID, USER_VERSION
+    ))?;
+
+    let transaction = connection.transaction()?;
+    transaction.execute_batch(CORE_TABLES)?;
+    transaction.execute(
+        "INSERT INTO gpkg_spatial_ref_sys VALUES ('WGS 84 geodetic', ?1, 'EPSG', ?1, ?2, \
+         'longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid')",
+        params![SRS_ID, EPSG_4326_WKT],
+    )?;
+    for (table_name, features) in layers {
+        write_layer(&transaction, table_name, features)?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Quotes an SQL identifier.
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Creates, fills and indexes the feature table of one layer.
+fn write_layer(
+    connection: &Connection,
+    table_name: &str,
+    features: &[Feature],
+) -> Result<(), LasPolyError> {
+    let columns = columns(features);
+    let geometries: Vec<_> = features
+        .iter()
+        .map(|feature| feature.geometry.as_ref().map(|geometry| &geometry.value))
+        .collect();
+    let mut type_names = geometries
+        .iter()
+        .flatten()
+        .filter_map(|g| geometry_type_name(g));
+    let geometry_type = match type_names.next() {
+        Some(first) if type_names.all(|name| name == first) => first,
+        _ => "GEOMETRY",
+    };
+
+    let column_definitions: String = columns
+        .iter()
+        .map(|column| {
+            let sql_type = match column.column_type {
+                ColumnType::Integer => "INTEGER",
+                ColumnType::Real => "DOUBLE",
+                ColumnType::Boolean => "BOOLEAN",
+                ColumnType::Text => "TEXT",
+            };
+            format!(", {} {}", quote(&column.name), sql_type)
+        })
+        .collect();
+    connection.execute_batch(&format!(
+        "CREATE TABLE {} (fid INTEGER PRIMARY KEY AUTOINCREMENT, {} {}{});",
+        quote(table_name),
+        GEOMETRY_COLUMN,
+        geometry_type,
+        column_definitions
+    ))?;
+
+    let rtree = format!("rtree_{}_{}", table_name, GEOMETRY_COLUMN);
+    connection.execute_batch(&format!(
+        "CREATE VIRTUAL TABLE {} USING rtree(id, minx, maxx, miny, maxy);",
+        quote(&rtree)
+    ))?;
+
+    let placeholders: String = (0..columns.len() + 2)
+        .map(|i| format!(", ?{}", i + 1))
+        .collect();
+    let column_names: String = columns
+        .iter()
+        .map(|column| format!(", {}", quote(&column.name)))
+        .collect();
+    let mut insert = connection.prepare(&format!(
+        "INSERT INTO {} (fid, {}{}) VALUES ({})",
+        quote(table_name),
+        GEOMETRY_COLUMN,
+        column_names,
+        &placeholders[2..]
+    ))?;
+    let mut insert_rtree = connection.prepare(&format!(
+        "INSERT INTO {} VALUES (?1, ?2, ?3, ?4, ?5)",
+        quote(&rtree)
+    ))?;
+    let mut extent: Option<[f64; 4]> = None;
+    for (index, (feature, geometry)) in features.iter().zip(&geometries).enumerate() {
+        let fid = index as i64 + 1;
+        let bbox = geometry.and_then(geometry_bbox);
+        let blob = match (geometry.and_then(geometry_wkb), bbox) {
+            (Some(wkb), Some(bbox)) => SqlValue::Blob(geometry_blob(&wkb, bbox)),
+            _ => SqlValue::Null,
+        };
+        let mut values = vec![SqlValue::Integer(fid), blob];
+        values.extend(columns.iter().map(|column| {
+            let value = feature
+                .properties
+                .as_ref()
+                .and_then(|properties| properties.get(&column.name));
+            column_value(column, value)
+        }));
+        insert.execute(params_from_iter(values))?;
+
+        if let Some([min_x, min_y, max_x, max_y]) = bbox {
+            insert_rtree.execute(params![fid, min_x, max_x, min_y, max_y])?;
+            extent = Some(match extent {
+                None => [min_x, min_y, max_x, max_y],
+                Some(e) => [
+                    e[0].min(min_x),
+                    e[1].min(min_y),
+                    e[2].max(max_x),
+                    e[3].max(max_y),
+                ],
+            });
+        }
+    }
+
+    connection.execute(
+        "INSERT INTO gpkg_contents (table_name, data_type, identifier, min_x, min_y, max_x, max_y, srs_id) \
+         VALUES (?1, 'features', ?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            table_name,
+            extent.map(|e| e[0]),
+            extent.map(|e| e[1]),
+            extent.map(|e| e[2]),
+            extent.map(|e| e[3]),
+            SRS_ID
+        ],
+    )?;
+    connection.execute(
+        "INSERT INTO gpkg_geometry_columns VALUES (?1, ?2, ?3, ?4, 0, 0)",
+        params![table_name, GEOMETRY_COLUMN, geometry_type, SRS_ID],
+    )?;
+    connection.execute(
+        "INSERT INTO gpkg_extensions VALUES (?1, ?2, 'gpkg_rtree_index', \
+         'http://www.geopackage.org/spec120/#extension_rtree', 'write-only')",
+        params![table_name, GEOMETRY_COLUMN],
+    )?;
+    // The triggers call the ST_ functions of the reading application, so they are only
+    // created once the index is filled
+    connection.execute_batch(&rtree_triggers(table_name, &rtree))?;
+    Ok(())
+}
+
+/// Converts a property to the type of its column. Values that do not fit the column,
+/// which only happens for text columns, are written as text.
+fn column_value(column: &Column, value: Option<&Value>) -> SqlValue {
+    match (column.column_type, value) {
+        (_, None) | (_, Some(Value::Null)) => SqlValue::Null,
+        (ColumnType::Integer, Some(Value::Number(n))) if n.is_i64() => {
+            SqlValue::Integer(n.as_i64().unwrap_or_default())
+        }
+        (ColumnType::Real, Some(Value::Number(n))) => {
+            SqlValue::Real(n.as_f64().unwrap_or_default())
+        }
+        (ColumnType::Boolean, Some(Value::Bool(b))) => SqlValue::Integer(i64::from(*b)),
+        (_, Some(value)) => SqlValue::Text(text_value(value)),
+    }
+}
+
+/// Wraps WKB in a GeoPackage geometry header: magic, version, flags for a little endian
+/// XY envelope, the SRS id and the envelope as min x, max x, min y, max y.
+fn geometry_blob(wkb: &[u8], [min_x, min_y, max_x, max_y]: [f64; 4]) -> Vec<u8> {
+    let mut blob = Vec::with_capacity(40 + wkb.len());
+    blob.extend_from_slice(b"GP");
+    blob.push(0);
+    blob.push(0b0000_0011);
+    blob.extend_from_slice(&SRS_ID.to_le_bytes());
+    for value in [min_x, max_x, min_y, max_y] {
+        blob.extend_from_slice(&value.to_le_bytes());
+    }
+    blob.extend_from_slice(wkb);
+    blob
+}
+
+/// The triggers of the R-tree spatial index extension that keep the index of a layer up to
+/// date when it is edited.
+fn rtree_triggers(table_name: &str, rtree: &str) -> String {
+    let (t, c, r) = (quote(table_name), GEOMETRY_COLUMN, quote(rtree));
+    let trigger = |suffix: &str| quote(&format!("{}_{}", rtree, suffix));
+    let insert_bounds = format!(
+        "INSERT OR REPLACE INTO {r} VALUES (NEW.fid, ST_MinX(NEW.{c}), ST_MaxX(NEW.{c}), \
+         ST_MinY(NEW.{c}), ST_MaxY(NEW.{c}));"
+    );
+    format!(
+        "CREATE TRIGGER {insert} AFTER INSERT ON {t} \
+         WHEN (NEW.{c} NOT NULL AND NOT ST_IsEmpty(NEW.{c})) \
+         BEGIN {insert_bounds} END;
+         CREATE TRIGGER {update1} AFTER UPDATE OF {c} ON {t} \
+         WHEN OLD.fid = NEW.fid AND (NEW.{c} NOTNULL AND NOT ST_IsEmpty(NEW.{c})) \
+         BEGIN {insert_bounds} END;
+         CREATE TRIGGER {update2} AFTER UPDATE OF {c} ON {t} \
+         WHEN OLD.fid = NEW.fid AND (NEW.{c} ISNULL OR ST_IsEmpty(NEW.{c})) \
+         BEGIN DELETE FROM {r} WHERE id = OLD.fid; END;
+         CREATE TRIGGER {update3} AFTER UPDATE ON {t} \
+         WHEN OLD.fid != NEW.fid AND (NEW.{c} NOTNULL AND NOT ST_IsEmpty(NEW.{c})) \
+         BEGIN DELETE FROM {r} WHERE id = OLD.fid; {insert_bounds} END;
+         CREATE TRIGGER {update4} AFTER UPDATE ON {t} \
+         WHEN OLD.fid != NEW.fid AND (NEW.{c} ISNULL OR ST_IsEmpty(NEW.{c})) \
+         BEGIN DELETE FROM {r} WHERE id IN (OLD.fid, NEW.fid); END;
+         CREATE TRIGGER {delete} AFTER DELETE ON {t} \
+         WHEN OLD.{c} NOT NULL \
+         BEGIN DELETE FROM {r} WHERE id = OLD.fid; END;",
+        insert = trigger("insert"),
+        update1 = trigger("update1"),
+        update2 = trigger("update2"),
+        update3 = trigger("update3"),
+        update4 = trigger("update4"),
+        delete = trigger("delete"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geometry_blob() {
+        let blob = geometry_blob(&[1, 2], [0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(&blob[..8], &[b'G', b'P', 0, 3, 0xE6, 0x10, 0, 0]);
+        assert_eq!(&blob[8..16], &0.0f64.to_le_bytes());
+        assert_eq!(&blob[16..24], &2.0f64.to_le_bytes());
+        assert_eq!(&blob[24..32], &1.0f64.to_le_bytes());
+        assert_eq!(&blob[40..], &[1, 2]);
+    }
+}
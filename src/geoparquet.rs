@@ -0,0 +1,522 @@
+//! Writing the outlines to GeoParquet.
+//!
+//! The file is written without the parquet crate, as uncompressed PLAIN encoded data pages
+//! with one page per column chunk and the metadata in the Thrift compact protocol. Each
+//! property is a typed optional column, `geometry` holds the WKB outline and `bbox` is the
+//! GeoParquet 1.1 covering struct of `xmin`, `ymin`, `xmax` and `ymax`. Rows are sorted
+//! along a Hilbert curve and split into row groups whose column statistics let readers
+//! such as DuckDB skip row groups outside a queried bbox.
+
+use std::io::{self, Write};
+
+use geojson::{Feature, Value};
+use serde_json::{json, Value as JsonValue};
+
+use crate::columns::{columns, text_value, ColumnType};
+use crate::hilbert::sort_by_hilbert;
+use crate::wkb::{geometry_bbox, geometry_wkb};
+
+const MAGIC: &[u8; 4] = b"PAR1";
+/// Rows per row group.
+pub(crate) const ROW_GROUP_SIZE: usize = 100_000;
+const GEOPARQUET_VERSION: &str = "1.1.0";
+const BBOX_FIELDS: [&str; 4] = ["xmin", "ymin", "xmax", "ymax"];
+
+// Parquet physical types
+const BOOLEAN: i32 = 0;
+const INT64: i32 = 2;
+const DOUBLE: i32 = 5;
+const BYTE_ARRAY: i32 = 6;
+
+const REQUIRED: i32 = 0;
+const OPTIONAL: i32 = 1;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CONVERTED_UTF8: i32 = 0;
+
+/// Writes Thrift structs in the compact protocol.
+#[derive(Default)]
+struct Thrift {
+    buf: Vec<u8>,
+    last_id: i16,
+    parents: Vec<i16>,
+}
+
+impl Thrift {
+    const I32: u8 = 5;
+    const I64: u8 = 6;
+    const BINARY: u8 = 8;
+    const LIST: u8 = 9;
+    const STRUCT: u8 = 12;
+
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.buf.push(value as u8);
+    }
+
+    fn zigzag(&mut self, value: i64) {
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn field(&mut self, id: i16, field_type: u8) {
+        let delta = id - self.last_id;
+        if (1..=15).contains(&delta) {
+            self.buf.push(((delta as u8) << 4) | field_type);
+        } else {
+            self.buf.push(field_type);
+            self.zigzag(i64::from(id));
+        }
+        self.last_id = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, Self::I32);
+        self.zigzag(i64::from(value));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, Self::I64);
+        self.zigzag(value);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, Self::BINARY);
+        self.list_element_binary(value);
+    }
+
+    fn list(&mut self, id: i16, element_type: u8, len: usize) {
+        self.field(id, Self::LIST);
+        if len < 15 {
+            self.buf.push(((len as u8) << 4) | element_type);
+        } else {
+            self.buf.push(0xF0 | element_type);
+            self.varint(len as u64);
+        }
+    }
+
+    fn list_element_i32(&mut self, value: i32) {
+        self.zigzag(i64::from(value));
+    }
+
+    fn list_element_binary(&mut self, value: &[u8]) {
+        self.varint(value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    /// Starts a struct field, or a struct list element when `id` is `None`.
+    fn begin_struct(&mut self, id: Option<i16>) {
+        if let Some(id) = id {
+            self.field(id, Self::STRUCT);
+        }
+        self.parents.push(self.last_id);
+        self.last_id = 0;
+    }
+
+    fn end_struct(&mut self) {
+        self.buf.push(0);
+        self.last_id = self.parents.pop().unwrap_or(0);
+    }
+}
+
+/// A non-null value of a column.
+enum Cell {
+    Int64(i64),
+    Double(f64),
+    Boolean(bool),
+    Bytes(Vec<u8>),
+}
+
+/// A feature with its geometry and the bbox of the geometry.
+type Row<'a> = (&'a Feature, Option<&'a Value>, Option<[f64; 4]>);
+
+/// A leaf column of the schema.
+struct Leaf {
+    path: Vec<String>,
+    physical_type: i32,
+    repetition: i32,
+    utf8: bool,
+}
+
+/// The leaf columns: one per property, then `geometry` and the `bbox` covering fields.
+fn leaves(columns: &[crate::columns::Column]) -> Vec<Leaf> {
+    let mut leaves: Vec<Leaf> = columns
+        .iter()
+        .map(|column| Leaf {
+            path: vec![column.name.clone()],
+            physical_type: match column.column_type {
+                ColumnType::Integer => INT64,
+                ColumnType::Real => DOUBLE,
+                ColumnType::Boolean => BOOLEAN,
+                ColumnType::Text => BYTE_ARRAY,
+            },
+            repetition: OPTIONAL,
+            utf8: column.column_type == ColumnType::Text,
+        })
+        .collect();
+    leaves.push(Leaf {
+        path: vec!["geometry".to_string()],
+        physical_type: BYTE_ARRAY,
+        repetition: OPTIONAL,
+        utf8: false,
+    });
+    // Required inside the optional bbox group, so null for features without a geometry
+    leaves.extend(BBOX_FIELDS.iter().map(|field| Leaf {
+        path: vec!["bbox".to_string(), field.to_string()],
+        physical_type: DOUBLE,
+        repetition: REQUIRED,
+        utf8: false,
+    }));
+    leaves
+}
+
+/// Converts a property to the physical type of its column. Values that do not fit the
+/// column, which only happens for text columns, are written as text.
+fn property_cell(column_type: ColumnType, value: Option<&JsonValue>) -> Option<Cell> {
+    let value = value.filter(|value| !value.is_null())?;
+    Some(match column_type {
+        ColumnType::Integer => Cell::Int64(value.as_i64().unwrap_or_default()),
+        ColumnType::Real => Cell::Double(value.as_f64().unwrap_or_default()),
+        ColumnType::Boolean => Cell::Boolean(value.as_bool().unwrap_or_default()),
+        ColumnType::Text => Cell::Bytes(text_value(value).into_bytes()),
+    })
+}
+
+/// Encodes definition levels of 0 or 1 with the RLE/bit-packing hybrid, as RLE runs,
+/// prefixed by their length.
+fn definition_levels(defined: &[bool]) -> Vec<u8> {
+    let mut runs = Thrift::default();
+    let mut start = 0;
+    while start < defined.len() {
+        let run = defined[start..]
+            .iter()
+            .take_while(|&&d| d == defined[start])
+            .count();
+        runs.varint((run as u64) << 1);
+        runs.buf.push(u8::from(defined[start]));
+        start += run;
+    }
+    let mut levels = (runs.buf.len() as u32).to_le_bytes().to_vec();
+    levels.extend(runs.buf);
+    levels
+}
+
+/// Encodes the non-null cells of a column with PLAIN encoding.
+fn plain_values(cells: &[Option<Cell>]) -> Vec<u8> {
+    let mut values = Vec::new();
+    let mut bits = Vec::new();
+    for cell in cells.iter().flatten() {
+        match cell {
+            Cell::Int64(value) => values.extend_from_slice(&value.to_le_bytes()),
+            Cell::Double(value) => values.extend_from_slice(&value.to_le_bytes()),
+            Cell::Boolean(value) => bits.push(*value),
+            Cell::Bytes(value) => {
+                values.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                values.extend_from_slice(value);
+            }
+        }
+    }
+    // Booleans are bit-packed, least significant bit first
+    for chunk in bits.chunks(8) {
+        values.push(
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, &bit)| byte | (u8::from(bit) << i)),
+        );
+    }
+    values
+}
+
+/// Returns the PLAIN encoded minimum and maximum of a numeric column, if it has values.
+fn min_max(cells: &[Option<Cell>]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let mut ints = cells.iter().flatten().filter_map(|cell| match cell {
+        Cell::Int64(value) => Some(*value),
+        _ => None,
+    });
+    if let Some(first) = ints.next() {
+        let (min, max) = ints.fold((first, first), |(min, max), v| (min.min(v), max.max(v)));
+        return Some((min.to_le_bytes().to_vec(), max.to_le_bytes().to_vec()));
+    }
+    let doubles: Vec<f64> = cells
+        .iter()
+        .flatten()
+        .filter_map(|cell| match cell {
+            Cell::Double(value) if !value.is_nan() => Some(*value),
+            _ => None,
+        })
+        .collect();
+    let min = doubles.iter().copied().reduce(f64::min)?;
+    let max = doubles.iter().copied().reduce(f64::max)?;
+    Some((min.to_le_bytes().to_vec(), max.to_le_bytes().to_vec()))
+}
+
+/// Encodes a column chunk as a single data page, returning the page and the column
+/// metadata for the footer, given the file offset the chunk is written at.
+fn column_chunk(leaf: &Leaf, cells: &[Option<Cell>], offset: usize) -> (Vec<u8>, Thrift) {
+    let defined: Vec<bool> = cells.iter().map(Option::is_some).collect();
+    let mut body = definition_levels(&defined);
+    body.extend(plain_values(cells));
+
+    let mut page = Thrift::default();
+    page.i32(1, 0); // DATA_PAGE
+    page.i32(2, body.len() as i32);
+    page.i32(3, body.len() as i32);
+    page.begin_struct(Some(5));
+    page.i32(1, cells.len() as i32);
+    page.i32(2, ENCODING_PLAIN);
+    page.i32(3, ENCODING_RLE);
+    page.i32(4, ENCODING_RLE);
+    page.end_struct();
+    page.end_struct();
+    let mut chunk = page.buf;
+    chunk.extend(body);
+
+    let mut meta = Thrift::default();
+    meta.begin_struct(None);
+    meta.i64(2, offset as i64);
+    meta.begin_struct(Some(3));
+    meta.i32(1, leaf.physical_type);
+    meta.list(2, Thrift::I32, 2);
+    meta.list_element_i32(ENCODING_PLAIN);
+    meta.list_element_i32(ENCODING_RLE);
+    meta.list(3, Thrift::BINARY, leaf.path.len());
+    for name in &leaf.path {
+        meta.list_element_binary(name.as_bytes());
+    }
+    meta.i32(4, 0); // UNCOMPRESSED
+    meta.i64(5, cells.len() as i64);
+    meta.i64(6, chunk.len() as i64);
+    meta.i64(7, chunk.len() as i64);
+    meta.i64(9, offset as i64);
+    meta.begin_struct(Some(12));
+    meta.i64(3, defined.iter().filter(|d| !**d).count() as i64);
+    if let Some((min, max)) = min_max(cells) {
+        meta.binary(5, &max);
+        meta.binary(6, &min);
+    }
+    meta.end_struct();
+    meta.end_struct();
+    meta.end_struct();
+    (chunk, meta)
+}
+
+/// Builds the GeoParquet metadata of the `geometry` column.
+fn geo_metadata(types: &[&str], extent: Option<[f64; 4]>) -> String {
+    let covering: serde_json::Map<String, JsonValue> = BBOX_FIELDS
+        .iter()
+        .map(|field| (field.to_string(), json!(["bbox", field])))
+        .collect();
+    let mut column = json!({
+        "encoding": "WKB",
+        "geometry_types": types,
+        "covering": {"bbox": covering},
+    });
+    if let Some(extent) = extent {
+        column["bbox"] = json!(extent);
+    }
+    json!({
+        "version": GEOPARQUET_VERSION,
+        "primary_column": "geometry",
+        "columns": {"geometry": column},
+    })
+    .to_string()
+}
+
+/// Writes features as GeoParquet, with geometries in OGC:CRS84 (longitude, latitude),
+/// which is the GeoParquet default and matches the GeoJSON output.
+///
+/// Rows are sorted in Hilbert order and written in row groups of `row_group_size` rows.
+/// Property types are inferred as for the other table formats, see the `columns` module.
+pub(crate) fn write_geoparquet<W: Write>(
+    writer: &mut W,
+    features: &[Feature],
+    row_group_size: usize,
+) -> io::Result<()> {
+    let columns = columns(features);
+    let leaves = leaves(&columns);
+    let mut rows: Vec<Row> = features
+        .iter()
+        .map(|feature| {
+            let value = feature.geometry.as_ref().map(|geometry| &geometry.value);
+            (feature, value, value.and_then(geometry_bbox))
+        })
+        .collect();
+    let extent = sort_by_hilbert(&mut rows, |row| row.2);
+    let mut types: Vec<&str> = Vec::new();
+    for (_, value, _) in &rows {
+        let name = match value {
+            Some(Value::Polygon(_)) => "Polygon",
+            Some(Value::MultiPolygon(_)) => "MultiPolygon",
+            _ => continue,
+        };
+        if !types.contains(&name) {
+            types.push(name);
+        }
+    }
+
+    writer.write_all(MAGIC)?;
+    let mut offset = MAGIC.len();
+    let mut row_groups = Vec::new();
+    for group in rows.chunks(row_group_size.max(1)) {
+        let mut cells: Vec<Vec<Option<Cell>>> = columns
+            .iter()
+            .map(|column| {
+                group
+                    .iter()
+                    .map(|(feature, _, _)| {
+                        let properties = feature.properties.as_ref();
+                        property_cell(
+                            column.column_type,
+                            properties.and_then(|p| p.get(&column.name)),
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        cells.push(
+            group
+                .iter()
+                .map(|(_, value, _)| value.and_then(geometry_wkb).map(Cell::Bytes))
+                .collect(),
+        );
+        for i in 0..BBOX_FIELDS.len() {
+            cells.push(
+                group
+                    .iter()
+                    .map(|(_, _, bbox)| bbox.map(|bbox| Cell::Double(bbox[i])))
+                    .collect(),
+            );
+        }
+
+        let mut chunks = Vec::new();
+        let mut group_len = 0;
+        for (leaf, column_cells) in leaves.iter().zip(&cells) {
+            let (chunk, meta) = column_chunk(leaf, column_cells, offset);
+            writer.write_all(&chunk)?;
+            offset += chunk.len();
+            group_len += chunk.len();
+            chunks.push(meta.buf);
+        }
+        row_groups.push((chunks, group_len, group.len()));
+    }
+
+    let mut footer = Thrift::default();
+    footer.i32(1, 1);
+    footer.list(2, Thrift::STRUCT, 1 + leaves.len() + 1);
+    footer.begin_struct(None);
+    footer.binary(4, b"schema");
+    footer.i32(5, (columns.len() + 2) as i32);
+    footer.end_struct();
+    for leaf in &leaves {
+        if leaf.path == ["bbox", BBOX_FIELDS[0]] {
+            footer.begin_struct(None);
+            footer.i32(3, OPTIONAL);
+            footer.binary(4, b"bbox");
+            footer.i32(5, BBOX_FIELDS.len() as i32);
+            footer.end_struct();
+        }
+        footer.begin_struct(None);
+        footer.i32(1, leaf.physical_type);
+        footer.i32(3, leaf.repetition);
+        footer.binary(4, leaf.path[leaf.path.len() - 1].as_bytes());
+        if leaf.utf8 {
+            footer.i32(6, CONVERTED_UTF8);
+            footer.begin_struct(Some(10));
+            footer.begin_struct(Some(1));
+            footer.end_struct();
+            footer.end_struct();
+        }
+        footer.end_struct();
+    }
+    footer.i64(3, rows.len() as i64);
+    footer.list(4, Thrift::STRUCT, row_groups.len());
+    for (chunks, group_len, num_rows) in &row_groups {
+        footer.begin_struct(None);
+        footer.list(1, Thrift::STRUCT, chunks.len());
+        for chunk in chunks {
+            // Column chunks were encoded as complete structs
+            footer.buf.extend_from_slice(chunk);
+        }
+        footer.i64(2, *group_len as i64);
+        footer.i64(3, *num_rows as i64);
+        footer.end_struct();
+    }
+    footer.list(5, Thrift::STRUCT, 1);
+    footer.begin_struct(None);
+    footer.binary(1, b"geo");
+    footer.binary(2, geo_metadata(&types, extent).as_bytes());
+    footer.end_struct();
+    footer.binary(
+        6,
+        concat!("las-poly ", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+    // Statistics are ordered by the physical type of each column
+    footer.list(7, Thrift::STRUCT, leaves.len());
+    for _ in &leaves {
+        footer.begin_struct(None);
+        footer.begin_struct(Some(1));
+        footer.end_struct();
+        footer.end_struct();
+    }
+    footer.end_struct();
+
+    writer.write_all(&footer.buf)?;
+    writer.write_all(&(footer.buf.len() as u32).to_le_bytes())?;
+    writer.write_all(MAGIC)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thrift_compact() {
+        let mut thrift = Thrift::default();
+        thrift.i32(1, -1);
+        thrift.i32(2, 1);
+        thrift.begin_struct(Some(20));
+        thrift.i64(1, 150);
+        thrift.end_struct();
+        thrift.binary(21, b"ab");
+        thrift.end_struct();
+        assert_eq!(
+            thrift.buf,
+            vec![
+                0x15, 0x01, 0x15, 0x02, 0x0C, 40, 0x16, 0xAC, 0x02, 0x00, 0x18, 2, b'a', b'b', 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn test_definition_levels() {
+        assert_eq!(
+            definition_levels(&[true, true, true, false, true]),
+            vec![6, 0, 0, 0, 6, 1, 2, 0, 2, 1]
+        );
+    }
+
+    #[test]
+    fn test_plain_values() {
+        let cells = vec![
+            Some(Cell::Boolean(true)),
+            None,
+            Some(Cell::Boolean(false)),
+            Some(Cell::Boolean(true)),
+        ];
+        assert_eq!(plain_values(&cells), vec![0b101]);
+        let cells = vec![Some(Cell::Bytes(b"ab".to_vec())), None];
+        assert_eq!(plain_values(&cells), vec![2, 0, 0, 0, b'a', b'b']);
+        let cells = vec![Some(Cell::Double(2.0)), None, Some(Cell::Double(-1.0))];
+        assert_eq!(
+            min_max(&cells),
+            Some((
+                (-1.0f64).to_le_bytes().to_vec(),
+                2.0f64.to_le_bytes().to_vec()
+            ))
+        );
+    }
+}
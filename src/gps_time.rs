@@ -0,0 +1,171 @@
+//! Converting the GPS times of a file to UTC acquisition timestamps.
+//!
+//! LAS files store either GPS week time, the seconds since the start of the GPS week,
+//! or adjusted standard GPS time, the seconds since the GPS epoch (1980-01-06) minus
+//! 1e9, as set by the global encoding bit. GPS time does not count leap seconds, so they
+//! are subtracted to get UTC.
+
+use chrono::{DateTime, NaiveDate, SecondsFormat, TimeDelta, Utc};
+use las::{GpsTimeType, Header};
+use serde_json::{Map, Value};
+
+const SECONDS_PER_WEEK: f64 = 604_800.0;
+
+/// Offset of adjusted standard GPS time from standard GPS time.
+const ADJUSTED_STANDARD_OFFSET: f64 = 1e9;
+
+/// The UTC days from which each additional leap second applies, since the GPS epoch.
+const LEAP_SECOND_DATES: [(i32, u32); 18] = [
+    (1981, 7),
+    (1982, 7),
+    (1983, 7),
+    (1985, 7),
+    (1988, 1),
+    (1990, 1),
+    (1991, 1),
+    (1992, 7),
+    (1993, 7),
+    (1994, 7),
+    (1996, 1),
+    (1997, 7),
+    (1999, 1),
+    (2006, 1),
+    (2009, 1),
+    (2012, 7),
+    (2015, 7),
+    (2017, 1),
+];
+
+fn start_of_day(date: NaiveDate) -> DateTime<Utc> {
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
+
+fn gps_epoch() -> DateTime<Utc> {
+    start_of_day(NaiveDate::from_ymd_opt(1980, 1, 6).unwrap())
+}
+
+/// Returns the GPS week holding `date`.
+fn gps_week_of(date: NaiveDate) -> i64 {
+    (date - gps_epoch().date_naive()).num_days().div_euclid(7)
+}
+
+/// Converts seconds since the GPS epoch to UTC.
+fn gps_seconds_to_utc(gps_seconds: f64) -> Option<DateTime<Utc>> {
+    if !gps_seconds.is_finite() {
+        return None;
+    }
+    let gps = gps_epoch().checked_add_signed(TimeDelta::try_milliseconds(
+        (gps_seconds * 1000.0).round() as i64,
+    )?)?;
+    let leap_seconds = LEAP_SECOND_DATES
+        .iter()
+        .enumerate()
+        .take_while(|&(i, &(year, month))| {
+            let leap_date = NaiveDate::from_ymd_opt(year, month, 1).unwrap();
+            gps - TimeDelta::seconds(i as i64 + 1) >= start_of_day(leap_date)
+        })
+        .count();
+    Some(gps - TimeDelta::seconds(leap_seconds as i64))
+}
+
+/// Converts the GPS time range of a file to UTC.
+///
+/// GPS week time needs the week: `gps_week` if given, otherwise the week of the header
+/// date. The header date is usually the day the file was created, on or after the
+/// flight, so the previous week is used when the header week would put the start of the
+/// range after the end of that day.
+pub(crate) fn acquisition_range(
+    header: &Header,
+    (gps_time_min, gps_time_max): (f64, f64),
+    gps_week: Option<u32>,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let convert = |offset: f64| {
+        Some((
+            gps_seconds_to_utc(offset + gps_time_min)?,
+            gps_seconds_to_utc(offset + gps_time_max)?,
+        ))
+    };
+    match header.gps_time_type() {
+        GpsTimeType::Standard => convert(ADJUSTED_STANDARD_OFFSET),
+        GpsTimeType::Week => match gps_week {
+            Some(week) => convert(f64::from(week) * SECONDS_PER_WEEK),
+            None => {
+                let date = header.date()?;
+                let week = gps_week_of(date);
+                let range = convert(week as f64 * SECONDS_PER_WEEK)?;
+                if range.0 >= start_of_day(date.succ_opt()?) {
+                    convert((week - 1) as f64 * SECONDS_PER_WEEK)
+                } else {
+                    Some(range)
+                }
+            }
+        },
+    }
+}
+
+/// Returns the `acquisition_start` and `acquisition_end` properties, if the GPS times of
+/// the file can be placed in time.
+pub(crate) fn acquisition_properties(
+    header: &Header,
+    gps_time_range: Option<(f64, f64)>,
+    gps_week: Option<u32>,
+) -> Map<String, Value> {
+    let mut map = Map::new();
+    if let Some((start, end)) =
+        gps_time_range.and_then(|range| acquisition_range(header, range, gps_week))
+    {
+        map.insert(
+            "acquisition_start".to_string(),
+            start.to_rfc3339_opts(SecondsFormat::Secs, true).into(),
+        );
+        map.insert(
+            "acquisition_end".to_string(),
+            end.to_rfc3339_opts(SecondsFormat::Secs, true).into(),
+        );
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_header(gps_time_type: GpsTimeType, date: Option<NaiveDate>) -> Header {
+        let mut builder = las::Builder::from((1, 4));
+        builder.gps_time_type = gps_time_type;
+        builder.date = date;
+        builder.into_header().unwrap()
+    }
+
+    #[test]
+    fn test_adjusted_standard_time() {
+        let header = test_header(GpsTimeType::Standard, None);
+        let map = acquisition_properties(&header, Some((0.0, 3600.0)), None);
+        // 1e9 GPS seconds is 2011-09-14 01:46:40 GPS, 15 leap seconds ahead of UTC
+        assert_eq!(map["acquisition_start"], "2011-09-14T01:46:25Z");
+        assert_eq!(map["acquisition_end"], "2011-09-14T02:46:25Z");
+    }
+
+    #[test]
+    fn test_week_time_from_header_date() {
+        // GPS week 2000 starts on Sunday 2018-05-06
+        let date = NaiveDate::from_ymd_opt(2018, 5, 9);
+        let header = test_header(GpsTimeType::Week, date);
+        let (start, _) = acquisition_range(&header, (3600.0, 3700.0), None).unwrap();
+        assert_eq!(start.to_rfc3339(), "2018-05-06T00:59:42+00:00");
+
+        // A Friday time of week with a Monday header date falls in the previous week
+        let date = NaiveDate::from_ymd_opt(2018, 5, 7);
+        let header = test_header(GpsTimeType::Week, date);
+        let (start, _) = acquisition_range(&header, (5.0 * 86400.0, 5.0 * 86400.0), None).unwrap();
+        assert_eq!(start.to_rfc3339(), "2018-05-03T23:59:42+00:00");
+    }
+
+    #[test]
+    fn test_week_time_with_given_week() {
+        let header = test_header(GpsTimeType::Week, None);
+        assert!(acquisition_range(&header, (3600.0, 3600.0), None).is_none());
+        let (start, _) = acquisition_range(&header, (3600.0, 3600.0), Some(2000)).unwrap();
+        assert_eq!(start.to_rfc3339(), "2018-05-06T00:59:42+00:00");
+    }
+}
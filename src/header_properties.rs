@@ -0,0 +1,151 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use las::{Header, Vlr};
+use serde_json::{json, Map, Value};
+
+/// Size of an extra bytes descriptor in the LASF_Spec record 4 VLR.
+const EXTRA_BYTES_DESCRIPTOR_LEN: usize = 192;
+
+/// Builds the full header property set of a file.
+///
+/// The global encoding, header size and offset to the point data are read from the raw
+/// header, so they are reported as written rather than as the las crate would write them.
+pub(crate) fn full_header_properties(
+    file_path: &str,
+    header: &Header,
+) -> Result<Map<String, Value>, las::Error> {
+    let raw_header = las::raw::Header::read_from(BufReader::new(File::open(file_path)?))?;
+    let mut map = Map::new();
+    map.insert("header_size".to_string(), raw_header.header_size.into());
+    map.insert(
+        "offset_to_point_data".to_string(),
+        raw_header.offset_to_point_data.into(),
+    );
+    map.insert(
+        "global_encoding".to_string(),
+        raw_header.global_encoding.into(),
+    );
+    map.insert("guid".to_string(), header.guid().to_string().into());
+
+    let format = header.point_format();
+    map.insert("point_format".to_string(), format.to_u8()?.into());
+    map.insert("point_record_length".to_string(), format.len().into());
+    map.insert("is_compressed".to_string(), format.is_compressed.into());
+
+    let transforms = header.transforms();
+    for (axis, transform) in [
+        ("x", transforms.x),
+        ("y", transforms.y),
+        ("z", transforms.z),
+    ] {
+        map.insert(format!("{}_scale", axis), transform.scale.into());
+        map.insert(format!("{}_offset", axis), transform.offset.into());
+    }
+    let bounds = header.bounds();
+    for (name, value) in [
+        ("min_x", bounds.min.x),
+        ("min_y", bounds.min.y),
+        ("min_z", bounds.min.z),
+        ("max_x", bounds.max.x),
+        ("max_y", bounds.max.y),
+        ("max_z", bounds.max.z),
+    ] {
+        map.insert(name.to_string(), value.into());
+    }
+
+    map.insert("vlr_count".to_string(), header.vlrs().len().into());
+    map.insert("evlr_count".to_string(), header.evlrs().len().into());
+    map.insert(
+        "vlrs".to_string(),
+        header
+            .all_vlrs()
+            .map(|vlr| format!("{}:{}", vlr.user_id, vlr.record_id).into())
+            .collect::<Vec<Value>>()
+            .into(),
+    );
+    if let Some(vlr) = header
+        .all_vlrs()
+        .find(|vlr| vlr.user_id == "LASF_Spec" && vlr.record_id == 4)
+    {
+        map.insert("extra_bytes".to_string(), extra_bytes_schema(vlr).into());
+    }
+    Ok(map)
+}
+
+/// Returns the name, data type and options byte of each extra bytes descriptor of a header.
+pub(crate) fn extra_bytes_descriptors(header: &Header) -> Vec<(String, u8, u8)> {
+    header
+        .all_vlrs()
+        .find(|vlr| vlr.user_id == "LASF_Spec" && vlr.record_id == 4)
+        .map(|vlr| {
+            vlr.data
+                .chunks_exact(EXTRA_BYTES_DESCRIPTOR_LEN)
+                .map(|descriptor| (las_string(&descriptor[4..36]), descriptor[2], descriptor[3]))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Describes the extra bytes of each point from the descriptors of an extra bytes VLR.
+fn extra_bytes_schema(vlr: &Vlr) -> Vec<Value> {
+    vlr.data
+        .chunks_exact(EXTRA_BYTES_DESCRIPTOR_LEN)
+        .map(|descriptor| {
+            let data_type = match descriptor[2] {
+                0 => format!("{} bytes", descriptor[3]),
+                1 => "uint8".to_string(),
+                2 => "int8".to_string(),
+                3 => "uint16".to_string(),
+                4 => "int16".to_string(),
+                5 => "uint32".to_string(),
+                6 => "int32".to_string(),
+                7 => "uint64".to_string(),
+                8 => "int64".to_string(),
+                9 => "float".to_string(),
+                10 => "double".to_string(),
+                other => format!("deprecated type {}", other),
+            };
+            json!({
+                "name": las_string(&descriptor[4..36]),
+                "data_type": data_type,
+                "description": las_string(&descriptor[160..192]),
+            })
+        })
+        .collect()
+}
+
+/// Reads a nul padded string.
+fn las_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extra_bytes_schema() {
+        let mut descriptor = vec![0; EXTRA_BYTES_DESCRIPTOR_LEN];
+        descriptor[2] = 3;
+        descriptor[4..13].copy_from_slice(b"amplitude");
+        descriptor[160..170].copy_from_slice(b"echo power");
+        let mut undocumented = vec![0; EXTRA_BYTES_DESCRIPTOR_LEN];
+        undocumented[3] = 4;
+        descriptor.extend(undocumented);
+        let vlr = Vlr {
+            user_id: "LASF_Spec".to_string(),
+            record_id: 4,
+            data: descriptor,
+            ..Default::default()
+        };
+        assert_eq!(
+            extra_bytes_schema(&vlr),
+            vec![
+                json!({"name": "amplitude", "data_type": "uint16", "description": "echo power"}),
+                json!({"name": "", "data_type": "4 bytes", "description": ""}),
+            ]
+        );
+    }
+}
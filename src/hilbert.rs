@@ -0,0 +1,131 @@
+//! Ordering features along a Hilbert curve.
+//!
+//! Sorting by the Hilbert value of the bbox centres keeps features that are close in space
+//! close in the output, so spatial indexes and per-block bounds stay tight.
+
+/// Largest grid coordinate of the curve.
+const HILBERT_MAX: f64 = 65535.0;
+
+/// Maps a point on a 65536 x 65536 grid to its distance along the Hilbert curve.
+fn hilbert(x: u32, y: u32) -> u32 {
+    let mut a = x ^ y;
+    let mut b = 0xFFFF ^ a;
+    let mut c = 0xFFFF ^ (x | y);
+    let mut d = x & (y ^ 0xFFFF);
+
+    let mut aa = a | (b >> 1);
+    let mut bb = (a >> 1) ^ a;
+    let mut cc = ((c >> 1) ^ (b & (d >> 1))) ^ c;
+    let mut dd = ((a & (c >> 1)) ^ (d >> 1)) ^ d;
+
+    a = aa;
+    b = bb;
+    c = cc;
+    d = dd;
+    aa = (a & (a >> 2)) ^ (b & (b >> 2));
+    bb = (a & (b >> 2)) ^ (b & ((a ^ b) >> 2));
+    cc ^= (a & (c >> 2)) ^ (b & (d >> 2));
+    dd ^= (b & (c >> 2)) ^ ((a ^ b) & (d >> 2));
+
+    a = aa;
+    b = bb;
+    c = cc;
+    d = dd;
+    aa = (a & (a >> 4)) ^ (b & (b >> 4));
+    bb = (a & (b >> 4)) ^ (b & ((a ^ b) >> 4));
+    cc ^= (a & (c >> 4)) ^ (b & (d >> 4));
+    dd ^= (b & (c >> 4)) ^ ((a ^ b) & (d >> 4));
+
+    a = aa;
+    b = bb;
+    c = cc;
+    d = dd;
+    cc ^= (a & (c >> 8)) ^ (b & (d >> 8));
+    dd ^= (b & (c >> 8)) ^ ((a ^ b) & (d >> 8));
+
+    a = cc ^ (cc >> 1);
+    b = dd ^ (dd >> 1);
+
+    let mut i0 = x ^ y;
+    let mut i1 = b | (0xFFFF ^ (i0 | a));
+
+    i0 = (i0 | (i0 << 8)) & 0x00FF00FF;
+    i0 = (i0 | (i0 << 4)) & 0x0F0F0F0F;
+    i0 = (i0 | (i0 << 2)) & 0x33333333;
+    i0 = (i0 | (i0 << 1)) & 0x55555555;
+
+    i1 = (i1 | (i1 << 8)) & 0x00FF00FF;
+    i1 = (i1 | (i1 << 4)) & 0x0F0F0F0F;
+    i1 = (i1 | (i1 << 2)) & 0x33333333;
+    i1 = (i1 | (i1 << 1)) & 0x55555555;
+
+    (i1 << 1) | i0
+}
+
+/// Returns the Hilbert value of the centre of a bbox within the extent.
+fn hilbert_value(bbox: [f64; 4], extent: [f64; 4]) -> u32 {
+    let scale = |value: f64, min: f64, size: f64| {
+        if size > 0.0 {
+            (HILBERT_MAX * (value - min) / size).floor() as u32
+        } else {
+            0
+        }
+    };
+    hilbert(
+        scale((bbox[0] + bbox[2]) / 2.0, extent[0], extent[2] - extent[0]),
+        scale((bbox[1] + bbox[3]) / 2.0, extent[1], extent[3] - extent[1]),
+    )
+}
+
+/// Sorts items by the Hilbert value of the centre of their bbox within the extent of all
+/// bboxes, returning that extent. Items without a bbox go last, in their original order.
+pub(crate) fn sort_by_hilbert<T>(
+    items: &mut [T],
+    bbox: impl Fn(&T) -> Option<[f64; 4]>,
+) -> Option<[f64; 4]> {
+    let extent = items.iter().filter_map(&bbox).reduce(|e, b| {
+        [
+            e[0].min(b[0]),
+            e[1].min(b[1]),
+            e[2].max(b[2]),
+            e[3].max(b[3]),
+        ]
+    })?;
+    items.sort_by_key(|item| bbox(item).map_or(u64::MAX, |b| u64::from(hilbert_value(b, extent))));
+    Some(extent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hilbert() {
+        // The curve starts at the origin and ends in the bottom right corner
+        assert_eq!(hilbert(0, 0), 0);
+        assert_eq!(hilbert(0xFFFF, 0), u32::MAX);
+        assert_eq!([hilbert(1, 0), hilbert(1, 1), hilbert(0, 1)], [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_sort_by_hilbert() {
+        let mut items = vec![
+            Some([9.0, 0.0, 10.0, 1.0]),
+            None,
+            Some([0.0, 9.0, 1.0, 10.0]),
+            Some([0.0, 0.0, 1.0, 1.0]),
+        ];
+        let extent = sort_by_hilbert(&mut items, |item| *item);
+        assert_eq!(extent, Some([0.0, 0.0, 10.0, 10.0]));
+        // Along the curve from the origin, the top left corner comes before the bottom right
+        assert_eq!(
+            items,
+            vec![
+                Some([0.0, 0.0, 1.0, 1.0]),
+                Some([0.0, 9.0, 1.0, 10.0]),
+                Some([9.0, 0.0, 10.0, 1.0]),
+                None,
+            ]
+        );
+    }
+}
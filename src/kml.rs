@@ -0,0 +1,421 @@
+//! Writing the outlines to KML and KMZ for Google Earth.
+//!
+//! Placemarks are put in nested folders following the `SourceFileDir` of each outline,
+//! starting at the deepest directory shared by all outlines, and their properties are shown
+//! as a table in the balloon. Outlines can be coloured by a property: its values are
+//! ranked and split into up to five classes, each with a shared style from a yellow to red
+//! ramp, so that ISO dates, counts and densities all sort as expected.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::{Component, Path};
+
+use geojson::{Feature, Value};
+use serde_json::Value as JsonValue;
+
+use crate::columns::text_value;
+
+/// Fill and line colours of the classes, as `bbggrr`.
+const RAMP: [&str; 5] = ["b2ffff", "5cccfe", "3c8dfd", "203bf0", "2600bd"];
+const DEFAULT_STYLE: &str = "outline";
+const MISSING_STYLE: &str = "missing";
+
+/// Escapes text for XML content and attributes.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Classes of the values of the style property.
+struct Classes {
+    property: String,
+    /// First and last value of each class.
+    ranges: Vec<(String, String)>,
+    /// Class of each value, keyed by its text.
+    class_of: BTreeMap<String, usize>,
+}
+
+impl Classes {
+    const MAX_CLASSES: usize = 5;
+
+    /// Ranks the distinct values of `property`, numerically when all are numbers and as
+    /// text otherwise, and splits them into classes of about the same number of values.
+    fn new(features: &[Feature], property: &str) -> Self {
+        let values: Vec<&JsonValue> = features
+            .iter()
+            .filter_map(|feature| feature.properties.as_ref()?.get(property))
+            .filter(|value| !value.is_null())
+            .collect();
+        let mut distinct: Vec<&JsonValue> = Vec::new();
+        if values.iter().all(|value| value.is_number()) {
+            let mut numbers: Vec<(f64, &JsonValue)> = values
+                .iter()
+                .map(|value| (value.as_f64().unwrap_or_default(), *value))
+                .collect();
+            numbers.sort_by(|a, b| a.0.total_cmp(&b.0));
+            numbers.dedup_by(|a, b| a.0 == b.0);
+            distinct.extend(numbers.into_iter().map(|(_, value)| value));
+        } else {
+            distinct = values;
+            distinct.sort_by_key(|value| text_value(value));
+            distinct.dedup_by_key(|value| text_value(value));
+        }
+
+        let count = distinct.len().min(Self::MAX_CLASSES);
+        let mut ranges: Vec<(String, String)> = Vec::with_capacity(count);
+        let mut class_of = BTreeMap::new();
+        for (rank, value) in distinct.iter().enumerate() {
+            let class = rank * count / distinct.len();
+            let text = text_value(value);
+            match ranges.get_mut(class) {
+                Some(range) => range.1 = text.clone(),
+                None => ranges.push((text.clone(), text.clone())),
+            }
+            class_of.insert(text, class);
+        }
+        Classes {
+            property: property.to_string(),
+            ranges,
+            class_of,
+        }
+    }
+
+    /// Returns the style of a feature, by the class of its value.
+    fn style(&self, feature: &Feature) -> String {
+        feature
+            .properties
+            .as_ref()
+            .and_then(|properties| properties.get(&self.property))
+            .and_then(|value| self.class_of.get(&text_value(value)))
+            .map_or_else(
+                || MISSING_STYLE.to_string(),
+                |class| format!("class{}", class),
+            )
+    }
+
+    /// Returns the ramp colour of a class, spreading fewer classes over the whole ramp.
+    fn colour(&self, class: usize) -> &'static str {
+        let last = self.ranges.len().saturating_sub(1).max(1);
+        RAMP[class * (RAMP.len() - 1) / last]
+    }
+
+    /// Describes the classes, for the document balloon.
+    fn legend(&self) -> String {
+        let rows: String = self
+            .ranges
+            .iter()
+            .enumerate()
+            .map(|(class, (first, last))| {
+                let range = if first == last {
+                    first.clone()
+                } else {
+                    format!("{} to {}", first, last)
+                };
+                let colour = self.colour(class);
+                format!(
+                    "<tr><td style=\"background:#{}{}{}\">&#160;&#160;&#160;</td><td>{}</td></tr>",
+                    &colour[4..6],
+                    &colour[2..4],
+                    &colour[0..2],
+                    escape(&range)
+                )
+            })
+            .collect();
+        format!(
+            "<p>Coloured by {}</p><table>{}</table>",
+            escape(&self.property),
+            rows
+        )
+    }
+}
+
+fn write_style<W: Write>(writer: &mut W, id: &str, line: &str, fill: &str) -> io::Result<()> {
+    writeln!(
+        writer,
+        "<Style id=\"{}\"><LineStyle><color>{}</color><width>2</width></LineStyle>\
+         <PolyStyle><color>{}</color></PolyStyle></Style>",
+        id, line, fill
+    )
+}
+
+/// Writes the properties as a table for the balloon, escaped as XML text.
+fn description(feature: &Feature) -> String {
+    let rows: String = feature
+        .properties
+        .iter()
+        .flatten()
+        .map(|(key, value)| {
+            format!(
+                "<tr><th align=\"left\">{}</th><td>{}</td></tr>",
+                escape(key),
+                escape(&text_value(value))
+            )
+        })
+        .collect();
+    escape(&format!("<table>{}</table>", rows))
+}
+
+fn write_ring<W: Write>(writer: &mut W, boundary: &str, ring: &[Vec<f64>]) -> io::Result<()> {
+    write!(writer, "<{}><LinearRing><coordinates>", boundary)?;
+    for (i, position) in ring.iter().enumerate() {
+        let separator = if i == 0 { "" } else { " " };
+        write!(writer, "{}{},{}", separator, position[0], position[1])?;
+    }
+    writeln!(writer, "</coordinates></LinearRing></{}>", boundary)
+}
+
+fn write_polygon<W: Write>(writer: &mut W, rings: &[Vec<Vec<f64>>]) -> io::Result<()> {
+    writeln!(writer, "<Polygon><tessellate>1</tessellate>")?;
+    for (i, ring) in rings.iter().enumerate() {
+        let boundary = if i == 0 {
+            "outerBoundaryIs"
+        } else {
+            "innerBoundaryIs"
+        };
+        write_ring(writer, boundary, ring)?;
+    }
+    writeln!(writer, "</Polygon>")
+}
+
+fn write_placemark<W: Write>(
+    writer: &mut W,
+    feature: &Feature,
+    classes: Option<&Classes>,
+) -> io::Result<()> {
+    let property = |key: &str| {
+        feature
+            .properties
+            .as_ref()
+            .and_then(|properties| properties.get(key))
+            .and_then(JsonValue::as_str)
+    };
+    let name = property("SourceFile")
+        .or_else(|| {
+            property("SourceFileDir")
+                .and_then(|dir| Path::new(dir).file_name())
+                .and_then(|name| name.to_str())
+        })
+        .unwrap_or("outline");
+    let style = classes.map_or_else(|| DEFAULT_STYLE.to_string(), |c| c.style(feature));
+    writeln!(writer, "<Placemark><name>{}</name>", escape(name))?;
+    writeln!(
+        writer,
+        "<description>{}</description>",
+        description(feature)
+    )?;
+    writeln!(writer, "<styleUrl>#{}</styleUrl>", style)?;
+    match feature.geometry.as_ref().map(|geometry| &geometry.value) {
+        Some(Value::Polygon(rings)) => write_polygon(writer, rings)?,
+        Some(Value::MultiPolygon(polygons)) => {
+            writeln!(writer, "<MultiGeometry>")?;
+            for rings in polygons {
+                write_polygon(writer, rings)?;
+            }
+            writeln!(writer, "</MultiGeometry>")?;
+        }
+        _ => {}
+    }
+    writeln!(writer, "</Placemark>")
+}
+
+/// A folder of placemarks, for one directory.
+#[derive(Default)]
+struct Folder<'a> {
+    folders: BTreeMap<String, Folder<'a>>,
+    features: Vec<&'a Feature>,
+}
+
+impl<'a> Folder<'a> {
+    fn write<W: Write>(
+        &self,
+        writer: &mut W,
+        name: &str,
+        classes: Option<&Classes>,
+    ) -> io::Result<()> {
+        writeln!(writer, "<Folder><name>{}</name>", escape(name))?;
+        for (name, folder) in &self.folders {
+            folder.write(writer, name, classes)?;
+        }
+        for feature in &self.features {
+            write_placemark(writer, feature, classes)?;
+        }
+        writeln!(writer, "</Folder>")
+    }
+}
+
+/// Returns the named components of a directory path.
+pub(crate) fn directory_components(dir: &str) -> Vec<String> {
+    Path::new(dir)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Groups the features into folders mirroring their `SourceFileDir`, below the deepest
+/// directory they share. Returns the name of that directory with its folder, and the
+/// features without a directory.
+fn folders(features: &[Feature]) -> (String, Folder<'_>, Vec<&Feature>) {
+    let mut with_dir = Vec::new();
+    let mut without_dir = Vec::new();
+    for feature in features {
+        match feature
+            .properties
+            .as_ref()
+            .and_then(|properties| properties.get("SourceFileDir"))
+            .and_then(JsonValue::as_str)
+        {
+            Some(dir) => with_dir.push((directory_components(dir), feature)),
+            None => without_dir.push(feature),
+        }
+    }
+
+    let mut common: Vec<String> = with_dir
+        .first()
+        .map(|(components, _)| components.clone())
+        .unwrap_or_default();
+    for (components, _) in &with_dir {
+        let shared = common
+            .iter()
+            .zip(components)
+            .take_while(|(a, b)| a == b)
+            .count();
+        common.truncate(shared);
+    }
+
+    let mut root = Folder::default();
+    for (components, feature) in with_dir {
+        let folder = components[common.len()..]
+            .iter()
+            .fold(&mut root, |folder, name| {
+                folder.folders.entry(name.clone()).or_default()
+            });
+        folder.features.push(feature);
+    }
+    let name = common.last().cloned().unwrap_or_else(|| "/".to_string());
+    (name, root, without_dir)
+}
+
+/// Writes features as a KML document named `name`, coloured by the classes of
+/// `style_property` when given.
+pub(crate) fn write_kml<W: Write>(
+    writer: &mut W,
+    name: &str,
+    features: &[Feature],
+    style_property: Option<&str>,
+) -> io::Result<()> {
+    let classes = style_property.map(|property| Classes::new(features, property));
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(writer, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#)?;
+    writeln!(writer, "<Document><name>{}</name>", escape(name))?;
+    match &classes {
+        Some(classes) => {
+            writeln!(
+                writer,
+                "<description>{}</description>",
+                escape(&classes.legend())
+            )?;
+            for class in 0..classes.ranges.len() {
+                let colour = classes.colour(class);
+                write_style(
+                    writer,
+                    &format!("class{}", class),
+                    &format!("ff{}", colour),
+                    &format!("99{}", colour),
+                )?;
+            }
+            write_style(writer, MISSING_STYLE, "ff999999", "66999999")?;
+        }
+        None => write_style(writer, DEFAULT_STYLE, "ff00aaff", "4000aaff")?,
+    }
+
+    let (root_name, root, without_dir) = folders(features);
+    if !root.features.is_empty() || !root.folders.is_empty() {
+        root.write(writer, &root_name, classes.as_ref())?;
+    }
+    for feature in without_dir {
+        write_placemark(writer, feature, classes.as_ref())?;
+    }
+    writeln!(writer, "</Document>")?;
+    writeln!(writer, "</kml>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn feature(properties: JsonValue) -> Feature {
+        Feature {
+            properties: properties.as_object().cloned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_classes() {
+        let features: Vec<Feature> = [3, 10, 2, 2, 7, 1, 5, 30]
+            .iter()
+            .map(|n| feature(json!({ "n": n })))
+            .chain([feature(json!({}))])
+            .collect();
+        let classes = Classes::new(&features, "n");
+        // 7 distinct values in 5 classes, ranked numerically
+        assert_eq!(
+            classes.ranges,
+            vec![
+                ("1".to_string(), "2".to_string()),
+                ("3".to_string(), "3".to_string()),
+                ("5".to_string(), "7".to_string()),
+                ("10".to_string(), "10".to_string()),
+                ("30".to_string(), "30".to_string()),
+            ]
+        );
+        assert_eq!(classes.style(&features[1]), "class3");
+        assert_eq!(classes.style(&features[8]), MISSING_STYLE);
+
+        let features = vec![
+            feature(json!({ "date": "2021-03-01" })),
+            feature(json!({ "date": "2020-06-01" })),
+        ];
+        let classes = Classes::new(&features, "date");
+        assert_eq!(classes.style(&features[0]), "class1");
+        assert_eq!(classes.colour(1), RAMP[4]);
+    }
+
+    #[test]
+    fn test_folders() {
+        let features = vec![
+            feature(json!({ "SourceFileDir": "/data/survey/2020/north" })),
+            feature(json!({ "SourceFileDir": "/data/survey/2020/south" })),
+            feature(json!({ "SourceFileDir": "/data/survey" })),
+            feature(json!({})),
+        ];
+        let (name, root, without_dir) = folders(&features);
+        assert_eq!(name, "survey");
+        assert_eq!(root.features.len(), 1);
+        let year = &root.folders["2020"];
+        assert_eq!(
+            year.folders.keys().collect::<Vec<_>>(),
+            vec!["north", "south"]
+        );
+        assert_eq!(without_dir.len(), 1);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a<b & \"c\""), "a&lt;b &amp; &quot;c&quot;");
+    }
+}
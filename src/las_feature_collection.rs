@@ -1,16 +1,51 @@
-use geo::{ConvexHull, Coord, Intersects, LineString, Polygon};
+use geo::{ConvexHull, Coord, GeodesicArea, Intersects, LineString, Polygon};
 use geojson::{Feature, FeatureCollection, GeoJson, Geometry, JsonObject, Value};
 use log::{debug, info};
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 use std::{
     collections::HashMap,
     hash::{Hash, Hasher},
 };
 use union_find::{QuickUnionUf, UnionByRank, UnionFind};
 
+use crate::flatgeobuf::write_flatgeobuf;
+use crate::geoparquet::{write_geoparquet, ROW_GROUP_SIZE};
+use crate::kml::write_kml;
+use crate::postgis::{write_sql, SqlOptions, BATCH_ROWS};
+use crate::shapefile::write_shapefile;
+
 const EPSILON: f64 = 1e-7;
 
+/// Properties derived from the outline geometry. They are recomputed for merged features
+/// instead of being merged.
+const OUTLINE_MEASURES: [&str; 4] = ["area_km2", "perimeter_m", "point_density", "cell_density"];
+
+/// Properties merged as the lowest value of the group.
+const MIN_VALUES: [&str; 3] = ["z_min", "intensity_min", "gps_time_min"];
+
+/// Properties merged as the highest value of the group.
+const MAX_VALUES: [&str; 3] = ["z_max", "intensity_max", "gps_time_max"];
+
+/// Properties merged as the total of the group.
+const SUMMED_VALUES: [&str; 5] = [
+    "occupied_area_m2",
+    "file_size",
+    "sampled_points",
+    "outliers_removed",
+    "point_spacing_samples",
+];
+
+/// Point spacing properties, merged as means weighted by `point_spacing_samples`. The
+/// merged percentiles are an approximation.
+const SPACING_VALUES: [&str; 4] = [
+    "point_spacing",
+    "point_spacing_p05",
+    "point_spacing_p50",
+    "point_spacing_p95",
+];
+
 pub struct LasOutlineFeatureCollection {
     features: Vec<Feature>,
 }
@@ -64,6 +99,89 @@ impl LasOutlineFeatureCollection {
         Ok(())
     }
 
+    /// Saves the features as FlatGeobuf, with a packed Hilbert R-tree so that clients can
+    /// read only the features in a bbox. The layer is named after the file.
+    pub fn save_to_flatgeobuf(&self, output_file_name: &str) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(output_file_name)?);
+        write_flatgeobuf(&mut file, &output_name(output_file_name), &self.features)?;
+        file.flush()?;
+        info!("Merged polygons saved to {}", output_file_name);
+        Ok(())
+    }
+
+    /// Saves the features as an ESRI Shapefile, with the `.shx`, `.dbf`, `.prj` and `.cpg`
+    /// files next to the `.shp`. Property names and text values that do not fit in the
+    /// `.dbf` are cut and long reals rounded, and the rules, the field of each property and
+    /// the full cut and rounded values are written to a `.fields.json` sidecar.
+    pub fn save_to_shapefile(&self, output_file_name: &str) -> std::io::Result<()> {
+        write_shapefile(output_file_name, &self.features)?;
+        info!("Merged polygons saved to {}", output_file_name);
+        Ok(())
+    }
+
+    /// Saves the features as GeoParquet, with WKB geometries, a typed column per property
+    /// and a `bbox` covering column so that readers can skip row groups outside a bbox.
+    pub fn save_to_geoparquet(&self, output_file_name: &str) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(output_file_name)?);
+        write_geoparquet(&mut file, &self.features, ROW_GROUP_SIZE)?;
+        file.flush()?;
+        info!("Merged polygons saved to {}", output_file_name);
+        Ok(())
+    }
+
+    /// Saves the features as KML, in folders mirroring their `SourceFileDir` with the
+    /// properties in the balloons. When `style_property` is given, the outlines are
+    /// coloured by the class of its value, see the `kml` module.
+    pub fn save_to_kml(
+        &self,
+        output_file_name: &str,
+        style_property: Option<&str>,
+    ) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(output_file_name)?);
+        write_kml(
+            &mut file,
+            &output_name(output_file_name),
+            &self.features,
+            style_property,
+        )?;
+        file.flush()?;
+        info!("Merged polygons saved to {}", output_file_name);
+        Ok(())
+    }
+
+    /// Saves the features as KMZ, a zip archive of the KML written by `save_to_kml`.
+    pub fn save_to_kmz(
+        &self,
+        output_file_name: &str,
+        style_property: Option<&str>,
+    ) -> std::io::Result<()> {
+        let mut archive = zip::ZipWriter::new(File::create(output_file_name)?);
+        archive.start_file("doc.kml", zip::write::SimpleFileOptions::default())?;
+        write_kml(
+            &mut archive,
+            &output_name(output_file_name),
+            &self.features,
+            style_property,
+        )?;
+        archive.finish()?;
+        info!("Merged polygons saved to {}", output_file_name);
+        Ok(())
+    }
+
+    /// Saves the features as a SQL script that creates and loads a PostGIS table, see the
+    /// `postgis` module. The table is named after the output file unless `options` names it.
+    pub fn save_to_sql(&self, output_file_name: &str, options: &SqlOptions) -> std::io::Result<()> {
+        let table = options
+            .table
+            .clone()
+            .unwrap_or_else(|| output_name(output_file_name).to_lowercase());
+        let mut file = BufWriter::new(File::create(output_file_name)?);
+        write_sql(&mut file, &table, &self.features, options, BATCH_ROWS)?;
+        file.flush()?;
+        info!("Merged polygons saved to {}", output_file_name);
+        Ok(())
+    }
+
     pub fn merge_geometries(&mut self, only_join_if_shared_vertex: bool, merge_if_overlap: bool) {
         let features_by_folder = self.group_features_by_folder();
         for (folder_path, features) in features_by_folder {
@@ -257,6 +375,96 @@ impl LasOutlineFeatureCollection {
         groups.into_values().collect()
     }
 
+    /// Finds groups of files that are likely duplicates of each other, e.g. the same tile
+    /// copied into several folders.
+    ///
+    /// Files are duplicates when they have the same number of points and the bounding boxes
+    /// of their outlines match within `EPSILON`. A group is an `exact` duplicate when every
+    /// file has the same `content_hash`, or, when the files were not hashed, the same
+    /// `file_size` and an identical outline. Otherwise it is a `geometric` duplicate, such
+    /// as a tile that was re-compressed as LAZ.
+    ///
+    /// Returns a collection with one feature per group, with the outline of the first file
+    /// and the `duplicate_type`, `number_of_files`, `number_of_points` and comma separated
+    /// `files` of the group.
+    pub fn find_duplicates(&self) -> LasOutlineFeatureCollection {
+        let mut by_points: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, feature) in self.features.iter().enumerate() {
+            if let Some(number_of_points) = feature
+                .property("number_of_points")
+                .and_then(serde_json::Value::as_u64)
+            {
+                by_points.entry(number_of_points).or_default().push(i);
+            }
+        }
+
+        let bboxes: Vec<Option<[f64; 4]>> = self.features.iter().map(outline_bbox).collect();
+        let mut uf = QuickUnionUf::<UnionByRank>::new(self.features.len());
+        for candidates in by_points.values() {
+            for (n, &i) in candidates.iter().enumerate() {
+                for &j in &candidates[n + 1..] {
+                    if let (Some(bbox1), Some(bbox2)) = (bboxes[i], bboxes[j]) {
+                        if bbox1
+                            .iter()
+                            .zip(bbox2)
+                            .all(|(a, b)| (a - b).abs() < EPSILON)
+                        {
+                            uf.union(i, j);
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut groups: HashMap<usize, Vec<&Feature>> = HashMap::new();
+        for (i, feature) in self.features.iter().enumerate() {
+            groups.entry(uf.find(i)).or_default().push(feature);
+        }
+        let mut groups: Vec<Vec<&Feature>> = groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect();
+        // Keep the report stable between runs, which finish files in any order
+        for group in groups.iter_mut() {
+            group.sort_by_key(|feature| source_files(&[feature]));
+        }
+        groups.sort_by_key(|group| source_files(group));
+
+        let mut report = LasOutlineFeatureCollection::new();
+        for group in groups {
+            let first = group[0];
+            let same = |key: &str| {
+                let value = first.property(key);
+                value.is_some() && group.iter().all(|feature| feature.property(key) == value)
+            };
+            let exact = if first.contains_property("content_hash") {
+                same("content_hash")
+            } else {
+                same("file_size")
+                    && group
+                        .iter()
+                        .all(|feature| feature.geometry == first.geometry)
+            };
+
+            let mut properties = JsonObject::new();
+            properties.insert(
+                "duplicate_type".to_string(),
+                if exact { "exact" } else { "geometric" }.into(),
+            );
+            properties.insert("number_of_files".to_string(), group.len().into());
+            if let Some(number_of_points) = first.property("number_of_points") {
+                properties.insert("number_of_points".to_string(), number_of_points.clone());
+            }
+            properties.insert("files".to_string(), source_files(&group).into());
+            report.add_feature(Feature {
+                geometry: first.geometry.clone(),
+                properties: Some(properties),
+                ..Default::default()
+            });
+        }
+        report
+    }
+
     fn merge_group(&self, features: Vec<Feature>, folder_path: &String) -> Option<Feature> {
         let merged_polygon = features.iter().fold(
             Polygon::new(LineString::new(vec![]), vec![]),
@@ -296,6 +504,7 @@ impl LasOutlineFeatureCollection {
         }
         // Merge properties
         let mut merged_properties: JsonObject = JsonObject::new();
+        let mut mean_weights: HashMap<String, f64> = HashMap::new();
         merged_properties.insert(
             "SourceFileDir".to_string(),
             serde_json::Value::String(folder_path.to_string()),
@@ -332,13 +541,62 @@ impl LasOutlineFeatureCollection {
                             });
                     }
                 }
+                let weight_of = |key: &str| {
+                    properties
+                        .get(key)
+                        .and_then(serde_json::Value::as_f64)
+                        .unwrap_or(0.0)
+                };
+                let point_weight = weight_of("number_of_points");
+                let spacing_weight = weight_of("point_spacing_samples");
                 for (key, value) in properties.iter() {
-                    if key != "SourceFile" && key != "SourceFileDir" && key != "number_of_points" {
-                        insert_unique_value(&mut merged_properties, key, value.clone());
+                    if SUMMED_VALUES.contains(&key.as_str()) {
+                        insert_summed_number(&mut merged_properties, key, value);
+                    } else if MIN_VALUES.contains(&key.as_str()) {
+                        insert_bound(&mut merged_properties, key, value, f64::min);
+                    } else if MAX_VALUES.contains(&key.as_str()) {
+                        insert_bound(&mut merged_properties, key, value, f64::max);
+                    } else if key == "z_mean" || SPACING_VALUES.contains(&key.as_str()) {
+                        let weight = if key == "z_mean" {
+                            point_weight
+                        } else {
+                            spacing_weight
+                        };
+                        insert_weighted_mean(
+                            &mut merged_properties,
+                            &mut mean_weights,
+                            key,
+                            value,
+                            weight,
+                        );
+                    } else if key == "validation_status" {
+                        insert_validation_status(&mut merged_properties, value);
+                    } else if key == "validation_issues" {
+                        let source_file = properties.get("SourceFile").and_then(|v| v.as_str());
+                        insert_validation_issues(&mut merged_properties, source_file, value);
+                    } else if key == "acquisition_start"
+                        || key == "acquisition_end"
+                        || key == "modified"
+                    {
+                        insert_time_bound(&mut merged_properties, key, value);
+                    } else if key != "SourceFile"
+                        && key != "SourceFileDir"
+                        && key != "number_of_points"
+                        && key != "content_hash"
+                        && !OUTLINE_MEASURES.contains(&key.as_str())
+                    {
+                        if let serde_json::Value::Object(counts) = value {
+                            insert_summed_counts(&mut merged_properties, key, counts);
+                        } else if let serde_json::Value::Array(items) = value {
+                            insert_unique_items(&mut merged_properties, key, items);
+                        } else {
+                            insert_unique_value(&mut merged_properties, key, value.clone());
+                        }
                     }
                 }
             }
         }
+        insert_outline_measures(&mut merged_properties, &merged_polygon);
         // Create a feature with the merged polygon and properties
         Some(Feature {
             geometry: Some(Geometry {
@@ -361,6 +619,46 @@ impl Default for LasOutlineFeatureCollection {
         LasOutlineFeatureCollection::new()
     }
 }
+
+/// Names a layer or document after the output file.
+fn output_name(output_file_name: &str) -> String {
+    Path::new(output_file_name)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Returns the bounding box of the outline of a feature as `[min_x, min_y, max_x, max_y]`.
+fn outline_bbox(feature: &Feature) -> Option<[f64; 4]> {
+    let Some(Geometry {
+        value: Value::Polygon(rings),
+        ..
+    }) = &feature.geometry
+    else {
+        return None;
+    };
+    rings.first()?.iter().fold(None, |bbox, c| {
+        let [min_x, min_y, max_x, max_y] = bbox.unwrap_or([c[0], c[1], c[0], c[1]]);
+        Some([
+            min_x.min(c[0]),
+            min_y.min(c[1]),
+            max_x.max(c[0]),
+            max_y.max(c[1]),
+        ])
+    })
+}
+
+/// Joins the `SourceFile` properties of a group of features with commas.
+fn source_files(features: &[&Feature]) -> String {
+    features
+        .iter()
+        .filter_map(|feature| feature.property("SourceFile"))
+        .filter_map(serde_json::Value::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 fn insert_unique_value(
     merged_properties: &mut serde_json::Map<String, serde_json::Value>,
     key: &str,
@@ -374,6 +672,7 @@ fn insert_unique_value(
         let new_value = match value {
             serde_json::Value::String(value_str) => value_str,
             serde_json::Value::Number(value_num) => value_num.to_string(),
+            serde_json::Value::Bool(value_bool) => value_bool.to_string(),
             _ => {
                 debug!("Unsupported value type: {:?}", value);
                 return;
@@ -389,3 +688,217 @@ fn insert_unique_value(
         }
     }
 }
+
+/// Adds the counts of an object property, such as a classification histogram, to the
+/// counts already merged under `key`.
+fn insert_summed_counts(
+    merged_properties: &mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+    counts: &serde_json::Map<String, serde_json::Value>,
+) {
+    let entry = merged_properties
+        .entry(key.to_string())
+        .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+
+    if let serde_json::Value::Object(merged_counts) = entry {
+        for (count_key, count_value) in counts {
+            let Some(count) = count_value.as_u64() else {
+                debug!("Unsupported count in {}: {:?}", key, count_value);
+                continue;
+            };
+            let total = merged_counts
+                .get(count_key)
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(0);
+            merged_counts.insert(
+                count_key.clone(),
+                serde_json::Value::Number(serde_json::Number::from(total + count)),
+            );
+        }
+    }
+}
+
+/// Widens the merged time range: keeps the earliest `acquisition_start`, and the latest
+/// `acquisition_end` and `modified`. The timestamps share one UTC format, so they compare
+/// as strings.
+fn insert_time_bound(
+    merged_properties: &mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+    value: &serde_json::Value,
+) {
+    let Some(time) = value.as_str() else {
+        return;
+    };
+    let replace = match merged_properties
+        .get(key)
+        .and_then(serde_json::Value::as_str)
+    {
+        Some(merged) if key == "acquisition_start" => time < merged,
+        Some(merged) => time > merged,
+        None => true,
+    };
+    if replace {
+        merged_properties.insert(key.to_string(), value.clone());
+    }
+}
+
+/// Adds a number property, such as an area or file size, to the total already merged
+/// under `key`. Integers stay integers.
+fn insert_summed_number(
+    merged_properties: &mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+    value: &serde_json::Value,
+) {
+    let merged = merged_properties.get(key);
+    let total = match (merged, value.as_u64()) {
+        (None, Some(count)) => count.into(),
+        (Some(serde_json::Value::Number(merged)), Some(count)) if merged.is_u64() => {
+            (merged.as_u64().unwrap_or(0) + count).into()
+        }
+        _ => match value.as_f64() {
+            Some(number) => {
+                (merged.and_then(serde_json::Value::as_f64).unwrap_or(0.0) + number).into()
+            }
+            None => return,
+        },
+    };
+    merged_properties.insert(key.to_string(), total);
+}
+
+/// Keeps the lower or higher of a number property and the value already merged under
+/// `key`, as chosen by `bound`.
+fn insert_bound(
+    merged_properties: &mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+    value: &serde_json::Value,
+    bound: fn(f64, f64) -> f64,
+) {
+    let Some(number) = value.as_f64() else {
+        return;
+    };
+    match merged_properties
+        .get(key)
+        .and_then(serde_json::Value::as_f64)
+    {
+        Some(merged) if bound(merged, number) == merged => {}
+        _ => {
+            merged_properties.insert(key.to_string(), value.clone());
+        }
+    }
+}
+
+/// Adds a number property to the weighted mean already merged under `key`. The weights
+/// merged so far are kept in `mean_weights`.
+fn insert_weighted_mean(
+    merged_properties: &mut serde_json::Map<String, serde_json::Value>,
+    mean_weights: &mut HashMap<String, f64>,
+    key: &str,
+    value: &serde_json::Value,
+    weight: f64,
+) {
+    let Some(number) = value.as_f64() else {
+        return;
+    };
+    let merged_weight = mean_weights.entry(key.to_string()).or_insert(0.0);
+    let mean = match merged_properties
+        .get(key)
+        .and_then(serde_json::Value::as_f64)
+    {
+        Some(merged) if *merged_weight + weight > 0.0 => {
+            (merged * *merged_weight + number * weight) / (*merged_weight + weight)
+        }
+        _ => number,
+    };
+    *merged_weight += weight;
+    merged_properties.insert(key.to_string(), mean.into());
+}
+
+/// Merges `validation_status`: a group is `invalid` when any of its files is.
+fn insert_validation_status(
+    merged_properties: &mut serde_json::Map<String, serde_json::Value>,
+    value: &serde_json::Value,
+) {
+    if merged_properties.get("validation_status") != Some(&"invalid".into()) {
+        merged_properties.insert("validation_status".to_string(), value.clone());
+    }
+}
+
+/// Adds the `validation_issues` of a file, prefixed with the file name, to those already
+/// merged. The issues of different files are separated by semicolons, like the issues of
+/// one file.
+fn insert_validation_issues(
+    merged_properties: &mut serde_json::Map<String, serde_json::Value>,
+    source_file: Option<&str>,
+    value: &serde_json::Value,
+) {
+    let Some(issues) = value.as_str() else {
+        return;
+    };
+    let issues = match source_file {
+        Some(source_file) => format!("{}: {}", source_file, issues),
+        None => issues.to_string(),
+    };
+    let merged = match merged_properties
+        .get("validation_issues")
+        .and_then(serde_json::Value::as_str)
+    {
+        Some(merged) => format!("{}; {}", merged, issues),
+        None => issues,
+    };
+    merged_properties.insert("validation_issues".to_string(), merged.into());
+}
+
+/// Adds the items of an array property, such as the VLRs of a header, to the items
+/// already merged under `key`, skipping items that are already there.
+fn insert_unique_items(
+    merged_properties: &mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+    items: &[serde_json::Value],
+) {
+    let entry = merged_properties
+        .entry(key.to_string())
+        .or_insert_with(|| serde_json::Value::Array(Vec::new()));
+
+    if let serde_json::Value::Array(merged_items) = entry {
+        for item in items {
+            if !merged_items.contains(item) {
+                merged_items.push(item.clone());
+            }
+        }
+    }
+}
+
+/// Adds the geodesic area and perimeter of an outline in EPSG:4326, and the point
+/// densities derived from them.
+///
+/// `point_density` is the number of points per square metre of the outline, and
+/// `cell_density` the number of points per square metre of occupied cells, when the
+/// properties have an `occupied_area_m2`.
+pub(crate) fn insert_outline_measures(properties: &mut JsonObject, polygon: &Polygon<f64>) {
+    let (perimeter, area) = polygon.geodesic_perimeter_area_unsigned();
+    properties.insert("area_km2".to_string(), (area / 1e6).into());
+    properties.insert("perimeter_m".to_string(), perimeter.into());
+
+    let Some(number_of_points) = properties
+        .get("number_of_points")
+        .and_then(serde_json::Value::as_u64)
+    else {
+        return;
+    };
+    if area > 0.0 {
+        properties.insert(
+            "point_density".to_string(),
+            (number_of_points as f64 / area).into(),
+        );
+    }
+    if let Some(occupied_area) = properties
+        .get("occupied_area_m2")
+        .and_then(serde_json::Value::as_f64)
+        .filter(|&occupied_area| occupied_area > 0.0)
+    {
+        properties.insert(
+            "cell_density".to_string(),
+            (number_of_points as f64 / occupied_area).into(),
+        );
+    }
+}
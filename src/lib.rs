@@ -25,6 +25,7 @@
 //!         recurse: true,
 //!         guess_crs: true,
 //!         output_file: None,
+//!         ..Default::default()
 //!     };
 //!
 //!     process_folder(config)?;
@@ -39,20 +40,48 @@
 //! }
 //! ```
 
+mod columns;
 mod crs_utils;
+mod file_info;
+mod flatgeobuf;
+mod geojson_seq;
+mod geopackage;
+mod geoparquet;
+mod gps_time;
+mod header_properties;
+mod hilbert;
+mod kml;
 pub mod las_feature_collection;
+mod outliers;
+mod outline;
+mod output_format;
+mod point_filter;
+mod postgis;
+mod repair;
+mod sampling;
+mod shapefile;
+mod spacing;
+mod stac;
+mod statistics;
+mod tindex;
+mod validation;
+mod vpc;
+mod wkb;
 
-use crs_utils::{extract_crs, extract_crs_from_geotiff, guess_las_crs, Crs, CrsError};
-use geo::{ConvexHull, Coord, LineString, Polygon};
-use las::Reader;
+use crs_utils::{
+    check_crs_extent, extract_crs, extract_crs_from_geotiff, guess_las_crs, Crs, CrsError,
+};
+use geo::{Coord, GeodesicArea, LineString, Polygon};
+use las::{Header, Reader};
 use serde::Serialize;
 use serde_json::Map;
 
+use std::ops::Range;
 use std::path::Path;
 use std::sync::mpsc;
 use std::sync::{
     atomic::{AtomicUsize, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 use std::thread;
 
@@ -60,10 +89,31 @@ use thiserror::Error;
 use threadpool::ThreadPool;
 use walkdir::WalkDir;
 
+pub use file_info::HashAlgorithm;
+use file_info::{hash_file, modified_time};
 use geojson::Feature;
 use geojson::{Geometry, Value};
-use las_feature_collection::LasOutlineFeatureCollection;
-use log::{debug, error, info}; // Add this line to import the logging macros
+use geojson_seq::GeoJsonSeqWriter;
+use geopackage::save_geopackage;
+use gps_time::acquisition_properties;
+use header_properties::full_header_properties;
+use las_feature_collection::{insert_outline_measures, LasOutlineFeatureCollection};
+use log::{debug, error, info, warn}; // Add this line to import the logging macros
+use outliers::remove_outliers;
+pub use outliers::OutlierFilter;
+use outline::{point_ranges, PartialOutline};
+pub use output_format::OutputFormat;
+pub use point_filter::PointFilter;
+pub use postgis::{SqlGeometry, SqlOptions};
+pub use repair::repair_file;
+pub use sampling::PointBudget;
+use spacing::estimate_point_spacing;
+use stac::write_stac_catalog;
+pub use statistics::Statistic;
+use statistics::{statistics_properties, PointSummary};
+use tindex::tindex_feature;
+use validation::validation_properties;
+use vpc::write_vpc;
 
 /// Processes a folder containing LAS files and generates GeoJSON polygons.
 ///
@@ -74,7 +124,22 @@ use log::{debug, error, info}; // Add this line to import the logging macros
 /// * `group_by_folder` - Whether to group the polygons by folder.
 /// * `recurse` - Whether to recurse into subdirectories.
 /// * `guess_crs` - Whether to guess the crs based on a random sample of 10 points.
+/// * `sample_points` - Build the detailed outline from a sample of this many points instead of every point.
+/// * `split_points` - Split files with more points than this into ranges that are read in parallel.
+/// * `point_filter` - Which points to use for detailed outlines, e.g. to drop noise classes.
+/// * `outlier_filter` - Drop isolated points, such as GPS glitches, before computing detailed outlines.
+/// * `statistics` - Per-file statistics to add to the properties, such as the Z range.
+/// * `density_cell_size` - Cell size for the occupied-cell point density of detailed outlines.
+/// * `point_spacing` - Estimate the nominal point spacing from this many random runs of points.
+/// * `gps_week` - GPS week of files with GPS week time, instead of the week of the header date.
+/// * `full_header` - Add the point format, scales, offsets, native bounds, VLRs and other header fields.
+/// * `hash` - Hash the content of each file, e.g. to find duplicates. Hashing is skipped when `None`.
+/// * `duplicates_file` - Write groups of likely duplicate files to this GeoJSON file.
+/// * `validate` - Check the header bounds and counts against the points, within this tolerance.
+/// * `crs_check` - What to do when the header bounds fall outside the CRS area of use.
+/// * `reguess_crs` - With `guess_crs`, replace a CRS that fails the check by a guessed one.
 /// * `output_file` - Optional output file name. If not provided, a default name will be used.
+/// * `format` - Output file format. If not provided, it is taken from the output file extension.
 ///
 /// # Returns
 ///
@@ -103,6 +168,7 @@ use log::{debug, error, info}; // Add this line to import the logging macros
 ///         recurse: true,
 ///         guess_crs: true,
 ///         output_file: Some(temp_dir.path().join("output.geojson").to_str().unwrap().to_string()),
+///         ..Default::default()
 ///     };
 ///
 ///     process_folder(config)?;
@@ -124,9 +190,23 @@ pub enum LasPolyError {
     PathError(String),
     #[error("Failed to create Proj instance: {0}")]
     ProjCreateError(#[from] proj::ProjCreateError),
+    #[error("Failed to write GeoPackage: {0}")]
+    GeoPackageError(#[from] rusqlite::Error),
+}
+
+/// What to do when the header bounds do not fit the resolved CRS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CrsCheck {
+    /// Skip the check.
+    Off,
+    /// Keep the file and record the problem in a `crs_warning` property.
+    #[default]
+    Warn,
+    /// Fail the file.
+    Error,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct ProcessConfig {
     pub folder_path: String,
     pub use_detailed_outline: bool,
@@ -136,6 +216,73 @@ pub struct ProcessConfig {
     pub recurse: bool,
     pub guess_crs: bool,
     pub output_file: Option<String>,
+    pub crs_check: CrsCheck,
+    /// With `guess_crs`, replaces a CRS that fails the `crs_check` by a guessed CRS that
+    /// passes it. Off by default, so the declared CRS of a file is kept.
+    pub reguess_crs: bool,
+    /// Point budget for a sampled outline. When set, the detailed outline is built from
+    /// this many points spread through the file, see the `sampling` module for the error bound.
+    pub sample_points: Option<PointBudget>,
+    /// Files with more points than this are split into ranges of about this many points,
+    /// and the ranges of the detailed outline are read in parallel.
+    pub split_points: Option<u64>,
+    /// Points to leave out of detailed and sampled outlines.
+    pub point_filter: PointFilter,
+    /// Removes isolated points before the detailed outline is computed. Files are then
+    /// read whole rather than split into ranges.
+    pub outlier_filter: Option<OutlierFilter>,
+    /// Statistics to add to the properties of each file. Statistics read from the points
+    /// cover every point, including those the point filter leaves out of the outline, and
+    /// are replaced by the header values for sampled outlines.
+    pub statistics: Vec<Statistic>,
+    /// Cell size, in native units, for the occupied-cell density of detailed outlines.
+    /// Every outline gets `area_km2`, `perimeter_m` and a hull `point_density`; with a cell
+    /// size the points are also binned into cells and `cell_density` is the number of
+    /// points per square metre of occupied cells.
+    pub density_cell_size: Option<f64>,
+    /// Number of random runs of points used to estimate the nominal point spacing, see the
+    /// `spacing` module. When set, `point_spacing` and its percentiles are added to the
+    /// properties.
+    pub point_spacing: Option<usize>,
+    /// GPS week used to place GPS week times in time for `acquisition_start` and
+    /// `acquisition_end`. When not set, the week is inferred from the header date.
+    pub gps_week: Option<u32>,
+    /// Adds the full header property set: point format, scales and offsets, GUID and
+    /// global encoding, native bounds, VLR and EVLR counts with a `user_id:record_id`
+    /// list, and the extra bytes schema.
+    pub full_header: bool,
+    /// Records a content hash of each file in `content_hash`. The file size and
+    /// modification time are always recorded.
+    pub hash: Option<HashAlgorithm>,
+    /// Writes a report of groups of likely duplicate files to this GeoJSON file, see
+    /// `LasOutlineFeatureCollection::find_duplicates`. Hashing the files tells exact copies
+    /// from geometric duplicates reliably.
+    pub duplicates_file: Option<String>,
+    /// Reads every point to check the header bounds, point count and return counts, and
+    /// records the result in `validation_status` and `validation_issues`. Bounds may differ
+    /// by this tolerance in native units, or by one step of the scale factor if that is
+    /// larger.
+    pub validate: Option<f64>,
+    /// Format of the output file. When not set, the format is taken from the extension of
+    /// `output_file`, falling back to GeoJSON. A GeoPackage holds a `footprints` layer with
+    /// the outline of every file and a `merged` layer with the merged outlines, merged by
+    /// folder when no merge option is set.
+    pub format: Option<OutputFormat>,
+    /// Property to colour KML and KMZ outlines by, such as `date` or `point_density`.
+    pub kml_style_by: Option<String>,
+    /// Writes each feature to this GeoJSON text sequence as soon as its file is done, see
+    /// the `geojson_seq` module. The output file is still written once all files are done.
+    pub stream_file: Option<String>,
+    /// Writes the PDAL `tindex` schema instead of the usual properties: `location` and
+    /// `srs`, see the `tindex` module. Outlines are not merged.
+    pub tindex: bool,
+    /// Adds the `created` and `modified` times of the file to the `tindex` schema.
+    pub tindex_timestamps: bool,
+    /// Writes a static STAC catalog of the files to this directory, with a Collection for
+    /// each folder and an Item for each file, see the `stac` module.
+    pub stac_dir: Option<String>,
+    /// Table name, geometry encoding and upsert of the SQL output, see the `postgis` module.
+    pub sql: SqlOptions,
 }
 
 pub fn process_folder(config: ProcessConfig) -> Result<(), LasPolyError> {
@@ -145,6 +292,11 @@ pub fn process_folder(config: ProcessConfig) -> Result<(), LasPolyError> {
     if !path.exists() {
         return Err(LasPolyError::PathError(config.folder_path));
     }
+    // Fail on a bad stream file before any file is queued
+    let mut stream = match &config.stream_file {
+        Some(stream_file) => Some(GeoJsonSeqWriter::create(stream_file)?),
+        None => None,
+    };
     let num_threads = num_cpus::get();
     debug!("Number of threads used: {:?}", num_threads);
 
@@ -203,17 +355,28 @@ pub fn process_folder(config: ProcessConfig) -> Result<(), LasPolyError> {
         let config = config.clone();
         let succeeded_files = Arc::clone(&succeeded_files);
         let failed_files = Arc::clone(&failed_files);
+        let split_pool = pool.clone();
         pool.execute(move || {
-            match create_polygon(&file_path, config.use_detailed_outline, config.guess_crs) {
+            let report_path = file_path.clone();
+            let report = move |result: Result<Feature, LasPolyError>| match result {
                 Ok(feature) => {
                     feature_tx.send(feature).unwrap();
                     succeeded_files.fetch_add(1, Ordering::SeqCst);
-                    debug!("Processed file successfully: {:?}", file_path);
+                    debug!("Processed file successfully: {:?}", report_path);
                 }
                 Err(e) => {
-                    error!("Error in thread {:?}: {:?}", file_path, e);
+                    error!("Error in thread {:?}: {:?}", report_path, e);
                     failed_files.fetch_add(1, Ordering::SeqCst);
                 }
+            };
+            if config.split_points.is_some()
+                && config.use_detailed_outline
+                && config.sample_points.is_none()
+                && config.outlier_filter.is_none()
+            {
+                create_polygon_in_ranges(file_path, config, &split_pool, report);
+            } else {
+                report(create_polygon_with_config(&file_path, &config));
             }
         });
     }
@@ -221,14 +384,46 @@ pub fn process_folder(config: ProcessConfig) -> Result<(), LasPolyError> {
     drop(feature_tx); // Close the channel to signal completion
 
     let mut feature_collection = LasOutlineFeatureCollection::new();
-
     // Collect features from the channel
     for feature in feature_rx {
+        if let Some(writer) = &mut stream {
+            if let Err(e) = writer.write_feature(&feature) {
+                // Keep going, the output file is written at the end
+                let stream_file = config.stream_file.as_deref().unwrap_or_default();
+                error!("Streaming to {} stopped: {}", stream_file, e);
+                stream = None;
+            }
+        }
         feature_collection.add_feature(feature);
     }
 
-    // Merge geometries if group_by_folder is true
-    if config.group_by_folder || config.merge_tiled || config.merge_if_overlap {
+    if let Some(duplicates_file) = &config.duplicates_file {
+        let duplicates = feature_collection.find_duplicates();
+        info!(
+            "Found {} groups of duplicate files",
+            duplicates.features().len()
+        );
+        duplicates.save_to_file(duplicates_file)?;
+    }
+
+    if let Some(stac_dir) = &config.stac_dir {
+        write_stac_catalog(stac_dir, feature_collection.features())?;
+        info!("STAC catalog saved to {}", stac_dir);
+    }
+
+    let format = OutputFormat::resolve(config.format, config.output_file.as_deref());
+    let footprints = (format == OutputFormat::GeoPackage && !config.tindex)
+        .then(|| feature_collection.features().clone());
+
+    // Merge geometries if group_by_folder is true. A tile index and a virtual point cloud
+    // keep one outline per file.
+    if !config.tindex
+        && format != OutputFormat::Vpc
+        && (config.group_by_folder
+            || config.merge_tiled
+            || config.merge_if_overlap
+            || footprints.is_some())
+    {
         feature_collection.merge_geometries(config.merge_tiled, config.merge_if_overlap);
     }
 
@@ -236,10 +431,41 @@ pub fn process_folder(config: ProcessConfig) -> Result<(), LasPolyError> {
     let file_stem = path
         .file_name()
         .unwrap_or_else(|| path.components().last().unwrap().as_os_str());
-    let binding = format!("{}.geojson", file_stem.to_string_lossy());
+    let binding = format!("{}.{}", file_stem.to_string_lossy(), format.extension());
     let output_file_name = config.output_file.as_deref().unwrap_or(&binding);
 
-    feature_collection.save_to_file(output_file_name)?;
+    match format {
+        OutputFormat::GeoJson => feature_collection.save_to_file(output_file_name)?,
+        OutputFormat::FlatGeobuf => feature_collection.save_to_flatgeobuf(output_file_name)?,
+        OutputFormat::Shapefile => feature_collection.save_to_shapefile(output_file_name)?,
+        OutputFormat::GeoParquet => feature_collection.save_to_geoparquet(output_file_name)?,
+        OutputFormat::Kml => {
+            feature_collection.save_to_kml(output_file_name, config.kml_style_by.as_deref())?
+        }
+        OutputFormat::Kmz => {
+            feature_collection.save_to_kmz(output_file_name, config.kml_style_by.as_deref())?
+        }
+        OutputFormat::Sql => feature_collection.save_to_sql(output_file_name, &config.sql)?,
+        OutputFormat::Vpc => {
+            write_vpc(output_file_name, feature_collection.features())?;
+            info!("Virtual point cloud saved to {}", output_file_name);
+        }
+        OutputFormat::GeoPackage if config.tindex => {
+            // The default layer name of pdal tindex
+            save_geopackage(output_file_name, &[("pdal", feature_collection.features())])?;
+            info!("Tile index saved to {}", output_file_name);
+        }
+        OutputFormat::GeoPackage => {
+            save_geopackage(
+                output_file_name,
+                &[
+                    ("footprints", &footprints.unwrap_or_default()),
+                    ("merged", feature_collection.features()),
+                ],
+            )?;
+            info!("Outlines saved to {}", output_file_name);
+        }
+    }
 
     Ok(())
 }
@@ -269,7 +495,7 @@ use proj::Proj;
 /// ```
 ///
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct FeatureProperties<'a> {
     filename: String,
     folder_path: Option<&'a Path>,
@@ -279,9 +505,59 @@ struct FeatureProperties<'a> {
     generating_software: String,
     version: String,
     system_identifier: String,
+    file_size: Option<u64>,
+    modified: Option<String>,
+    content_hash: Option<String>,
+    crs_warning: Option<String>,
+    sampled_points: Option<u64>,
+    outliers_removed: Option<u64>,
+    occupied_area_m2: Option<f64>,
+    statistics: Map<String, serde_json::Value>,
+    full_header: Map<String, serde_json::Value>,
 }
 
-impl FeatureProperties<'_> {
+impl<'a> FeatureProperties<'a> {
+    /// Reads the properties stored in the LAS header, and the size and modification time
+    /// of the file.
+    fn from_header(file_path: &'a str, header: &Header) -> Self {
+        let metadata = std::fs::metadata(file_path).ok();
+        FeatureProperties {
+            filename: file_path.to_string(),
+            // Extract folder path from file path
+            folder_path: Path::new(file_path).parent(),
+            number_of_points: header.number_of_points(),
+            date: header.date().map(|d| d.to_string()),
+            file_source_id: header.file_source_id(),
+            generating_software: header.generating_software().to_string(),
+            version: format!("{}.{}", header.version().major, header.version().minor),
+            system_identifier: header.system_identifier().to_string(),
+            file_size: metadata.as_ref().map(|metadata| metadata.len()),
+            modified: metadata.as_ref().and_then(modified_time),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a feature with these properties and the given outline, adding the area,
+    /// perimeter and densities of the outline.
+    fn into_feature(self, geojson_polygon: Value) -> Feature {
+        let mut properties = self.to_map();
+        if let Value::Polygon(ref rings) = geojson_polygon {
+            let exterior: Vec<Coord<f64>> = rings[0]
+                .iter()
+                .map(|c| Coord { x: c[0], y: c[1] })
+                .collect();
+            let polygon = Polygon::new(LineString::from(exterior), vec![]);
+            insert_outline_measures(&mut properties, &polygon);
+        }
+        Feature {
+            geometry: Some(Geometry::new(geojson_polygon)),
+            properties: Some(properties),
+            id: None,
+            bbox: None,
+            foreign_members: None,
+        }
+    }
+
     fn to_map(&self) -> Map<String, serde_json::Value> {
         let mut map: Map<String, serde_json::Value> = Map::new();
         map.insert("SourceFile".to_string(), self.filename.clone().into());
@@ -305,6 +581,29 @@ impl FeatureProperties<'_> {
             "system_identifier".to_string(),
             self.system_identifier.clone().into(),
         );
+        if let Some(file_size) = self.file_size {
+            map.insert("file_size".to_string(), file_size.into());
+        }
+        if let Some(ref modified) = self.modified {
+            map.insert("modified".to_string(), modified.clone().into());
+        }
+        if let Some(ref content_hash) = self.content_hash {
+            map.insert("content_hash".to_string(), content_hash.clone().into());
+        }
+        if let Some(ref crs_warning) = self.crs_warning {
+            map.insert("crs_warning".to_string(), crs_warning.clone().into());
+        }
+        if let Some(sampled_points) = self.sampled_points {
+            map.insert("sampled_points".to_string(), sampled_points.into());
+        }
+        if let Some(outliers_removed) = self.outliers_removed {
+            map.insert("outliers_removed".to_string(), outliers_removed.into());
+        }
+        if let Some(occupied_area_m2) = self.occupied_area_m2 {
+            map.insert("occupied_area_m2".to_string(), occupied_area_m2.into());
+        }
+        map.extend(self.statistics.clone());
+        map.extend(self.full_header.clone());
         map
     }
 }
@@ -312,9 +611,135 @@ impl FeatureProperties<'_> {
 pub fn create_polygon(
     file_path: &str,
     use_detailed_outline: bool,
-    mut guess_crs: bool,
+    guess_crs: bool,
+) -> Result<Feature, LasPolyError> {
+    let config = ProcessConfig {
+        use_detailed_outline,
+        guess_crs,
+        ..Default::default()
+    };
+    create_polygon_with_config(file_path, &config)
+}
+
+/// Creates a polygon from a LAS file using the per-file options of a `ProcessConfig`.
+///
+/// `create_polygon` is a shorthand for this function with the default options.
+pub fn create_polygon_with_config(
+    file_path: &str,
+    config: &ProcessConfig,
 ) -> Result<Feature, LasPolyError> {
     // Open the LAS file
+    let mut reader = Reader::from_path(file_path)?;
+    let resolved = resolve_crs(file_path, reader.header(), config)?;
+    let to_epsg4326 = &resolved.to_epsg4326;
+
+    let mut sampled_points = None;
+    let mut outliers_removed = None;
+    let mut summary = None;
+    let geojson_polygon = if !config.use_detailed_outline && config.sample_points.is_none() {
+        // Use the header to create a faster outline of data
+        let bounds = reader.header().bounds();
+        let exterior_coords = vec![
+            to_epsg4326
+                .convert((bounds.min.x, bounds.min.y))
+                .unwrap_or((bounds.min.x, bounds.min.y)),
+            to_epsg4326
+                .convert((bounds.max.x, bounds.min.y))
+                .unwrap_or((bounds.max.x, bounds.min.y)),
+            to_epsg4326
+                .convert((bounds.max.x, bounds.max.y))
+                .unwrap_or((bounds.max.x, bounds.max.y)),
+            to_epsg4326
+                .convert((bounds.min.x, bounds.max.y))
+                .unwrap_or((bounds.min.x, bounds.max.y)),
+            to_epsg4326
+                .convert((bounds.min.x, bounds.min.y))
+                .unwrap_or((bounds.min.x, bounds.min.y)),
+        ]
+        .into_iter()
+        .map(|(x, y)| vec![x, y])
+        .collect();
+        Value::Polygon(vec![exterior_coords])
+    } else {
+        // Collect points, either all of them or an evenly spread sample
+        let las_points: Box<dyn Iterator<Item = las::Point>> = match config.sample_points {
+            Some(budget) => {
+                let sample = sampling::sample_points(&mut reader, budget)?;
+                sampled_points = Some(sample.len() as u64);
+                Box::new(sample.into_iter())
+            }
+            None => Box::new(reader.points().filter_map(Result::ok)),
+        };
+        let point_summary =
+            summary.insert(PointSummary::with_cell_size(occupied_cell_size(config)));
+        let coords = las_points
+            .filter(|p| summarize_point(point_summary, p, &config.point_filter))
+            .map(|p| Coord { x: p.x, y: p.y });
+        let outline = match config.outlier_filter {
+            Some(outlier_filter) => {
+                let mut coords: Vec<Coord<f64>> = coords.collect();
+                let removed = remove_outliers(&mut coords, &outlier_filter);
+                debug!("Removed {} outliers from {:?}", removed, file_path);
+                outliers_removed = Some(removed as u64);
+                PartialOutline::from_coords(coords.into_iter(), to_epsg4326)
+            }
+            None => PartialOutline::from_coords(coords, to_epsg4326),
+        };
+        outline.into_value()
+    };
+
+    if config.tindex {
+        return Ok(tindex_feature(
+            file_path,
+            &resolved.crs,
+            config.tindex_timestamps,
+            geojson_polygon,
+        ));
+    }
+
+    let mut properties = FeatureProperties::from_header(file_path, reader.header());
+    properties.crs_warning = resolved.crs_warning;
+    properties.sampled_points = sampled_points;
+    properties.outliers_removed = outliers_removed;
+    properties.occupied_area_m2 = summary
+        .as_ref()
+        .and_then(|summary| occupied_area_m2(reader.header(), summary, to_epsg4326));
+    // A sample would give too narrow ranges, so sampled outlines get the header values
+    let file_summary = summary.as_ref().filter(|_| sampled_points.is_none());
+    properties.statistics =
+        statistics_properties(reader.header(), file_summary, &config.statistics);
+    properties.statistics.extend(acquisition_properties(
+        reader.header(),
+        file_summary.and_then(PointSummary::gps_time_range),
+        config.gps_week,
+    ));
+    properties
+        .statistics
+        .extend(extra_read_properties(file_path, config)?);
+    if config.full_header {
+        properties.full_header = full_header_properties(file_path, reader.header())?;
+    }
+    properties.content_hash = config
+        .hash
+        .map(|algorithm| hash_file(file_path, algorithm))
+        .transpose()?;
+    Ok(properties.into_feature(geojson_polygon))
+}
+
+/// The CRS of a file and the transformation of its coordinates to EPSG:4326.
+struct ResolvedCrs {
+    crs: String,
+    to_epsg4326: Proj,
+    crs_warning: Option<String>,
+}
+
+/// Finds the CRS of a file, guessing it if allowed, and checks it against the header bounds.
+fn resolve_crs(
+    file_path: &str,
+    header: &Header,
+    config: &ProcessConfig,
+) -> Result<ResolvedCrs, LasPolyError> {
+    let mut guess_crs = config.guess_crs;
     let mut crs = match extract_crs(file_path)? {
         // Check the CRS of the LAS file
         Some(Crs::Wkt(wkt)) => wkt,
@@ -337,10 +762,11 @@ pub fn create_polygon(
     debug!("CRS: {:?}", crs);
 
     // Create a Proj instance for transforming coordinates to EPSG:4326
-    let to_epsg4326 = match Proj::new_known_crs(&crs, "EPSG:4326", None) {
+    let mut to_epsg4326 = match Proj::new_known_crs(&crs, "EPSG:4326", None) {
         Ok(proj) => proj,
         Err(e) => {
             if guess_crs {
+                guess_crs = false;
                 crs = guess_las_crs(file_path, 10)?;
                 Proj::new_known_crs(&crs, "EPSG:4326", None)?
             } else {
@@ -348,85 +774,313 @@ pub fn create_polygon(
             }
         }
     };
-    let mut reader = Reader::from_path(file_path)?;
 
-    let geojson_polygon = if !use_detailed_outline {
-        // Use the header to create a faster outline of data
-        let bounds = reader.header().bounds();
-        let exterior_coords = vec![
-            to_epsg4326
-                .convert((bounds.min.x, bounds.min.y))
-                .unwrap_or((bounds.min.x, bounds.min.y)),
-            to_epsg4326
-                .convert((bounds.max.x, bounds.min.y))
-                .unwrap_or((bounds.max.x, bounds.min.y)),
-            to_epsg4326
-                .convert((bounds.max.x, bounds.max.y))
-                .unwrap_or((bounds.max.x, bounds.max.y)),
-            to_epsg4326
-                .convert((bounds.min.x, bounds.max.y))
-                .unwrap_or((bounds.min.x, bounds.max.y)),
-            to_epsg4326
-                .convert((bounds.min.x, bounds.min.y))
-                .unwrap_or((bounds.min.x, bounds.min.y)),
-        ]
-        .into_iter()
-        .map(|(x, y)| vec![x, y])
-        .collect();
-        Value::Polygon(vec![exterior_coords])
-    } else {
-        // Collect points
-        let points: Vec<Coord<f64>> = reader
-            .points()
-            .filter_map(Result::ok)
-            .map(|p| {
-                let (x, y) = to_epsg4326.convert((p.x, p.y)).unwrap_or((p.x, p.y));
-                Coord { x, y }
-            })
-            .collect();
-
-        // Create a LineString from the points
-        let line_string = LineString::from(points);
-
-        // Compute the convex_hull
-        let convex_hull: Polygon<f64> = line_string.convex_hull();
-
-        // Convert the convex_hull to GeoJSON
-        let exterior_coords: Vec<Vec<f64>> = convex_hull
-            .exterior()
-            .coords()
-            .map(|c| vec![c.x, c.y])
-            .collect();
-        Value::Polygon(vec![exterior_coords])
+    // Sanity check the header bounds against the CRS
+    let mut crs_warning = None;
+    if config.crs_check != CrsCheck::Off {
+        let bounds = header.bounds();
+        let mut check = check_crs_extent(&crs, &bounds, &to_epsg4326);
+        if check.is_err() && guess_crs && config.reguess_crs {
+            debug!("CRS {:?} does not match {}, guessing again", crs, file_path);
+            // The file keeps its own CRS and the warning unless the guess can be used
+            if let Ok(guessed_crs) = guess_las_crs(file_path, 10) {
+                if let Ok(guessed_proj) = Proj::new_known_crs(&guessed_crs, "EPSG:4326", None) {
+                    let guessed_check = check_crs_extent(&guessed_crs, &bounds, &guessed_proj);
+                    if guessed_check.is_ok() {
+                        debug!("Using guessed CRS {:?} for {}", guessed_crs, file_path);
+                        crs = guessed_crs;
+                        to_epsg4326 = guessed_proj;
+                        check = guessed_check;
+                    }
+                }
+            }
+        }
+        if let Err(e) = check {
+            match config.crs_check {
+                CrsCheck::Error => return Err(LasPolyError::CrsError(e)),
+                _ => {
+                    warn!("{}: {}", file_path, e);
+                    crs_warning = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(ResolvedCrs {
+        crs,
+        to_epsg4326,
+        crs_warning,
+    })
+}
+
+/// Shared state of the range jobs of a file split by `create_polygon_in_ranges`.
+struct SplitFile<F> {
+    remaining: usize,
+    outline: PartialOutline,
+    summary: PointSummary,
+    extra: Map<String, serde_json::Value>,
+    full_header: Map<String, serde_json::Value>,
+    content_hash: Option<String>,
+    error: Option<LasPolyError>,
+    on_done: Option<F>,
+}
+
+/// The file the jobs of a split file work on.
+struct SplitContext {
+    file_path: String,
+    crs: String,
+    crs_warning: Option<String>,
+    config: ProcessConfig,
+    header: Header,
+}
+
+/// What a job of a split file adds to its feature.
+enum SplitPart {
+    Range(PartialOutline, Box<PointSummary>),
+    Extra(Map<String, serde_json::Value>),
+    FullHeader(Map<String, serde_json::Value>),
+    ContentHash(String),
+}
+
+/// A job of a split file: a range of its points, or one of the whole-file reads.
+type SplitJob = Box<dyn FnOnce(&SplitContext) -> Result<SplitPart, LasPolyError> + Send>;
+
+/// Creates the detailed outline of a large file by splitting it into point ranges.
+///
+/// Each range is read and hulled as a separate job on `pool`, and the job that finishes
+/// last combines the partial hulls and calls `on_done` with the feature. Files that fit
+/// in a single range are processed in the calling thread.
+fn create_polygon_in_ranges<F>(
+    file_path: String,
+    config: ProcessConfig,
+    pool: &ThreadPool,
+    on_done: F,
+) where
+    F: FnOnce(Result<Feature, LasPolyError>) + Send + 'static,
+{
+    let range_points = config.split_points.unwrap_or(u64::MAX);
+    let header = match Reader::from_path(&file_path) {
+        Ok(reader) => reader.header().clone(),
+        Err(e) => return on_done(Err(e.into())),
     };
-    let geometry = Geometry::new(geojson_polygon);
-
-    // Extract folder path from file path
-    let folder_path = Path::new(file_path).parent();
-
-    // Add additional properties from the LAS header
-    let header = reader.header();
-    let properties = FeatureProperties {
-        filename: file_path.to_string(),
-        folder_path,
-        number_of_points: header.number_of_points(),
-        date: header.date().map(|d| d.to_string()),
-        file_source_id: header.file_source_id(),
-        generating_software: header.generating_software().to_string(),
-        version: format!("{}.{}", header.version().major, header.version().minor),
-        system_identifier: header.system_identifier().to_string(),
+    let ranges = point_ranges(&header, range_points);
+    if ranges.len() <= 1 {
+        return on_done(create_polygon_with_config(&file_path, &config));
+    }
+    let resolved = match resolve_crs(&file_path, &header, &config) {
+        Ok(resolved) => resolved,
+        Err(e) => return on_done(Err(e)),
     };
+    debug!("Splitting {:?} into {} ranges", file_path, ranges.len());
+
+    // The whole-file reads go first, so they run alongside the ranges
+    let mut jobs: Vec<SplitJob> = Vec::new();
+    if config.point_spacing.is_some() || config.validate.is_some() {
+        jobs.push(Box::new(|file| {
+            extra_read_properties(&file.file_path, &file.config).map(SplitPart::Extra)
+        }));
+    }
+    if config.full_header {
+        jobs.push(Box::new(|file| {
+            Ok(SplitPart::FullHeader(full_header_properties(
+                &file.file_path,
+                &file.header,
+            )?))
+        }));
+    }
+    if let Some(algorithm) = config.hash {
+        jobs.push(Box::new(move |file| {
+            Ok(SplitPart::ContentHash(hash_file(
+                &file.file_path,
+                algorithm,
+            )?))
+        }));
+    }
+    for range in ranges {
+        jobs.push(Box::new(move |file| {
+            let (outline, summary) =
+                outline_of_range(&file.file_path, &file.crs, &file.config, range)?;
+            Ok(SplitPart::Range(outline, Box::new(summary)))
+        }));
+    }
+
+    let state = Arc::new(Mutex::new(SplitFile {
+        remaining: jobs.len(),
+        outline: PartialOutline::default(),
+        summary: PointSummary::with_cell_size(occupied_cell_size(&config)),
+        extra: Map::new(),
+        full_header: Map::new(),
+        content_hash: None,
+        error: None,
+        on_done: Some(on_done),
+    }));
+    let file = Arc::new(SplitContext {
+        file_path,
+        crs: resolved.crs,
+        crs_warning: resolved.crs_warning,
+        config,
+        header,
+    });
+    for job in jobs {
+        let file = Arc::clone(&file);
+        let state = Arc::clone(&state);
+        pool.execute(move || {
+            let part = job(&file);
+            finish_split_job(&file, &state, part);
+        });
+    }
+}
 
-    // Convert the properties struct to a map
-    let properties_map = properties.to_map();
+/// Adds the result of a job to the state of a split file. The last job to finish builds
+/// the feature and hands it on.
+fn finish_split_job<F>(
+    file: &SplitContext,
+    state: &Mutex<SplitFile<F>>,
+    part: Result<SplitPart, LasPolyError>,
+) where
+    F: FnOnce(Result<Feature, LasPolyError>),
+{
+    let mut state = state.lock().unwrap();
+    match part {
+        Ok(SplitPart::Range(outline, summary)) => {
+            state.outline.merge(outline);
+            state.summary.merge(&summary);
+        }
+        Ok(SplitPart::Extra(extra)) => state.extra = extra,
+        Ok(SplitPart::FullHeader(full_header)) => state.full_header = full_header,
+        Ok(SplitPart::ContentHash(content_hash)) => state.content_hash = Some(content_hash),
+        Err(e) => {
+            state.error.get_or_insert(e);
+        }
+    }
+    state.remaining -= 1;
+    if state.remaining > 0 {
+        return;
+    }
 
-    let feature = Feature {
-        geometry: Some(geometry),
-        properties: Some(properties_map),
-        id: None,
-        bbox: None,
-        foreign_members: None,
+    let config = &file.config;
+    let header = &file.header;
+    let result = match state.error.take() {
+        Some(e) => Err(e),
+        None if config.tindex => Ok(tindex_feature(
+            &file.file_path,
+            &file.crs,
+            config.tindex_timestamps,
+            std::mem::take(&mut state.outline).into_value(),
+        )),
+        None => {
+            let outline = std::mem::take(&mut state.outline);
+            let mut properties = FeatureProperties::from_header(&file.file_path, header);
+            properties.crs_warning = file.crs_warning.clone();
+            properties.occupied_area_m2 = Proj::new_known_crs(&file.crs, "EPSG:4326", None)
+                .ok()
+                .and_then(|proj| occupied_area_m2(header, &state.summary, &proj));
+            properties.statistics =
+                statistics_properties(header, Some(&state.summary), &config.statistics);
+            properties.statistics.extend(acquisition_properties(
+                header,
+                state.summary.gps_time_range(),
+                config.gps_week,
+            ));
+            properties
+                .statistics
+                .extend(std::mem::take(&mut state.extra));
+            properties.full_header = std::mem::take(&mut state.full_header);
+            properties.content_hash = state.content_hash.take();
+            Ok(properties.into_feature(outline.into_value()))
+        }
     };
+    if let Some(on_done) = state.on_done.take() {
+        on_done(result);
+    }
+}
+
+/// Builds the partial outline and point summary of the points in `range`.
+fn outline_of_range(
+    file_path: &str,
+    crs: &str,
+    config: &ProcessConfig,
+    range: Range<u64>,
+) -> Result<(PartialOutline, PointSummary), LasPolyError> {
+    let to_epsg4326 = Proj::new_known_crs(crs, "EPSG:4326", None)?;
+    let mut reader = Reader::from_path(file_path)?;
+    reader.seek(range.start)?;
+    let mut summary = PointSummary::with_cell_size(occupied_cell_size(config));
+    let points = reader
+        .points()
+        .take((range.end - range.start) as usize)
+        .filter_map(Result::ok)
+        .filter(|p| summarize_point(&mut summary, p, &config.point_filter))
+        .map(|p| Coord { x: p.x, y: p.y });
+    let mut outline = PartialOutline::from_coords(points, &to_epsg4326);
+    outline.reduce();
+    Ok((outline, summary))
+}
+
+/// Adds a point to the statistics of `summary` and returns whether it passes the point
+/// filter. Statistics describe every point of the file, the occupied cells only the points
+/// kept for the outline.
+fn summarize_point(summary: &mut PointSummary, point: &las::Point, filter: &PointFilter) -> bool {
+    summary.add(point);
+    let keep = filter.matches(point);
+    if keep {
+        summary.add_cell(point);
+    }
+    keep
+}
 
-    Ok(feature)
+/// Builds the properties that need their own read of the points: the point spacing
+/// estimate and the header validation, if the config asks for them.
+fn extra_read_properties(
+    file_path: &str,
+    config: &ProcessConfig,
+) -> Result<Map<String, serde_json::Value>, LasPolyError> {
+    let mut map = Map::new();
+    if let Some(num_runs) = config.point_spacing {
+        if let Some(spacing) = estimate_point_spacing(file_path, num_runs, &config.point_filter)? {
+            map.extend(spacing.properties());
+        }
+    }
+    if let Some(tolerance) = config.validate {
+        map.extend(validation_properties(file_path, tolerance)?);
+    }
+    Ok(map)
+}
+
+/// Returns the cell size for occupied-cell densities. Sampled outlines only see some of
+/// the cells, so they do not get one.
+fn occupied_cell_size(config: &ProcessConfig) -> Option<f64> {
+    config
+        .density_cell_size
+        .filter(|_| config.sample_points.is_none())
+}
+
+/// Converts the occupied-cell area of `summary` to square metres.
+///
+/// Native areas are scaled by the ratio of the geodesic area of the header bounds to
+/// their planar area, which is close enough for tiles of a few kilometres.
+fn occupied_area_m2(header: &Header, summary: &PointSummary, to_epsg4326: &Proj) -> Option<f64> {
+    let occupied_area = summary.occupied_area()?;
+    let bounds = header.bounds();
+    let planar_area = (bounds.max.x - bounds.min.x) * (bounds.max.y - bounds.min.y);
+    if planar_area <= 0.0 {
+        return None;
+    }
+    let corners = [
+        (bounds.min.x, bounds.min.y),
+        (bounds.max.x, bounds.min.y),
+        (bounds.max.x, bounds.max.y),
+        (bounds.min.x, bounds.max.y),
+        (bounds.min.x, bounds.min.y),
+    ]
+    .into_iter()
+    .map(|corner| {
+        to_epsg4326
+            .convert(corner)
+            .ok()
+            .map(|(x, y)| Coord { x, y })
+    })
+    .collect::<Option<Vec<_>>>()?;
+    let geodesic_area = Polygon::new(LineString::from(corners), vec![]).geodesic_area_unsigned();
+    Some(occupied_area * geodesic_area / planar_area)
 }
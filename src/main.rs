@@ -15,8 +15,19 @@
 //! ```sh
 //! las_poly --folder_path "path/to/folder" --use_detailed_outline --group_by_folder --recurse
 //! ```
-use clap::Parser;
-use log::error;
+//!
+//! Files whose header bounds or counts do not match their points can be rewritten with
+//! the `repair` subcommand:
+//!
+//! ```sh
+//! las_poly repair <files>... [--output-dir <dir> | --in-place]
+//! ```
+//!
+//! As `repair` is a subcommand, a folder named `repair` is processed by giving its path
+//! another spelling, such as `./repair`.
+use clap::{Parser, Subcommand};
+use log::{error, info, warn};
+use std::path::Path;
 use std::process; // Add this line to import the logging macros
 
 /// Command-line arguments structure
@@ -25,17 +36,139 @@ use std::process; // Add this line to import the logging macros
     name = "las_poly",
     version = "1.0",
     author = "Jonathan Davidson <jrjddavidson@gmail.com>",
-    about = "Creates a geojson file with the outlines of LAS files found in the specified folder"
+    about = "Creates a geojson file with the outlines of LAS files found in the specified folder",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Args {
-    /// Path to the folder containing LAS files
-    folder_path: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the folder containing LAS files. A folder named repair must be given as ./repair, since repair is a subcommand.
+    #[arg(required = true)]
+    folder_path: Option<String>,
     name: Option<String>,
 
     /// Use a detailed outline. The default simple outline uses the header information for the data bounds, this option will read every point and create a convex hull around points.
     #[arg(short, long)]
     use_detailed_outline: bool,
 
+    /// Build the detailed outline from a sample of points instead of every point. Either a point count (100000) or a fraction of the file (0.05 or 5%).
+    #[arg(long, value_name = "BUDGET")]
+    sample_points: Option<las_poly::PointBudget>,
+
+    /// Split files with more points than this into ranges of about this many points, and read the ranges of the detailed outline in parallel.
+    #[arg(long, value_name = "POINTS")]
+    split_points: Option<u64>,
+
+    /// Only use points with these classifications for detailed outlines, e.g. 2,6.
+    #[arg(long, value_delimiter = ',', value_name = "CLASSES")]
+    include_classes: Vec<u8>,
+
+    /// Leave points with these classifications out of detailed outlines, e.g. 7,18 for noise.
+    #[arg(long, value_delimiter = ',', value_name = "CLASSES")]
+    exclude_classes: Vec<u8>,
+
+    /// Leave withheld points out of detailed outlines.
+    #[arg(long)]
+    exclude_withheld: bool,
+
+    /// Leave synthetic points out of detailed outlines.
+    #[arg(long)]
+    exclude_synthetic: bool,
+
+    /// Only use points with these return numbers for detailed outlines, e.g. 1.
+    #[arg(long, value_delimiter = ',', value_name = "RETURNS")]
+    return_numbers: Vec<u8>,
+
+    /// Only use the last return of each pulse for detailed outlines.
+    #[arg(long)]
+    last_returns_only: bool,
+
+    /// Leave points with an absolute scan angle (degrees) above this out of detailed outlines.
+    #[arg(long, value_name = "DEGREES")]
+    max_scan_angle: Option<f32>,
+
+    /// Leave points below this elevation out of detailed outlines.
+    #[arg(long, allow_negative_numbers = true)]
+    z_min: Option<f64>,
+
+    /// Leave points above this elevation out of detailed outlines.
+    #[arg(long, allow_negative_numbers = true)]
+    z_max: Option<f64>,
+
+    /// Remove isolated points before computing detailed outlines: density:<cell_size>[:<min_points>] drops points whose cell and its neighbours hold fewer than min_points (default 2) points, percentile:<percent> trims the given percentile off each end of X and Y.
+    #[arg(long, value_name = "METHOD")]
+    remove_outliers: Option<las_poly::OutlierFilter>,
+
+    /// Per-file statistics to add to the output properties, comma separated. Intensity and GPS time need --use-detailed-outline, and cover every point of the file regardless of the point filters. Sampled outlines only get the header values.
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "STATISTICS")]
+    statistics: Vec<las_poly::Statistic>,
+
+    /// Cell size, in native units, for the occupied-cell point density (cell_density) of detailed outlines.
+    #[arg(long, value_name = "SIZE")]
+    density_cell_size: Option<f64>,
+
+    /// Estimate the nominal point spacing (point_spacing, in native units) from nearest-neighbour distances in random runs of 1000 points, 20 runs or as many as given with --point-spacing=RUNS.
+    #[arg(long, value_name = "RUNS", num_args = 0..=1, require_equals = true, default_missing_value = "20")]
+    point_spacing: Option<usize>,
+
+    /// GPS week of files that store GPS week time, used for acquisition_start and acquisition_end. Inferred from the header date when not given.
+    #[arg(long, value_name = "WEEK")]
+    gps_week: Option<u32>,
+
+    /// Add the full header to the properties: point format, scales and offsets, GUID, global encoding, native bounds, VLR/EVLR counts and ids, and the extra bytes schema.
+    #[arg(long)]
+    full_header: bool,
+
+    /// Record a content hash of each file (content_hash), in the worker threads. File size and modification time are always recorded.
+    #[arg(long, value_enum, value_name = "ALGORITHM")]
+    hash: Option<las_poly::HashAlgorithm>,
+
+    /// Write groups of likely duplicate files (exact copies, or the same points re-compressed) to this GeoJSON file.
+    #[arg(long, value_name = "FILE")]
+    duplicates_file: Option<String>,
+
+    /// Read every point to check the header bounds and counts, flagging differences in validation_status. Bounds may differ by one scale step, or by a larger tolerance in native units given with --validate=TOLERANCE.
+    #[arg(long, value_name = "TOLERANCE", num_args = 0..=1, require_equals = true, default_missing_value = "0")]
+    validate: Option<f64>,
+
+    /// Output format. Taken from the extension of the output name when not given, GeoJSON otherwise. A GeoPackage has a footprints layer with every file and a merged layer, FlatGeobuf has a spatial index for bbox queries, a Shapefile comes with a .fields.json sidecar mapping properties to its 10 character field names, GeoParquet has bbox covering columns, KML and KMZ put the outlines in folders following the source directories, a QGIS virtual point cloud (vpc) opens all files as one layer, and sql is a script creating and loading a PostGIS table with psql.
+    #[arg(long, value_enum)]
+    format: Option<las_poly::OutputFormat>,
+
+    /// Colour KML and KMZ outlines by this property, such as date or point_density, in up to five classes of its ranked values.
+    #[arg(long, value_name = "PROPERTY")]
+    kml_style_by: Option<String>,
+
+    /// Also write each outline to this GeoJSON text sequence as soon as its file is done, flushed per feature, so an interrupted run keeps what it finished. A .geojsons name adds RFC 8142 record separators, other names are newline delimited.
+    #[arg(long, value_name = "FILE")]
+    stream_file: Option<String>,
+
+    /// Write the PDAL tindex schema instead of the usual properties: the file path in location and the WKT of its CRS in srs (the WKT of the file, or the WKT PROJ exports for its GeoTIFF or guessed CRS). Outlines are not merged, and a GeoPackage gets a single pdal layer.
+    #[arg(long, conflicts_with_all = ["group_by_folder", "merge_tiled", "merge_if_overlap"])]
+    tindex: bool,
+
+    /// Add the created and modified times of each file to the tindex schema.
+    #[arg(long, requires = "tindex")]
+    tindex_timestamps: bool,
+
+    /// Write a static STAC catalog to this directory: a Collection per folder and an Item per file, with the pointcloud and projection extensions and assets linking to the source files.
+    #[arg(long, value_name = "DIR")]
+    stac_dir: Option<String>,
+
+    /// Table the SQL output creates and loads, optionally as schema.table. Defaults to the lowercased name of the output file.
+    #[arg(long, value_name = "TABLE")]
+    sql_table: Option<String>,
+
+    /// Geometry encoding of the SQL output: hex EWKB, or WKT through ST_GeomFromText.
+    #[arg(long, value_enum, default_value_t = las_poly::SqlGeometry::Ewkb)]
+    sql_geometry: las_poly::SqlGeometry,
+
+    /// Make the SQL output update the rows of files already in the table, keyed on the source file path with a unique index, instead of adding rows again.
+    #[arg(long, conflicts_with_all = ["group_by_folder", "merge_tiled", "merge_if_overlap"])]
+    sql_upsert: bool,
+
     /// Group by folder - create one polygon outline per folder.
     #[arg(long)]
     group_by_folder: bool,
@@ -55,6 +188,82 @@ struct Args {
     /// Guess the CRS of the las file is the WKT or Geotiff header information is not present.
     #[arg(short, long)]
     guess_crs: bool,
+
+    /// Check the header bounds against the CRS area of use.
+    #[arg(long, value_enum, default_value_t = las_poly::CrsCheck::Warn)]
+    crs_check: las_poly::CrsCheck,
+
+    /// Replace a CRS that fails the --crs-check by a guessed CRS that passes it. The declared CRS is kept otherwise.
+    #[arg(long, requires = "guess_crs")]
+    reguess_crs: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Rewrite LAS/LAZ files with the header bounds, point count and return counts recomputed from the points. Repaired copies are written next to the files as <name>_repaired.<ext> unless --output-dir or --in-place is given.
+    Repair {
+        /// LAS/LAZ files to repair
+        #[arg(required = true)]
+        files: Vec<String>,
+
+        /// Write the repaired files to this folder, under their original names.
+        #[arg(long, value_name = "DIR", conflicts_with = "in_place")]
+        output_dir: Option<String>,
+
+        /// Replace the files with their repaired versions.
+        #[arg(long)]
+        in_place: bool,
+    },
+}
+
+/// Returns where the repaired version of a file is written.
+fn repaired_path(file: &str, output_dir: Option<&str>, in_place: bool) -> String {
+    let path = Path::new(file);
+    if in_place {
+        return file.to_string();
+    }
+    let repaired = match output_dir {
+        Some(dir) => Path::new(dir).join(path.file_name().unwrap_or_default()),
+        None => {
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            match path.extension() {
+                Some(extension) => path.with_file_name(format!(
+                    "{}_repaired.{}",
+                    stem,
+                    extension.to_string_lossy()
+                )),
+                None => path.with_file_name(format!("{}_repaired", stem)),
+            }
+        }
+    };
+    repaired.to_string_lossy().into_owned()
+}
+
+/// Repairs each file, returning false if any could not be repaired.
+fn repair(files: &[String], output_dir: Option<&str>, in_place: bool) -> bool {
+    if let Some(dir) = output_dir {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            error!("Error creating {}: {}", dir, e);
+            return false;
+        }
+    }
+    let mut success = true;
+    for file in files {
+        let output = repaired_path(file, output_dir, in_place);
+        match las_poly::repair_file(file, &output) {
+            Ok(issues) if issues.is_empty() => info!("{}: header was already correct", file),
+            Ok(issues) => {
+                for issue in issues {
+                    warn!("{}: fixed {}", file, issue);
+                }
+            }
+            Err(e) => {
+                error!("Error repairing {}: {}", file, e);
+                success = false;
+            }
+        }
+    }
+    success
 }
 
 fn main() {
@@ -62,8 +271,21 @@ fn main() {
     env_logger::Builder::from_default_env().init();
 
     let args = Args::parse();
+    if let Some(Command::Repair {
+        files,
+        output_dir,
+        in_place,
+    }) = args.command
+    {
+        if !repair(&files, output_dir.as_deref(), in_place) {
+            process::exit(1);
+        }
+        return;
+    }
+
     let config = las_poly::ProcessConfig {
-        folder_path: args.folder_path,
+        // Required unless a subcommand is given
+        folder_path: args.folder_path.unwrap_or_default(),
         use_detailed_outline: args.use_detailed_outline,
         group_by_folder: args.group_by_folder,
         merge_tiled: args.merge_tiled,
@@ -71,6 +293,41 @@ fn main() {
         recurse: args.recurse,
         guess_crs: args.guess_crs,
         output_file: args.name,
+        crs_check: args.crs_check,
+        reguess_crs: args.reguess_crs,
+        sample_points: args.sample_points,
+        split_points: args.split_points,
+        point_filter: las_poly::PointFilter {
+            include_classes: args.include_classes,
+            exclude_classes: args.exclude_classes,
+            exclude_withheld: args.exclude_withheld,
+            exclude_synthetic: args.exclude_synthetic,
+            return_numbers: args.return_numbers,
+            last_returns_only: args.last_returns_only,
+            max_scan_angle: args.max_scan_angle,
+            z_min: args.z_min,
+            z_max: args.z_max,
+        },
+        outlier_filter: args.remove_outliers,
+        statistics: args.statistics,
+        density_cell_size: args.density_cell_size,
+        point_spacing: args.point_spacing,
+        gps_week: args.gps_week,
+        full_header: args.full_header,
+        hash: args.hash,
+        duplicates_file: args.duplicates_file,
+        validate: args.validate,
+        format: args.format,
+        kml_style_by: args.kml_style_by,
+        stream_file: args.stream_file,
+        tindex: args.tindex,
+        tindex_timestamps: args.tindex_timestamps,
+        stac_dir: args.stac_dir,
+        sql: las_poly::SqlOptions {
+            table: args.sql_table,
+            geometry: args.sql_geometry,
+            upsert: args.sql_upsert,
+        },
     };
 
     if let Err(e) = las_poly::process_folder(config) {
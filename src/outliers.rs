@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use geo::Coord;
+
+/// Rejects isolated points before the detailed outline is computed.
+///
+/// Works in the native units of the file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutlierFilter {
+    /// Bins the points into square cells of `cell_size` and drops the points of any cell
+    /// whose 3x3 neighbourhood holds fewer than `min_points` points.
+    Density { cell_size: f64, min_points: usize },
+    /// Drops points below the `percent` percentile or above the `100 - percent`
+    /// percentile of either X or Y.
+    Percentile { percent: f64 },
+}
+
+impl FromStr for OutlierFilter {
+    type Err = String;
+
+    /// Parses `density:<cell_size>[:<min_points>]` or `percentile:<percent>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split(':');
+        let method = parts.next().unwrap_or_default();
+        let values: Vec<&str> = parts.collect();
+        let parse_f64 = |value: &str| {
+            value
+                .parse::<f64>()
+                .map_err(|_| format!("invalid number {:?} in {}", value, s))
+        };
+        match (method, values.as_slice()) {
+            ("density", [cell_size]) | ("density", [cell_size, _]) => {
+                let cell_size = parse_f64(cell_size)?;
+                if !(cell_size > 0.0 && cell_size.is_finite()) {
+                    return Err(format!("cell size must be positive: {}", s));
+                }
+                let min_points = match values.get(1) {
+                    Some(min_points) => min_points
+                        .parse()
+                        .map_err(|_| format!("invalid point count {:?} in {}", min_points, s))?,
+                    None => 2,
+                };
+                Ok(OutlierFilter::Density {
+                    cell_size,
+                    min_points,
+                })
+            }
+            ("percentile", [percent]) => {
+                let percent = parse_f64(percent)?;
+                if !(0.0..50.0).contains(&percent) {
+                    return Err(format!("percentile must be between 0 and 50: {}", s));
+                }
+                Ok(OutlierFilter::Percentile { percent })
+            }
+            _ => Err(format!(
+                "expected density:<cell_size>[:<min_points>] or percentile:<percent>, got {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Removes the outliers from `coords` and returns how many were removed.
+pub(crate) fn remove_outliers(coords: &mut Vec<Coord<f64>>, filter: &OutlierFilter) -> usize {
+    let before = coords.len();
+    match *filter {
+        OutlierFilter::Density {
+            cell_size,
+            min_points,
+        } => {
+            let cell_of = |c: &Coord<f64>| {
+                (
+                    (c.x / cell_size).floor() as i64,
+                    (c.y / cell_size).floor() as i64,
+                )
+            };
+            let mut counts: HashMap<(i64, i64), usize> = HashMap::new();
+            for c in coords.iter() {
+                *counts.entry(cell_of(c)).or_default() += 1;
+            }
+            let dense: HashMap<(i64, i64), bool> = counts
+                .keys()
+                .map(|&(cx, cy)| {
+                    let mut neighbourhood = 0;
+                    for dx in -1..=1 {
+                        for dy in -1..=1 {
+                            neighbourhood += counts.get(&(cx + dx, cy + dy)).unwrap_or(&0);
+                        }
+                    }
+                    ((cx, cy), neighbourhood >= min_points)
+                })
+                .collect();
+            coords.retain(|c| dense[&cell_of(c)]);
+        }
+        OutlierFilter::Percentile { percent } => {
+            if coords.is_empty() {
+                return 0;
+            }
+            let (x_min, x_max) = percentile_range(coords.iter().map(|c| c.x).collect(), percent);
+            let (y_min, y_max) = percentile_range(coords.iter().map(|c| c.y).collect(), percent);
+            coords.retain(|c| c.x >= x_min && c.x <= x_max && c.y >= y_min && c.y <= y_max);
+        }
+    }
+    before - coords.len()
+}
+
+/// Returns the values at the `percent` and `100 - percent` percentiles.
+fn percentile_range(mut values: Vec<f64>, percent: f64) -> (f64, f64) {
+    let last = values.len() - 1;
+    let low = ((last as f64) * percent / 100.0).floor() as usize;
+    let high = last - low;
+    let (_, low_value, _) = values.select_nth_unstable_by(low, f64::total_cmp);
+    let low_value = *low_value;
+    let (_, high_value, _) = values.select_nth_unstable_by(high, f64::total_cmp);
+    (low_value, *high_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_with_spike() -> Vec<Coord<f64>> {
+        let mut coords = Vec::new();
+        for i in 0..100 {
+            for j in 0..100 {
+                coords.push(Coord {
+                    x: i as f64,
+                    y: j as f64,
+                });
+            }
+        }
+        coords.push(Coord { x: 5000.0, y: 50.0 });
+        coords
+    }
+
+    #[test]
+    fn test_parse_outlier_filter() {
+        assert_eq!(
+            "density:10".parse(),
+            Ok(OutlierFilter::Density {
+                cell_size: 10.0,
+                min_points: 2
+            })
+        );
+        assert_eq!(
+            "density:5:20".parse(),
+            Ok(OutlierFilter::Density {
+                cell_size: 5.0,
+                min_points: 20
+            })
+        );
+        assert_eq!(
+            "percentile:0.1".parse(),
+            Ok(OutlierFilter::Percentile { percent: 0.1 })
+        );
+        assert!("density".parse::<OutlierFilter>().is_err());
+        assert!("percentile:60".parse::<OutlierFilter>().is_err());
+        assert!("density:NaN".parse::<OutlierFilter>().is_err());
+        assert!("density:inf".parse::<OutlierFilter>().is_err());
+    }
+
+    #[test]
+    fn test_density_removes_isolated_point() {
+        let mut coords = grid_with_spike();
+        let filter = OutlierFilter::Density {
+            cell_size: 10.0,
+            min_points: 2,
+        };
+        assert_eq!(remove_outliers(&mut coords, &filter), 1);
+        assert_eq!(coords.len(), 10_000);
+    }
+
+    #[test]
+    fn test_percentile_removes_extremes() {
+        let mut coords = grid_with_spike();
+        let filter = OutlierFilter::Percentile { percent: 0.01 };
+        assert_eq!(remove_outliers(&mut coords, &filter), 1);
+        assert!(coords.iter().all(|c| c.x < 100.0));
+    }
+}
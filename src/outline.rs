@@ -0,0 +1,99 @@
+use std::ops::Range;
+
+use geo::{ConvexHull, Coord, LineString, Polygon};
+use geojson::Value;
+use las::Header;
+use proj::Proj;
+
+/// Number of buffered coordinates after which they are reduced to their convex hull.
+const HULL_BATCH_SIZE: usize = 1_000_000;
+
+/// The convex hull of part of a file, in EPSG:4326.
+///
+/// Partial outlines of different point ranges can be merged, and the hull of the
+/// merged outline is the same as the hull of all of their points.
+#[derive(Default)]
+pub(crate) struct PartialOutline {
+    coords: Vec<Coord<f64>>,
+}
+
+impl PartialOutline {
+    /// Builds the hull of `coords`, transforming them from native units with `to_epsg4326`.
+    ///
+    /// Points are reduced to their hull in batches so memory use does not grow with the file size.
+    pub fn from_coords<I: Iterator<Item = Coord<f64>>>(coords: I, to_epsg4326: &Proj) -> Self {
+        let mut outline = PartialOutline::default();
+        for c in coords {
+            let (x, y) = to_epsg4326.convert((c.x, c.y)).unwrap_or((c.x, c.y));
+            outline.coords.push(Coord { x, y });
+            if outline.coords.len() >= HULL_BATCH_SIZE {
+                outline.reduce();
+            }
+        }
+        outline
+    }
+
+    /// Adds the points of another partial outline.
+    pub fn merge(&mut self, other: PartialOutline) {
+        self.coords.extend(other.coords);
+        self.reduce();
+    }
+
+    /// Returns the hull as a GeoJSON polygon.
+    pub fn into_value(self) -> Value {
+        let convex_hull = self.hull();
+
+        // Convert the convex_hull to GeoJSON
+        let exterior_coords: Vec<Vec<f64>> = convex_hull
+            .exterior()
+            .coords()
+            .map(|c| vec![c.x, c.y])
+            .collect();
+        Value::Polygon(vec![exterior_coords])
+    }
+
+    fn hull(&self) -> Polygon<f64> {
+        // Create a LineString from the points
+        let line_string = LineString::from(self.coords.clone());
+
+        // Compute the convex_hull
+        line_string.convex_hull()
+    }
+
+    /// Replaces the buffered coordinates by the corners of their hull.
+    pub fn reduce(&mut self) {
+        if self.coords.len() > 3 {
+            let mut coords = self.hull().exterior().clone().into_inner();
+            // Drop the closing coordinate, it is added again by the next hull
+            coords.pop();
+            self.coords = coords;
+        }
+    }
+}
+
+/// Splits the points of a file into ranges of about `range_points` points.
+///
+/// For LAZ files with fixed size chunks the ranges are rounded up to whole chunks, so
+/// each range starts at a chunk boundary and no chunk is decompressed twice.
+pub(crate) fn point_ranges(header: &Header, range_points: u64) -> Vec<Range<u64>> {
+    let total_points = header.number_of_points();
+    let mut range_points = range_points.max(1);
+    if header.point_format().is_compressed {
+        if let Some(vlr) = header
+            .laz_vlr()
+            .filter(|vlr| !vlr.uses_variable_size_chunks())
+        {
+            let chunk_size = u64::from(vlr.chunk_size()).max(1);
+            range_points = range_points.div_ceil(chunk_size).saturating_mul(chunk_size);
+        }
+    }
+
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < total_points {
+        let end = start.saturating_add(range_points).min(total_points);
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges
+}
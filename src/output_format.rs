@@ -0,0 +1,101 @@
+use std::path::Path;
+
+/// File formats the outlines can be written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// A GeoJSON FeatureCollection.
+    #[default]
+    #[value(name = "geojson")]
+    GeoJson,
+    /// A GeoPackage with a `footprints` layer of the file outlines and a `merged` layer.
+    #[value(name = "gpkg")]
+    GeoPackage,
+    /// FlatGeobuf with a packed Hilbert R-tree spatial index.
+    #[value(name = "fgb")]
+    FlatGeobuf,
+    /// An ESRI Shapefile, with a sidecar describing how properties were fitted into it.
+    #[value(name = "shp")]
+    Shapefile,
+    /// GeoParquet with WKB geometries and `bbox` covering columns.
+    #[value(name = "parquet")]
+    GeoParquet,
+    /// KML for Google Earth, with folders following the source directories.
+    #[value(name = "kml")]
+    Kml,
+    /// KML zipped into a KMZ archive.
+    #[value(name = "kmz")]
+    Kmz,
+    /// A QGIS virtual point cloud of the files, with one outline per file.
+    #[value(name = "vpc")]
+    Vpc,
+    /// A SQL script creating and loading a PostGIS table.
+    #[value(name = "sql")]
+    Sql,
+}
+
+impl OutputFormat {
+    /// Returns the format written to files with this extension, if any.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "geojson" | "json" => Some(OutputFormat::GeoJson),
+            "gpkg" => Some(OutputFormat::GeoPackage),
+            "fgb" => Some(OutputFormat::FlatGeobuf),
+            "shp" => Some(OutputFormat::Shapefile),
+            "parquet" | "geoparquet" => Some(OutputFormat::GeoParquet),
+            "kml" => Some(OutputFormat::Kml),
+            "kmz" => Some(OutputFormat::Kmz),
+            "vpc" => Some(OutputFormat::Vpc),
+            "sql" => Some(OutputFormat::Sql),
+            _ => None,
+        }
+    }
+
+    /// Returns the format to write: the given format, else the format of the output file
+    /// extension, else GeoJSON.
+    pub fn resolve(format: Option<Self>, output_file: Option<&str>) -> Self {
+        format
+            .or_else(|| {
+                output_file
+                    .and_then(|file| Path::new(file).extension())
+                    .and_then(|extension| Self::from_extension(&extension.to_string_lossy()))
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns the extension of the default output file name.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::GeoJson => "geojson",
+            OutputFormat::GeoPackage => "gpkg",
+            OutputFormat::FlatGeobuf => "fgb",
+            OutputFormat::Shapefile => "shp",
+            OutputFormat::GeoParquet => "parquet",
+            OutputFormat::Kml => "kml",
+            OutputFormat::Kmz => "kmz",
+            OutputFormat::Vpc => "vpc",
+            OutputFormat::Sql => "sql",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(OutputFormat::resolve(None, None), OutputFormat::GeoJson);
+        assert_eq!(
+            OutputFormat::resolve(None, Some("out/tiles.GPKG")),
+            OutputFormat::GeoPackage
+        );
+        assert_eq!(
+            OutputFormat::resolve(None, Some("tiles.txt")),
+            OutputFormat::GeoJson
+        );
+        assert_eq!(
+            OutputFormat::resolve(Some(OutputFormat::GeoPackage), Some("tiles.geojson")),
+            OutputFormat::GeoPackage
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use las::Point;
+
+/// Selects the points used for detailed outlines.
+///
+/// The default filter keeps every point. Filters only apply when points are read, so
+/// they have no effect on the header outline.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PointFilter {
+    /// Only keep points with these classifications. Empty keeps every classification.
+    pub include_classes: Vec<u8>,
+    /// Drop points with these classifications, e.g. 7 (low noise) and 18 (high noise).
+    pub exclude_classes: Vec<u8>,
+    /// Drop points flagged as withheld.
+    pub exclude_withheld: bool,
+    /// Drop points flagged as synthetic.
+    pub exclude_synthetic: bool,
+    /// Only keep points with these return numbers. Empty keeps every return.
+    pub return_numbers: Vec<u8>,
+    /// Only keep the last return of each pulse.
+    pub last_returns_only: bool,
+    /// Drop points with an absolute scan angle (in degrees) above this.
+    pub max_scan_angle: Option<f32>,
+    /// Drop points below this elevation, in native units.
+    pub z_min: Option<f64>,
+    /// Drop points above this elevation, in native units.
+    pub z_max: Option<f64>,
+}
+
+impl PointFilter {
+    /// Returns true if the filter keeps every point.
+    pub fn is_empty(&self) -> bool {
+        *self == PointFilter::default()
+    }
+
+    /// Returns true if `point` passes the filter.
+    pub fn matches(&self, point: &Point) -> bool {
+        let class = u8::from(point.classification);
+        if !self.include_classes.is_empty() && !self.include_classes.contains(&class) {
+            return false;
+        }
+        if self.exclude_classes.contains(&class) {
+            return false;
+        }
+        if (self.exclude_withheld && point.is_withheld)
+            || (self.exclude_synthetic && point.is_synthetic)
+        {
+            return false;
+        }
+        if !self.return_numbers.is_empty() && !self.return_numbers.contains(&point.return_number) {
+            return false;
+        }
+        if self.last_returns_only && point.return_number < point.number_of_returns {
+            return false;
+        }
+        if let Some(max_scan_angle) = self.max_scan_angle {
+            if point.scan_angle.abs() > max_scan_angle {
+                return false;
+            }
+        }
+        if self.z_min.is_some_and(|z_min| point.z < z_min)
+            || self.z_max.is_some_and(|z_max| point.z > z_max)
+        {
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use las::point::Classification;
+
+    fn point(class: u8) -> Point {
+        Point {
+            classification: Classification::new(class).unwrap(),
+            return_number: 1,
+            number_of_returns: 2,
+            scan_angle: -15.0,
+            z: 10.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_default_filter_keeps_everything() {
+        let filter = PointFilter::default();
+        assert!(filter.is_empty());
+        assert!(filter.matches(&point(7)));
+    }
+
+    #[test]
+    fn test_classification_filters() {
+        let filter = PointFilter {
+            exclude_classes: vec![7, 18],
+            ..Default::default()
+        };
+        assert!(!filter.matches(&point(7)));
+        assert!(filter.matches(&point(2)));
+
+        let filter = PointFilter {
+            include_classes: vec![2],
+            ..Default::default()
+        };
+        assert!(filter.matches(&point(2)));
+        assert!(!filter.matches(&point(6)));
+    }
+
+    #[test]
+    fn test_flag_and_return_filters() {
+        let mut withheld = point(2);
+        withheld.is_withheld = true;
+        let filter = PointFilter {
+            exclude_withheld: true,
+            ..Default::default()
+        };
+        assert!(!filter.matches(&withheld));
+
+        let filter = PointFilter {
+            last_returns_only: true,
+            ..Default::default()
+        };
+        assert!(!filter.matches(&point(2)));
+
+        let filter = PointFilter {
+            return_numbers: vec![1],
+            ..Default::default()
+        };
+        assert!(filter.matches(&point(2)));
+    }
+
+    #[test]
+    fn test_scan_angle_and_z_filters() {
+        let filter = PointFilter {
+            max_scan_angle: Some(10.0),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&point(2)));
+
+        let filter = PointFilter {
+            z_min: Some(0.0),
+            z_max: Some(5.0),
+            ..Default::default()
+        };
+        assert!(!filter.matches(&point(2)));
+    }
+}
@@ -0,0 +1,373 @@
+//! Writing the outlines as a SQL script for loading into PostGIS.
+//!
+//! The script is self-contained so it can be written where the database is out of reach
+//! and loaded later with `psql -f`. It runs in one transaction: the table is created if it
+//! does not exist, with an `id` key and a `geom` column in EPSG:4326, columns are added for
+//! the properties with types inferred as for the other table formats, a GiST index is
+//! created on `geom`, and the rows are inserted in batches. Re-loading a script into an
+//! existing table adds any new columns, and with `upsert` replaces the rows of files that
+//! are already there, keyed on `SourceFile` (or `location` for the tile index schema).
+
+use std::io::{self, Write};
+
+use geojson::{Feature, Value};
+use serde_json::Value as JsonValue;
+
+use crate::columns::{columns, text_value, Column, ColumnType};
+use crate::wkb::geometry_wkb;
+
+/// Number of rows in each `INSERT` statement.
+pub(crate) const BATCH_ROWS: usize = 500;
+
+const SRID: u32 = 4326;
+const EWKB_SRID_FLAG: u32 = 0x2000_0000;
+
+/// Columns of the table that do not come from the properties.
+const ID_COLUMN: &str = "id";
+const GEOMETRY_COLUMN: &str = "geom";
+
+/// How the geometries are written in the `INSERT` statements.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SqlGeometry {
+    /// Hex encoded EWKB, exact and fast to load.
+    #[default]
+    Ewkb,
+    /// WKT through `ST_GeomFromText`, easier to read and edit in the script.
+    Wkt,
+}
+
+/// Options of the SQL output.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SqlOptions {
+    /// Table to create and load, optionally schema qualified as `schema.table`. Defaults to
+    /// the lowercased name of the output file.
+    pub table: Option<String>,
+    /// Encoding of the geometries.
+    pub geometry: SqlGeometry,
+    /// Updates the rows of files already in the table instead of adding new rows, using a
+    /// unique index on the source file path.
+    pub upsert: bool,
+}
+
+/// Quotes an identifier, doubling any quotes in it.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Quotes a string literal. Backslashes are literal, the script turns on
+/// `standard_conforming_strings`.
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn sql_type(column_type: ColumnType) -> &'static str {
+    match column_type {
+        ColumnType::Integer => "bigint",
+        ColumnType::Real => "double precision",
+        ColumnType::Boolean => "boolean",
+        ColumnType::Text => "text",
+    }
+}
+
+fn sql_value(value: Option<&JsonValue>, column_type: ColumnType) -> String {
+    match (value, column_type) {
+        (None | Some(JsonValue::Null), _) => "NULL".to_string(),
+        (Some(JsonValue::Bool(b)), ColumnType::Boolean) => b.to_string().to_uppercase(),
+        (Some(JsonValue::Number(n)), ColumnType::Integer | ColumnType::Real) => n.to_string(),
+        (Some(value), _) => quote_literal(&text_value(value)),
+    }
+}
+
+/// Encodes a Polygon or MultiPolygon as hex EWKB with the SRID.
+fn ewkb_hex(value: &Value) -> Option<String> {
+    let wkb = geometry_wkb(value)?;
+    let geometry_type = u32::from_le_bytes(wkb[1..5].try_into().unwrap());
+    let mut ewkb = Vec::with_capacity(wkb.len() + 4);
+    ewkb.push(wkb[0]);
+    ewkb.extend_from_slice(&(geometry_type | EWKB_SRID_FLAG).to_le_bytes());
+    ewkb.extend_from_slice(&SRID.to_le_bytes());
+    ewkb.extend_from_slice(&wkb[5..]);
+    Some(ewkb.iter().map(|byte| format!("{:02X}", byte)).collect())
+}
+
+fn wkt_rings(rings: &[Vec<Vec<f64>>]) -> String {
+    let rings: Vec<String> = rings
+        .iter()
+        .map(|ring| {
+            let positions: Vec<String> = ring
+                .iter()
+                .map(|position| format!("{} {}", position[0], position[1]))
+                .collect();
+            format!("({})", positions.join(","))
+        })
+        .collect();
+    format!("({})", rings.join(","))
+}
+
+/// Encodes a Polygon or MultiPolygon as WKT.
+fn wkt(value: &Value) -> Option<String> {
+    match value {
+        Value::Polygon(rings) => Some(format!("POLYGON{}", wkt_rings(rings))),
+        Value::MultiPolygon(polygons) => {
+            let polygons: Vec<String> = polygons.iter().map(|rings| wkt_rings(rings)).collect();
+            Some(format!("MULTIPOLYGON({})", polygons.join(",")))
+        }
+        _ => None,
+    }
+}
+
+fn geometry_value(feature: &Feature, encoding: SqlGeometry) -> String {
+    let value = feature.geometry.as_ref().map(|geometry| &geometry.value);
+    match encoding {
+        SqlGeometry::Ewkb => value
+            .and_then(ewkb_hex)
+            .map(|hex| format!("'{}'::geometry", hex)),
+        SqlGeometry::Wkt => value
+            .and_then(wkt)
+            .map(|wkt| format!("ST_GeomFromText('{}', {})", wkt, SRID)),
+    }
+    .unwrap_or_else(|| "NULL".to_string())
+}
+
+/// Splits an optionally schema qualified table name into its quoted form and the bare
+/// table name, used to name its indexes.
+fn table_names(table: &str) -> (String, &str) {
+    match table.split_once('.') {
+        Some((schema, name)) => (
+            format!("{}.{}", quote_identifier(schema), quote_identifier(name)),
+            name,
+        ),
+        None => (quote_identifier(table), table),
+    }
+}
+
+/// Writes the SQL script loading the features into `table`, see the module documentation.
+/// Upserting needs a `SourceFile` or `location` property to key the rows on.
+pub(crate) fn write_sql<W: Write>(
+    writer: &mut W,
+    table: &str,
+    features: &[Feature],
+    options: &SqlOptions,
+    batch_rows: usize,
+) -> io::Result<()> {
+    let columns: Vec<Column> = columns(features)
+        .into_iter()
+        .filter(|column| column.name != ID_COLUMN && column.name != GEOMETRY_COLUMN)
+        .collect();
+    let key = if options.upsert {
+        let key = ["SourceFile", "location"]
+            .into_iter()
+            .find(|key| columns.iter().any(|column| column.name == *key))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "upserting needs a SourceFile or location property, outlines must not be merged",
+                )
+            })?;
+        Some(key)
+    } else {
+        None
+    };
+    let (table_sql, table_name) = table_names(table);
+
+    writeln!(
+        writer,
+        "-- Outlines written by las_poly {}, load with: psql -v ON_ERROR_STOP=1 -f <file>",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(writer, "SET client_encoding = 'UTF8';")?;
+    writeln!(writer, "SET standard_conforming_strings = on;")?;
+    writeln!(writer, "BEGIN;")?;
+    writeln!(
+        writer,
+        "CREATE TABLE IF NOT EXISTS {} (\n    {} bigserial PRIMARY KEY,\n    {} geometry(Geometry, {})\n);",
+        table_sql, ID_COLUMN, GEOMETRY_COLUMN, SRID
+    )?;
+    for column in &columns {
+        writeln!(
+            writer,
+            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {};",
+            table_sql,
+            quote_identifier(&column.name),
+            sql_type(column.column_type)
+        )?;
+    }
+    if let Some(key) = key {
+        writeln!(
+            writer,
+            "CREATE UNIQUE INDEX IF NOT EXISTS {} ON {} ({});",
+            quote_identifier(&format!("{}_{}_key", table_name, key)),
+            table_sql,
+            quote_identifier(key)
+        )?;
+    }
+    writeln!(
+        writer,
+        "CREATE INDEX IF NOT EXISTS {} ON {} USING GIST ({});",
+        quote_identifier(&format!("{}_{}_idx", table_name, GEOMETRY_COLUMN)),
+        table_sql,
+        GEOMETRY_COLUMN
+    )?;
+
+    let mut column_list: Vec<String> = columns
+        .iter()
+        .map(|column| quote_identifier(&column.name))
+        .collect();
+    column_list.push(GEOMETRY_COLUMN.to_string());
+    let conflict = key.map(|key| {
+        let updates: Vec<String> = column_list
+            .iter()
+            .map(|column| format!("{} = EXCLUDED.{}", column, column))
+            .collect();
+        format!(
+            "\nON CONFLICT ({}) DO UPDATE SET {}",
+            quote_identifier(key),
+            updates.join(", ")
+        )
+    });
+    for batch in features.chunks(batch_rows.max(1)) {
+        writeln!(
+            writer,
+            "INSERT INTO {} ({}) VALUES",
+            table_sql,
+            column_list.join(", ")
+        )?;
+        let rows: Vec<String> = batch
+            .iter()
+            .map(|feature| {
+                let properties = feature.properties.as_ref();
+                let mut values: Vec<String> = columns
+                    .iter()
+                    .map(|column| {
+                        sql_value(
+                            properties.and_then(|p| p.get(&column.name)),
+                            column.column_type,
+                        )
+                    })
+                    .collect();
+                values.push(geometry_value(feature, options.geometry));
+                format!("    ({})", values.join(", "))
+            })
+            .collect();
+        writeln!(
+            writer,
+            "{}{};",
+            rows.join(",\n"),
+            conflict.as_deref().unwrap_or_default()
+        )?;
+    }
+    writeln!(writer, "COMMIT;")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geojson::Geometry;
+    use serde_json::json;
+
+    fn feature(properties: JsonValue) -> Feature {
+        Feature {
+            geometry: Some(Geometry::new(Value::Polygon(vec![vec![
+                vec![0.0, 0.0],
+                vec![1.0, 0.0],
+                vec![1.0, 0.5],
+                vec![0.0, 0.0],
+            ]]))),
+            properties: properties.as_object().cloned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_ewkb_hex() {
+        let value = feature(json!({})).geometry.unwrap().value;
+        let hex = ewkb_hex(&value).unwrap();
+        // Little endian polygon with the SRID flag, SRID 4326, one ring of four points
+        assert!(hex.starts_with("0103000020E61000000100000004000000"));
+        assert_eq!(hex.len(), (geometry_wkb(&value).unwrap().len() + 4) * 2);
+    }
+
+    #[test]
+    fn test_wkt() {
+        let value = feature(json!({})).geometry.unwrap().value;
+        assert_eq!(wkt(&value).unwrap(), "POLYGON((0 0,1 0,1 0.5,0 0))");
+        let Value::Polygon(rings) = value else {
+            unreachable!()
+        };
+        assert_eq!(
+            wkt(&Value::MultiPolygon(vec![rings.clone(), rings])).unwrap(),
+            "MULTIPOLYGON(((0 0,1 0,1 0.5,0 0)),((0 0,1 0,1 0.5,0 0)))"
+        );
+    }
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(quote_identifier(r#"a"b"#), r#""a""b""#);
+        assert_eq!(quote_literal(r"it's C:\las"), r"'it''s C:\las'");
+        assert_eq!(
+            table_names("lidar.Tiles"),
+            (r#""lidar"."Tiles""#.to_string(), "Tiles")
+        );
+    }
+
+    #[test]
+    fn test_write_sql() {
+        let features: Vec<Feature> = (0..3)
+            .map(|i| {
+                feature(json!({
+                    "SourceFile": format!("tile_{}'s.las", i),
+                    "number_of_points": 100 + i,
+                    "point_density": 1.5,
+                    "has_rgb": i == 0,
+                    "codes": [1, 2],
+                }))
+            })
+            .collect();
+        let options = SqlOptions {
+            upsert: true,
+            ..Default::default()
+        };
+        let mut sql = Vec::new();
+        write_sql(&mut sql, "tiles", &features, &options, 2).unwrap();
+        let sql = String::from_utf8(sql).unwrap();
+        assert!(sql.contains(
+            "CREATE TABLE IF NOT EXISTS \"tiles\" (\n    id bigserial PRIMARY KEY,\n    geom geometry(Geometry, 4326)\n);"
+        ));
+        assert!(sql.contains(r#"ADD COLUMN IF NOT EXISTS "number_of_points" bigint;"#));
+        assert!(sql.contains(r#"ADD COLUMN IF NOT EXISTS "point_density" double precision;"#));
+        assert!(sql.contains(r#"ADD COLUMN IF NOT EXISTS "has_rgb" boolean;"#));
+        assert!(sql.contains(r#"ADD COLUMN IF NOT EXISTS "codes" text;"#));
+        assert!(sql.contains(
+            r#"CREATE INDEX IF NOT EXISTS "tiles_geom_idx" ON "tiles" USING GIST (geom);"#
+        ));
+        assert!(sql.contains(
+            r#"CREATE UNIQUE INDEX IF NOT EXISTS "tiles_SourceFile_key" ON "tiles" ("SourceFile");"#
+        ));
+        assert_eq!(sql.matches("INSERT INTO").count(), 2);
+        assert_eq!(
+            sql.matches("ON CONFLICT (\"SourceFile\") DO UPDATE")
+                .count(),
+            2
+        );
+        assert!(sql.contains("('tile_0''s.las', '[1,2]', TRUE, 100, 1.5, '0103000020E6100000"));
+        assert!(sql.trim_end().ends_with("COMMIT;"));
+
+        let options = SqlOptions {
+            geometry: SqlGeometry::Wkt,
+            ..Default::default()
+        };
+        let mut sql = Vec::new();
+        write_sql(&mut sql, "tiles", &features, &options, BATCH_ROWS).unwrap();
+        let sql = String::from_utf8(sql).unwrap();
+        assert!(!sql.contains("ON CONFLICT"));
+        assert!(sql.contains("ST_GeomFromText('POLYGON((0 0,1 0,1 0.5,0 0))', 4326));"));
+
+        let merged = [feature(json!({ "SourceFileDir": "/data" }))];
+        let options = SqlOptions {
+            upsert: true,
+            ..Default::default()
+        };
+        assert!(write_sql(&mut Vec::new(), "tiles", &merged, &options, BATCH_ROWS).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use las::{Reader, Writer};
+use log::info;
+
+use crate::validation::{header_from_points, header_issues};
+use crate::LasPolyError;
+
+/// Rewrites a LAS/LAZ file with the bounds, point count and return counts of its header
+/// recomputed from the points.
+///
+/// The points, VLRs and other header fields are copied unchanged. A file with an
+/// unreadable point, e.g. one whose header counts more points than it holds, is not
+/// repaired: the error is returned and no partial copy is left behind. When `output_path`
+/// is the same file as `file_path` the file is repaired in place, by writing a copy next
+/// to it and renaming the copy over the original once every point has been written.
+///
+/// The header is checked against the points before anything is written. A correct file
+/// is left untouched when repairing in place, and copied as is otherwise.
+///
+/// Returns the header problems that were fixed, or nothing if the header was correct.
+///
+/// # Examples
+///
+/// ```rust
+/// use las_poly::repair_file;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let temp_dir = tempfile::tempdir()?;
+///     let input = temp_dir.path().join("input.las");
+///     let mut writer = las::Writer::from_path(&input, Default::default())?;
+///     writer.write_point(Default::default())?;
+///     writer.close()?;
+///
+///     let output = temp_dir.path().join("repaired.las");
+///     let fixed = repair_file(input.to_str().unwrap(), output.to_str().unwrap())?;
+///     assert!(fixed.is_empty());
+///     Ok(())
+/// }
+/// ```
+pub fn repair_file(file_path: &str, output_path: &str) -> Result<Vec<String>, LasPolyError> {
+    let in_place = same_file(file_path, output_path);
+    let write_path = if in_place {
+        repairing_path(file_path)
+    } else {
+        PathBuf::from(output_path)
+    };
+
+    let mut reader = Reader::from_path(file_path)?;
+    let header = reader.header().clone();
+    let issues = header_issues(&header, &header_from_points(&mut reader)?, 0.0);
+    if issues.is_empty() {
+        if !in_place {
+            fs::copy(file_path, output_path)?;
+        }
+        info!("{} already has a correct header", file_path);
+        return Ok(issues);
+    }
+
+    reader.seek(0)?;
+    if let Err(e) = write_points(&mut reader, &write_path) {
+        // Leave no partial copy behind, in place the original is untouched
+        let _ = fs::remove_file(&write_path);
+        return Err(e);
+    }
+    // Close the original before replacing it
+    drop(reader);
+    if in_place {
+        fs::rename(&write_path, file_path)?;
+    }
+    info!(
+        "Repaired {} into {} ({} problems fixed)",
+        file_path,
+        output_path,
+        issues.len()
+    );
+    Ok(issues)
+}
+
+/// Copies the points to a new file at `write_path`, failing on the first unreadable
+/// point. The writer recomputes the bounds and counts of the header from the points.
+fn write_points(reader: &mut Reader, write_path: &Path) -> Result<(), LasPolyError> {
+    let mut writer = Writer::from_path(write_path, reader.header().clone())?;
+    for point in reader.points() {
+        writer.write_point(point?)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+/// Returns true if both paths name the same existing file, however they are spelled.
+fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Returns the path of the copy written while repairing a file in place. It keeps the
+/// extension, which decides whether the copy is compressed.
+fn repairing_path(file_path: &str) -> PathBuf {
+    let path = Path::new(file_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!(
+            "{}.repairing.{}",
+            stem,
+            extension.to_string_lossy()
+        )),
+        None => path.with_file_name(format!("{}.repairing", stem)),
+    }
+}
@@ -0,0 +1,161 @@
+//! Reading a subset of the points of a LAS/LAZ file.
+//!
+//! The sampled outline is the convex hull of the points returned by [`sample_points`].
+//! Because those points are a subset of the file, the sampled hull is always contained
+//! in the full hull: it can only under-estimate the footprint, never over-estimate it.
+//!
+//! # Error bound
+//!
+//! For `n` points spread uniformly over a convex footprint with `r` corners, the
+//! expected fraction of the full hull area missed by the hull of the sample is about
+//! `(2r / 3) * ln(n) / n` (Rényi & Sulanke). For a rectangular tile (`r = 4`) this is
+//! roughly 0.3% for 10 000 points and 0.03% for 100 000 points. The missed area sits in
+//! thin slivers along the edges, so the outline shrinks by a small fraction of the
+//! average spacing between sampled points rather than losing whole corners.
+//!
+//! LAZ files are sampled in whole chunks, so the points are clustered along the flight
+//! lines of the sampled chunks. The bound above then applies with `n` taken as the
+//! number of sampled chunks, and files with few chunks should be given a larger budget.
+
+use std::str::FromStr;
+
+use las::{Point, Reader};
+use log::debug;
+
+/// Length of the runs read from LAZ files that use variable sized chunks.
+const VARIABLE_CHUNK_RUN: u64 = 50_000;
+
+/// Fewest points read from a non-empty file, the points needed for a polygon outline.
+const MIN_SAMPLE_POINTS: u64 = 3;
+
+/// How many points to read when building a sampled outline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointBudget {
+    /// Read at most this many points.
+    Count(u64),
+    /// Read this fraction (between 0 and 1) of the points.
+    Fraction(f64),
+}
+
+impl PointBudget {
+    /// Returns the number of points to read from a file with `total_points` points, at
+    /// least three unless the file has fewer.
+    pub fn points_for(&self, total_points: u64) -> u64 {
+        let budget = match *self {
+            PointBudget::Count(count) => count,
+            PointBudget::Fraction(fraction) => {
+                (total_points as f64 * fraction.clamp(0.0, 1.0)).ceil() as u64
+            }
+        };
+        budget.max(MIN_SAMPLE_POINTS).min(total_points)
+    }
+}
+
+impl FromStr for PointBudget {
+    type Err = String;
+
+    /// Parses either a point count (`100000`) or a fraction (`0.05` or `5%`). Zero budgets
+    /// are rejected.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(percent) = s.strip_suffix('%') {
+            let percent: f64 = percent
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid percentage: {}", s))?;
+            if !(percent > 0.0 && percent <= 100.0) {
+                return Err(format!("percentage must be above 0 and at most 100: {}", s));
+            }
+            return Ok(PointBudget::Fraction(percent / 100.0));
+        }
+        if let Ok(count) = s.parse::<u64>() {
+            if count == 0 {
+                return Err(format!("point count must be above 0: {}", s));
+            }
+            return Ok(PointBudget::Count(count));
+        }
+        let fraction: f64 = s
+            .parse()
+            .map_err(|_| format!("expected a point count or a fraction: {}", s))?;
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(format!("fraction must be above 0 and at most 1: {}", s));
+        }
+        Ok(PointBudget::Fraction(fraction))
+    }
+}
+
+/// Reads about `budget` points spread evenly through the file.
+///
+/// Uncompressed files are read one point at a time at evenly spaced indices. LAZ files
+/// are read in whole chunks, evenly spaced through the chunk table, since seeking to a
+/// single point would decompress the chunk up to that point anyway.
+pub fn sample_points(reader: &mut Reader, budget: PointBudget) -> Result<Vec<Point>, las::Error> {
+    let total_points = reader.header().number_of_points();
+    let num_points = budget.points_for(total_points);
+    if num_points >= total_points {
+        return reader.points().collect();
+    }
+
+    if reader.header().point_format().is_compressed {
+        let run_length = reader
+            .header()
+            .laz_vlr()
+            .filter(|vlr| !vlr.uses_variable_size_chunks())
+            .map(|vlr| u64::from(vlr.chunk_size()))
+            .unwrap_or(VARIABLE_CHUNK_RUN)
+            .max(1);
+        sample_runs(reader, total_points, num_points, run_length)
+    } else {
+        sample_runs(reader, total_points, num_points, 1)
+    }
+}
+
+/// Reads runs of `run_length` points, starting at evenly spaced multiples of `run_length`.
+fn sample_runs(
+    reader: &mut Reader,
+    total_points: u64,
+    num_points: u64,
+    run_length: u64,
+) -> Result<Vec<Point>, las::Error> {
+    let total_runs = total_points.div_ceil(run_length);
+    let num_runs = num_points.div_ceil(run_length).min(total_runs);
+    debug!(
+        "Sampling {} runs of {} points out of {}",
+        num_runs, run_length, total_runs
+    );
+
+    let mut points = Vec::with_capacity((num_runs * run_length).min(total_points) as usize);
+    for i in 0..num_runs {
+        let run = i * total_runs / num_runs;
+        reader.seek(run * run_length)?;
+        let _ = reader.read_points_into(run_length, &mut points)?;
+    }
+    Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_point_budget() {
+        assert_eq!("1000".parse(), Ok(PointBudget::Count(1000)));
+        assert_eq!("0.25".parse(), Ok(PointBudget::Fraction(0.25)));
+        assert_eq!("5%".parse(), Ok(PointBudget::Fraction(0.05)));
+        assert!("1.5".parse::<PointBudget>().is_err());
+        assert!("lots".parse::<PointBudget>().is_err());
+        assert!("0".parse::<PointBudget>().is_err());
+        assert!("0%".parse::<PointBudget>().is_err());
+        assert!("0.0".parse::<PointBudget>().is_err());
+    }
+
+    #[test]
+    fn test_points_for() {
+        assert_eq!(PointBudget::Count(1000).points_for(500), 500);
+        assert_eq!(PointBudget::Count(100).points_for(500), 100);
+        assert_eq!(PointBudget::Fraction(0.1).points_for(500), 50);
+        assert_eq!(PointBudget::Fraction(0.001).points_for(500), 3);
+        assert_eq!(PointBudget::Count(1).points_for(500), 3);
+        assert_eq!(PointBudget::Count(1).points_for(2), 2);
+    }
+}
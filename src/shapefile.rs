@@ -0,0 +1,511 @@
+//! Writing the outlines to an ESRI Shapefile.
+//!
+//! The outlines are written as polygons to the `.shp` and its `.shx` index, the properties
+//! to a dBASE `.dbf` table, the CRS to a `.prj` and the UTF-8 encoding of the table to a
+//! `.cpg`. dBASE limits field names to 10 characters and text values to 254 bytes, so names
+//! and values are cut by fixed rules. The rules, the field each property went to, the
+//! full text of every cut value and the full value of every rounded real are written to a
+//! `.fields.json` sidecar.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use chrono::{Datelike, Utc};
+use geojson::{Feature, PolygonType, Value};
+use serde_json::{json, Value as JsonValue};
+
+use crate::columns::{columns, text_value, Column, ColumnType};
+
+const FILE_CODE: i32 = 9994;
+const VERSION: i32 = 1000;
+const SHAPE_NULL: i32 = 0;
+const SHAPE_POLYGON: i32 = 5;
+const HEADER_LEN: usize = 100;
+
+/// Longest dBASE field name.
+const MAX_NAME_LEN: usize = 10;
+/// Longest dBASE character field, in bytes.
+const MAX_TEXT_LEN: usize = 254;
+/// Longest dBASE numeric field, including the sign and decimal point.
+const MAX_NUMERIC_LEN: usize = 19;
+/// Most decimals kept in numeric fields.
+const MAX_DECIMALS: usize = 15;
+
+const WGS84_ESRI_WKT: &str = "GEOGCS[\"GCS_WGS_1984\",DATUM[\"D_WGS_1984\",SPHEROID[\"WGS_1984\",6378137.0,298.257223563]],PRIMEM[\"Greenwich\",0.0],UNIT[\"Degree\",0.0174532925199433]]";
+
+const RULES: [&str; 5] = [
+    "Field names are the property names with characters other than ASCII letters, digits and underscores replaced by underscores, cut to 10 characters.",
+    "A field name already in use, ignoring case, is replaced by the first 8 characters plus _1 to _9, then the first 7 characters plus _10 to _99, and so on with one character less for each further digit of the number.",
+    "Text values longer than 254 bytes are cut to 254 bytes at a character boundary. The full values are listed in truncated_values by record number, starting at 1.",
+    "Integers and reals are numeric fields, booleans logical fields (T or F) and arrays and objects JSON text.",
+    "Numeric fields are at most 19 characters wide, with at most 15 decimals. Reals that need more are rounded to fewer decimals, and a column whose values still do not fit is written as text. The full values of rounded reals are listed in rounded_values by record number, starting at 1.",
+];
+
+/// A dBASE field and the property it holds.
+struct DbfField {
+    property: String,
+    name: String,
+    column_type: ColumnType,
+    field_type: u8,
+    length: usize,
+    decimals: usize,
+}
+
+/// Writes features to a Shapefile at `output_file_name` with its `.shx`, `.dbf`, `.prj`,
+/// `.cpg` and `.fields.json` files next to it.
+///
+/// Outer rings are written clockwise and holes counterclockwise, as Shapefiles require.
+/// Features without a Polygon or MultiPolygon get a null shape.
+pub(crate) fn write_shapefile(output_file_name: &str, features: &[Feature]) -> io::Result<()> {
+    let path = Path::new(output_file_name);
+    let shapes: Vec<Option<Vec<Vec<[f64; 2]>>>> = features
+        .iter()
+        .map(|feature| {
+            feature
+                .geometry
+                .as_ref()
+                .and_then(|g| shape_rings(&g.value))
+        })
+        .collect();
+    write_shp(path, &shapes)?;
+
+    let columns = columns(features);
+    let fields = dbf_fields(features, &columns);
+    let (truncated, rounded) = write_dbf(&path.with_extension("dbf"), features, &fields)?;
+    fs::write(path.with_extension("prj"), WGS84_ESRI_WKT)?;
+    fs::write(path.with_extension("cpg"), "UTF-8")?;
+
+    let sidecar = json!({
+        "rules": RULES,
+        "fields": fields.iter().map(|field| json!({
+            "property": field.property,
+            "field": field.name,
+            "type": (field.field_type as char).to_string(),
+            "length": field.length,
+            "decimals": field.decimals,
+        })).collect::<Vec<_>>(),
+        "truncated_values": truncated,
+        "rounded_values": rounded,
+    });
+    fs::write(
+        path.with_extension("fields.json"),
+        serde_json::to_string_pretty(&sidecar)?,
+    )
+}
+
+/// Returns twice the signed area of a ring, positive for counterclockwise rings.
+fn signed_area(ring: &[[f64; 2]]) -> f64 {
+    ring.windows(2)
+        .map(|w| w[0][0] * w[1][1] - w[1][0] * w[0][1])
+        .sum()
+}
+
+/// Returns the rings of a Polygon or MultiPolygon, outer rings clockwise and holes
+/// counterclockwise.
+fn shape_rings(value: &Value) -> Option<Vec<Vec<[f64; 2]>>> {
+    let polygons: Vec<&PolygonType> = match value {
+        Value::Polygon(rings) => vec![rings],
+        Value::MultiPolygon(polygons) => polygons.iter().collect(),
+        _ => return None,
+    };
+    let mut shape = Vec::new();
+    for rings in polygons {
+        for (index, ring) in rings.iter().enumerate() {
+            let mut ring: Vec<[f64; 2]> = ring.iter().map(|p| [p[0], p[1]]).collect();
+            let clockwise = signed_area(&ring) < 0.0;
+            if clockwise != (index == 0) {
+                ring.reverse();
+            }
+            shape.push(ring);
+        }
+    }
+    Some(shape)
+}
+
+fn shape_bbox(rings: &[Vec<[f64; 2]>]) -> [f64; 4] {
+    rings.iter().flatten().fold(
+        [
+            f64::INFINITY,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NEG_INFINITY,
+        ],
+        |b, p| {
+            [
+                b[0].min(p[0]),
+                b[1].min(p[1]),
+                b[2].max(p[0]),
+                b[3].max(p[1]),
+            ]
+        },
+    )
+}
+
+/// Encodes the content of a shape record.
+fn shape_content(shape: &Option<Vec<Vec<[f64; 2]>>>) -> Vec<u8> {
+    let mut content = Vec::new();
+    let Some(rings) = shape else {
+        content.extend_from_slice(&SHAPE_NULL.to_le_bytes());
+        return content;
+    };
+    content.extend_from_slice(&SHAPE_POLYGON.to_le_bytes());
+    for value in shape_bbox(rings) {
+        content.extend_from_slice(&value.to_le_bytes());
+    }
+    let num_points: usize = rings.iter().map(|ring| ring.len()).sum();
+    content.extend_from_slice(&(rings.len() as i32).to_le_bytes());
+    content.extend_from_slice(&(num_points as i32).to_le_bytes());
+    let mut first = 0;
+    for ring in rings {
+        content.extend_from_slice(&(first as i32).to_le_bytes());
+        first += ring.len();
+    }
+    for point in rings.iter().flatten() {
+        content.extend_from_slice(&point[0].to_le_bytes());
+        content.extend_from_slice(&point[1].to_le_bytes());
+    }
+    content
+}
+
+/// Encodes the header shared by the `.shp` and `.shx`. Lengths are in 16 bit words.
+fn main_header(file_len: usize, bbox: [f64; 4]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(&FILE_CODE.to_be_bytes());
+    header.extend_from_slice(&[0; 20]);
+    header.extend_from_slice(&((file_len / 2) as i32).to_be_bytes());
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.extend_from_slice(&SHAPE_POLYGON.to_le_bytes());
+    for value in bbox {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    // Z and M ranges
+    header.extend_from_slice(&[0; 32]);
+    header
+}
+
+fn write_shp(path: &Path, shapes: &[Option<Vec<Vec<[f64; 2]>>>]) -> io::Result<()> {
+    let contents: Vec<Vec<u8>> = shapes.iter().map(shape_content).collect();
+    let rings: Vec<Vec<[f64; 2]>> = shapes.iter().flatten().flatten().cloned().collect();
+    let bbox = if rings.is_empty() {
+        [0.0; 4]
+    } else {
+        shape_bbox(&rings)
+    };
+    let shp_len = HEADER_LEN + contents.iter().map(|c| 8 + c.len()).sum::<usize>();
+    let shx_len = HEADER_LEN + 8 * contents.len();
+
+    let mut shp = BufWriter::new(File::create(path.with_extension("shp"))?);
+    let mut shx = BufWriter::new(File::create(path.with_extension("shx"))?);
+    shp.write_all(&main_header(shp_len, bbox))?;
+    shx.write_all(&main_header(shx_len, bbox))?;
+    let mut offset = HEADER_LEN;
+    for (index, content) in contents.iter().enumerate() {
+        shp.write_all(&(index as i32 + 1).to_be_bytes())?;
+        shp.write_all(&((content.len() / 2) as i32).to_be_bytes())?;
+        shp.write_all(content)?;
+        shx.write_all(&((offset / 2) as i32).to_be_bytes())?;
+        shx.write_all(&((content.len() / 2) as i32).to_be_bytes())?;
+        offset += 8 + content.len();
+    }
+    shp.flush()?;
+    shx.flush()
+}
+
+/// Maps a property name to a dBASE field name that is not in `used`, see `RULES`.
+fn field_name(property: &str, used: &mut HashSet<String>) -> String {
+    let cleaned: String = property
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let prefix = |len: usize| cleaned.chars().take(len).collect::<String>();
+    let name = prefix(MAX_NAME_LEN);
+    if used.insert(name.to_ascii_uppercase()) {
+        return name;
+    }
+    // The suffixes differ, so a free name is always found
+    (1..)
+        .map(|n| {
+            let suffix = format!("_{}", n);
+            format!("{}{}", prefix(MAX_NAME_LEN - suffix.len()), suffix)
+        })
+        .find(|candidate| used.insert(candidate.to_ascii_uppercase()))
+        .unwrap()
+}
+
+/// Formats a property for a field, before padding. Text is not yet cut.
+fn format_value(field: &DbfField, value: &JsonValue) -> String {
+    match field.column_type {
+        ColumnType::Integer => value.as_i64().unwrap_or_default().to_string(),
+        ColumnType::Real => format!("{:.*}", field.decimals, value.as_f64().unwrap_or_default()),
+        ColumnType::Boolean => if value.as_bool().unwrap_or_default() {
+            "T"
+        } else {
+            "F"
+        }
+        .to_string(),
+        ColumnType::Text => text_value(value),
+    }
+}
+
+/// Chooses the name, type and size of the field of each column from its values.
+fn dbf_fields(features: &[Feature], columns: &[Column]) -> Vec<DbfField> {
+    let mut used = HashSet::new();
+    columns
+        .iter()
+        .map(|column| {
+            let values: Vec<&JsonValue> = features
+                .iter()
+                .filter_map(|f| f.properties.as_ref()?.get(&column.name))
+                .filter(|value| !value.is_null())
+                .collect();
+            let (field_type, decimals) = match column.column_type {
+                ColumnType::Integer => (b'N', 0),
+                ColumnType::Real => {
+                    // The shortest representation never uses an exponent
+                    let decimals = values
+                        .iter()
+                        .filter_map(|v| v.as_f64())
+                        .map(|v| v.to_string().split('.').nth(1).map_or(0, str::len))
+                        .max()
+                        .unwrap_or(0);
+                    (b'N', decimals.min(MAX_DECIMALS))
+                }
+                ColumnType::Boolean => (b'L', 0),
+                ColumnType::Text => (b'C', 0),
+            };
+            let mut field = DbfField {
+                property: column.name.clone(),
+                name: field_name(&column.name, &mut used),
+                column_type: column.column_type,
+                field_type,
+                length: 0,
+                decimals,
+            };
+            field.length = field_length(&field, &values);
+            if field.field_type == b'N' && field.length > MAX_NUMERIC_LEN {
+                // Give up decimals first, then fall back to text
+                field.decimals = field
+                    .decimals
+                    .saturating_sub(field.length - MAX_NUMERIC_LEN);
+                field.length = field_length(&field, &values);
+                if field.length > MAX_NUMERIC_LEN {
+                    field.column_type = ColumnType::Text;
+                    field.field_type = b'C';
+                    field.decimals = 0;
+                    field.length = field_length(&field, &values);
+                }
+            }
+            field
+        })
+        .collect()
+}
+
+/// Returns the width a field needs for its values.
+fn field_length(field: &DbfField, values: &[&JsonValue]) -> usize {
+    values
+        .iter()
+        .map(|value| format_value(field, value).len())
+        .max()
+        .unwrap_or(1)
+        .clamp(1, MAX_TEXT_LEN)
+}
+
+/// Cuts text to at most `max_len` bytes without splitting a character.
+fn truncate(text: &str, max_len: usize) -> &str {
+    let mut end = max_len.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Writes the dBASE table, returning the text values that were cut and the reals that
+/// were rounded.
+fn write_dbf(
+    path: &Path,
+    features: &[Feature],
+    fields: &[DbfField],
+) -> io::Result<(Vec<JsonValue>, Vec<JsonValue>)> {
+    let record_len = 1 + fields.iter().map(|f| f.length).sum::<usize>();
+    let header_len = 32 + 32 * fields.len() + 1;
+    let today = Utc::now().date_naive();
+
+    let mut dbf = BufWriter::new(File::create(path)?);
+    dbf.write_all(&[
+        0x03,
+        (today.year() - 1900) as u8,
+        today.month() as u8,
+        today.day() as u8,
+    ])?;
+    dbf.write_all(&(features.len() as u32).to_le_bytes())?;
+    dbf.write_all(&(header_len as u16).to_le_bytes())?;
+    dbf.write_all(&(record_len as u16).to_le_bytes())?;
+    dbf.write_all(&[0; 20])?;
+    for field in fields {
+        let mut descriptor = [0u8; 32];
+        descriptor[..field.name.len()].copy_from_slice(field.name.as_bytes());
+        descriptor[11] = field.field_type;
+        descriptor[16] = field.length as u8;
+        descriptor[17] = field.decimals as u8;
+        dbf.write_all(&descriptor)?;
+    }
+    dbf.write_all(&[0x0D])?;
+
+    let mut truncated = Vec::new();
+    let mut rounded = Vec::new();
+    for (index, feature) in features.iter().enumerate() {
+        let mut record = Vec::with_capacity(record_len);
+        record.push(b' ');
+        for field in fields {
+            let value = feature
+                .properties
+                .as_ref()
+                .and_then(|properties| properties.get(&field.property))
+                .filter(|value| !value.is_null());
+            let text = match value {
+                Some(value) => format_value(field, value),
+                None if field.field_type == b'L' => "?".to_string(),
+                None => String::new(),
+            };
+            let cut = truncate(&text, field.length);
+            if cut.len() < text.len() {
+                truncated.push(json!({
+                    "record": index + 1,
+                    "field": field.name,
+                    "value": text,
+                }));
+            }
+            if let (ColumnType::Real, Some(value)) = (field.column_type, value) {
+                if text.parse::<f64>().ok() != value.as_f64() {
+                    rounded.push(json!({
+                        "record": index + 1,
+                        "field": field.name,
+                        "value": value,
+                    }));
+                }
+            }
+            // Numbers are right aligned, everything else left aligned
+            let padding = vec![b' '; field.length - cut.len()];
+            if field.field_type == b'N' {
+                record.extend_from_slice(&padding);
+                record.extend_from_slice(cut.as_bytes());
+            } else {
+                record.extend_from_slice(cut.as_bytes());
+                record.extend_from_slice(&padding);
+            }
+        }
+        dbf.write_all(&record)?;
+    }
+    dbf.write_all(&[0x1A])?;
+    dbf.flush()?;
+    Ok((truncated, rounded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_name() {
+        let mut used = HashSet::new();
+        let names: Vec<String> = [
+            "acquisition_start",
+            "acquisition_end",
+            "Acquisitio",
+            "z-min",
+            "id",
+        ]
+        .iter()
+        .map(|property| field_name(property, &mut used))
+        .collect();
+        assert_eq!(
+            names,
+            vec!["acquisitio", "acquisit_1", "Acquisit_2", "z_min", "id"]
+        );
+    }
+
+    #[test]
+    fn test_field_name_many_shared_prefixes() {
+        let mut used = HashSet::new();
+        let names: Vec<String> = (0..1100)
+            .map(|i| field_name(&format!("extra_bytes_{}", i), &mut used))
+            .collect();
+        assert_eq!(names[100], "extra__100");
+        assert_eq!(names[1099], "extra_1099");
+        assert!(names.iter().all(|name| name.len() <= MAX_NAME_LEN));
+        assert_eq!(used.len(), names.len());
+    }
+
+    #[test]
+    fn test_numeric_field_width() {
+        let features: Vec<Feature> = [
+            serde_json::json!({"small": 1.5, "precise": 1234567890.5, "big": i64::MIN}),
+            serde_json::json!({"small": 2, "precise": 0.123456789012345, "big": 1}),
+        ]
+        .into_iter()
+        .map(|properties| Feature {
+            properties: properties.as_object().cloned(),
+            ..Default::default()
+        })
+        .collect();
+        let fields = dbf_fields(&features, &columns(&features));
+        let field = |name: &str| fields.iter().find(|f| f.property == name).unwrap();
+        assert_eq!(
+            (field("small").field_type, field("small").length),
+            (b'N', 3)
+        );
+        // 10 digits, the point and 15 decimals are 26 characters, 8 decimals fit
+        assert_eq!(field("precise").field_type, b'N');
+        assert_eq!(
+            (field("precise").length, field("precise").decimals),
+            (MAX_NUMERIC_LEN, 8)
+        );
+        // i64::MIN needs 20 characters
+        assert_eq!(field("big").field_type, b'C');
+        assert_eq!(field("big").length, 20);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let (truncated, rounded) =
+            write_dbf(&temp_dir.path().join("tiles.dbf"), &features, &fields).unwrap();
+        assert!(truncated.is_empty());
+        assert_eq!(
+            rounded,
+            vec![json!({"record": 2, "field": "precise", "value": 0.123456789012345})]
+        );
+    }
+
+    #[test]
+    fn test_shape_rings_orientation() {
+        let counterclockwise = vec![
+            vec![0.0, 0.0],
+            vec![1.0, 0.0],
+            vec![1.0, 1.0],
+            vec![0.0, 0.0],
+        ];
+        let hole = vec![
+            vec![0.2, 0.1],
+            vec![0.8, 0.7],
+            vec![0.8, 0.1],
+            vec![0.2, 0.1],
+        ];
+        let rings = shape_rings(&Value::Polygon(vec![counterclockwise, hole])).unwrap();
+        assert!(signed_area(&rings[0]) < 0.0);
+        assert!(signed_area(&rings[1]) > 0.0);
+        assert_eq!(rings[0][1], [1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("abc", 2), "ab");
+        assert_eq!(truncate("aé", 2), "a");
+        assert_eq!(truncate("abc", 254), "abc");
+    }
+}
@@ -0,0 +1,172 @@
+//! Estimating the nominal point spacing (NPS) of a file.
+//!
+//! Runs of consecutive points are read at random positions with
+//! [`grab_random_runs`]. Points are stored roughly in acquisition order, so a run holds
+//! a stretch of neighbouring scan lines, and the nearest neighbour of each point in the
+//! middle half of a run is searched for within the run. The points at the ends of a run
+//! are only used as neighbours, as their own neighbours may lie outside it.
+//!
+//! Only first returns are used, since NPS is specified for first returns, and points
+//! that repeat the XY position of their neighbour are skipped. Distances are in the
+//! native units of the file.
+
+use geo::Coord;
+use las::Reader;
+use serde_json::{Map, Value};
+
+use crate::crs_utils::{grab_random_runs, CrsError};
+use crate::PointFilter;
+
+/// Number of consecutive points in each run.
+const RUN_LENGTH: usize = 1000;
+
+/// Nearest-neighbour distance statistics of a file.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct PointSpacing {
+    /// Mean nearest-neighbour distance, the estimated NPS.
+    pub mean: f64,
+    pub p05: f64,
+    pub p50: f64,
+    pub p95: f64,
+    /// Number of distances the estimate is based on.
+    pub samples: usize,
+}
+
+impl PointSpacing {
+    /// Summarises nearest-neighbour distances. Returns `None` if there are none.
+    pub fn from_distances(mut distances: Vec<f64>) -> Option<Self> {
+        if distances.is_empty() {
+            return None;
+        }
+        distances.sort_by(f64::total_cmp);
+        let percentile = |percent: f64| {
+            let index = ((distances.len() - 1) as f64 * percent / 100.0).round() as usize;
+            distances[index]
+        };
+        Some(PointSpacing {
+            mean: distances.iter().sum::<f64>() / distances.len() as f64,
+            p05: percentile(5.0),
+            p50: percentile(50.0),
+            p95: percentile(95.0),
+            samples: distances.len(),
+        })
+    }
+
+    /// Returns the `point_spacing` properties.
+    pub fn properties(&self) -> Map<String, Value> {
+        let mut map = Map::new();
+        map.insert("point_spacing".to_string(), self.mean.into());
+        map.insert("point_spacing_p05".to_string(), self.p05.into());
+        map.insert("point_spacing_p50".to_string(), self.p50.into());
+        map.insert("point_spacing_p95".to_string(), self.p95.into());
+        map.insert("point_spacing_samples".to_string(), self.samples.into());
+        map
+    }
+}
+
+/// Estimates the point spacing of a file from `num_runs` random runs of points that pass
+/// `point_filter`.
+pub(crate) fn estimate_point_spacing(
+    file_path: &str,
+    num_runs: usize,
+    point_filter: &PointFilter,
+) -> Result<Option<PointSpacing>, CrsError> {
+    let reader = Reader::from_path(file_path)?;
+    let mut distances = Vec::new();
+    for run in grab_random_runs(reader, num_runs, RUN_LENGTH)? {
+        let coords: Vec<Coord<f64>> = run
+            .into_iter()
+            .filter(|p| p.return_number <= 1 && point_filter.matches(p))
+            .map(|p| Coord { x: p.x, y: p.y })
+            .collect();
+        distances.extend(nearest_neighbour_distances(&coords));
+    }
+    Ok(PointSpacing::from_distances(distances))
+}
+
+/// Returns the distance from each point in the middle half of `coords` to its nearest
+/// neighbour in `coords`, skipping points without a neighbour at a non-zero distance.
+pub(crate) fn nearest_neighbour_distances(coords: &[Coord<f64>]) -> Vec<f64> {
+    let mut by_x: Vec<usize> = (0..coords.len()).collect();
+    by_x.sort_by(|&a, &b| coords[a].x.total_cmp(&coords[b].x));
+    let mut rank = vec![0; coords.len()];
+    for (position, &index) in by_x.iter().enumerate() {
+        rank[index] = position;
+    }
+
+    let middle = coords.len() / 4..coords.len() - coords.len() / 4;
+    let mut distances = Vec::with_capacity(middle.len());
+    for index in middle {
+        let point = coords[index];
+        let mut best = f64::INFINITY;
+        // Walk outwards in X order until the X gap alone exceeds the best distance
+        let mut check = |other: usize| {
+            let dx = coords[other].x - point.x;
+            if dx * dx >= best {
+                return false;
+            }
+            let dy = coords[other].y - point.y;
+            let squared = dx * dx + dy * dy;
+            if squared > 0.0 && squared < best {
+                best = squared;
+            }
+            true
+        };
+        for &other in by_x[rank[index] + 1..].iter() {
+            if !check(other) {
+                break;
+            }
+        }
+        for &other in by_x[..rank[index]].iter().rev() {
+            if !check(other) {
+                break;
+            }
+        }
+        if best.is_finite() {
+            distances.push(best.sqrt());
+        }
+    }
+    distances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_neighbour_distances_on_grid() {
+        let coords: Vec<Coord<f64>> = (0..20)
+            .flat_map(|i| {
+                (0..20).map(move |j| Coord {
+                    x: i as f64 * 2.0,
+                    y: j as f64 * 2.0,
+                })
+            })
+            .collect();
+        let distances = nearest_neighbour_distances(&coords);
+        assert_eq!(distances.len(), 200);
+        assert!(distances.iter().all(|&d| d == 2.0));
+    }
+
+    #[test]
+    fn test_repeated_positions_are_skipped() {
+        let coords = vec![
+            Coord { x: 0.0, y: 0.0 },
+            Coord { x: 0.0, y: 0.0 },
+            Coord { x: 3.0, y: 4.0 },
+            Coord { x: 0.0, y: 0.0 },
+        ];
+        assert_eq!(nearest_neighbour_distances(&coords), vec![5.0, 5.0]);
+    }
+
+    #[test]
+    fn test_spacing_percentiles() {
+        let spacing = PointSpacing::from_distances((1..=101).map(f64::from).collect()).unwrap();
+        assert_eq!(spacing.mean, 51.0);
+        assert_eq!(spacing.p05, 6.0);
+        assert_eq!(spacing.p50, 51.0);
+        assert_eq!(spacing.p95, 96.0);
+        assert_eq!(spacing.samples, 101);
+        assert_eq!(PointSpacing::from_distances(Vec::new()), None);
+    }
+}
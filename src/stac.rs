@@ -0,0 +1,544 @@
+//! Writing the outlines as a static STAC catalog.
+//!
+//! The catalog is self-contained, with relative links and no `self` links, laid out as
+//! STAC recommends:
+//!
+//! ```text
+//! catalog.json
+//! <collection>/collection.json
+//! <collection>/<item>/<item>.json
+//! ```
+//!
+//! Each source directory becomes a Collection and each file an Item carrying the
+//! properties collected for it, the pointcloud extension (`pc:count`, `pc:type`,
+//! `pc:encoding`, `pc:schemas` and `pc:statistics`) and the projection extension
+//! (`proj:epsg`, `proj:bbox` and `proj:geometry` in the native CRS). The data asset of an
+//! Item links to the absolute path of the source file.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use geojson::Feature;
+use las::{Header, Reader};
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::crs_utils::{extract_crs, extract_crs_from_geotiff, Crs};
+use crate::header_properties::extra_bytes_descriptors;
+use crate::kml::directory_components;
+use crate::wkb::geometry_bbox;
+use crate::LasPolyError;
+
+pub(crate) const STAC_VERSION: &str = "1.0.0";
+pub(crate) const POINTCLOUD_EXTENSION: &str =
+    "https://stac-extensions.github.io/pointcloud/v1.0.0/schema.json";
+pub(crate) const PROJECTION_EXTENSION: &str =
+    "https://stac-extensions.github.io/projection/v1.1.0/schema.json";
+
+/// Makes an id safe to use as a file name, keeping letters, digits, `-`, `_` and `.`.
+fn sanitize_id(name: &str) -> String {
+    let id: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if id.is_empty() {
+        "_".to_string()
+    } else {
+        id
+    }
+}
+
+/// Returns `id`, or `id` with the first free `-2`, `-3`, ... suffix if it is taken.
+fn unique_id(id: String, taken: &mut HashSet<String>) -> String {
+    let mut unique = id.clone();
+    let mut n = 2;
+    while !taken.insert(unique.to_lowercase()) {
+        unique = format!("{}-{}", id, n);
+        n += 1;
+    }
+    unique
+}
+
+/// Reads the EPSG code of a CRS given as `EPSG:<code>` or as WKT, where it is the
+/// `AUTHORITY` (WKT 1) or `ID` (WKT 2) of the outermost CRS.
+pub(crate) fn epsg_code(crs: &str) -> Option<u32> {
+    if let Some(code) = crs.trim().strip_prefix("EPSG:") {
+        return code.parse().ok();
+    }
+    let mut depth = 0;
+    let mut code = None;
+    for (i, c) in crs.char_indices() {
+        match c {
+            '[' | '(' => depth += 1,
+            ']' | ')' => depth -= 1,
+            ',' if depth == 1 => {
+                let rest = crs[i + 1..].trim_start();
+                let authority = rest
+                    .strip_prefix("AUTHORITY[")
+                    .or_else(|| rest.strip_prefix("ID["));
+                if let Some(authority) = authority {
+                    let mut parts = authority.splitn(2, ',');
+                    let name = parts.next().unwrap_or_default().trim().trim_matches('"');
+                    let value = parts.next().unwrap_or_default();
+                    let value = value.split([']', ',']).next();
+                    if name.eq_ignore_ascii_case("EPSG") {
+                        code = value.and_then(|value| value.trim().trim_matches('"').parse().ok());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    code
+}
+
+/// Returns the CRS stored in a file, as WKT or as `EPSG:<code>`.
+pub(crate) fn file_crs(file_path: &str) -> Option<String> {
+    match extract_crs(file_path).ok()?? {
+        Crs::Wkt(wkt) => Some(wkt),
+        Crs::GeoTiff(geo_key_directory, geo_double_params, geo_ascii_params) => {
+            extract_crs_from_geotiff(
+                &geo_key_directory,
+                geo_double_params.as_deref(),
+                geo_ascii_params.as_deref(),
+            )
+            .ok()
+        }
+    }
+}
+
+/// Describes the dimensions of the point records of a file, for `pc:schemas`.
+pub(crate) fn point_schemas(header: &Header) -> Vec<JsonValue> {
+    let format = header.point_format();
+    let mut schemas = vec![
+        ("X", 4, "signed"),
+        ("Y", 4, "signed"),
+        ("Z", 4, "signed"),
+        ("Intensity", 2, "unsigned"),
+        ("ReturnNumber", 1, "unsigned"),
+        ("NumberOfReturns", 1, "unsigned"),
+    ];
+    if format.is_extended {
+        schemas.extend([
+            ("ClassFlags", 1, "unsigned"),
+            ("ScanChannel", 1, "unsigned"),
+        ]);
+    }
+    schemas.extend([
+        ("ScanDirectionFlag", 1, "unsigned"),
+        ("EdgeOfFlightLine", 1, "unsigned"),
+        ("Classification", 1, "unsigned"),
+    ]);
+    if format.is_extended {
+        schemas.extend([("UserData", 1, "unsigned"), ("ScanAngle", 2, "signed")]);
+    } else {
+        schemas.extend([("ScanAngleRank", 1, "signed"), ("UserData", 1, "unsigned")]);
+    }
+    schemas.push(("PointSourceId", 2, "unsigned"));
+    if format.has_gps_time {
+        schemas.push(("GpsTime", 8, "floating"));
+    }
+    if format.has_color {
+        schemas.extend([
+            ("Red", 2, "unsigned"),
+            ("Green", 2, "unsigned"),
+            ("Blue", 2, "unsigned"),
+        ]);
+    }
+    if format.has_nir {
+        schemas.push(("Infrared", 2, "unsigned"));
+    }
+    if format.has_waveform {
+        schemas.extend([
+            ("WavePacketDescriptorIndex", 1, "unsigned"),
+            ("ByteOffsetToWaveformData", 8, "unsigned"),
+            ("WaveformPacketSize", 4, "unsigned"),
+            ("ReturnPointWaveformLocation", 4, "floating"),
+            ("Xt", 4, "floating"),
+            ("Yt", 4, "floating"),
+            ("Zt", 4, "floating"),
+        ]);
+    }
+    let mut schemas: Vec<JsonValue> = schemas
+        .into_iter()
+        .map(|(name, size, kind)| json!({ "name": name, "size": size, "type": kind }))
+        .collect();
+    for (name, data_type, options) in extra_bytes_descriptors(header) {
+        let (size, kind) = match data_type {
+            0 => (options, "unsigned"),
+            // Unsigned and signed integers of 1, 2, 4 and 8 bytes
+            1..=8 => {
+                let kind = if data_type % 2 == 1 {
+                    "unsigned"
+                } else {
+                    "signed"
+                };
+                (1 << ((data_type - 1) / 2), kind)
+            }
+            9 => (4, "floating"),
+            10 => (8, "floating"),
+            _ => continue,
+        };
+        schemas.push(json!({ "name": name, "size": size, "type": kind }));
+    }
+    schemas
+}
+
+/// Builds `pc:statistics` from the header bounds and any statistics in the properties.
+fn point_statistics(header: &Header, properties: &Map<String, JsonValue>) -> Vec<JsonValue> {
+    let bounds = header.bounds();
+    let mut statistics = vec![
+        json!({ "name": "X", "minimum": bounds.min.x, "maximum": bounds.max.x }),
+        json!({ "name": "Y", "minimum": bounds.min.y, "maximum": bounds.max.y }),
+    ];
+    let mut z = json!({ "name": "Z", "minimum": bounds.min.z, "maximum": bounds.max.z });
+    for (property, field) in [
+        ("z_min", "minimum"),
+        ("z_max", "maximum"),
+        ("z_mean", "average"),
+    ] {
+        if let Some(value) = properties.get(property) {
+            z[field] = value.clone();
+        }
+    }
+    statistics.push(z);
+    for (name, prefix) in [("Intensity", "intensity"), ("GpsTime", "gps_time")] {
+        let min = properties.get(&format!("{}_min", prefix));
+        let max = properties.get(&format!("{}_max", prefix));
+        if let (Some(min), Some(max)) = (min, max) {
+            statistics.push(json!({ "name": name, "minimum": min, "maximum": max }));
+        }
+    }
+    statistics
+}
+
+/// Returns the start and end time of a file: the acquisition times when known, else the
+/// day of the header date, else the modification time of the file.
+pub(crate) fn time_range(properties: &Map<String, JsonValue>) -> Option<(String, String)> {
+    let text = |key: &str| properties.get(key).and_then(JsonValue::as_str);
+    if let (Some(start), Some(end)) = (text("acquisition_start"), text("acquisition_end")) {
+        return Some((start.to_string(), end.to_string()));
+    }
+    if let Some(date) = text("date") {
+        return Some((format!("{}T00:00:00Z", date), format!("{}T23:59:59Z", date)));
+    }
+    text("modified").map(|modified| (modified.to_string(), modified.to_string()))
+}
+
+/// Sets `datetime`, or `start_datetime` and `end_datetime` for a range of times.
+pub(crate) fn insert_datetime(
+    properties: &mut Map<String, JsonValue>,
+    time_range: Option<&(String, String)>,
+) {
+    match time_range {
+        Some((start, end)) if start != end => {
+            properties.insert("datetime".to_string(), JsonValue::Null);
+            properties.insert("start_datetime".to_string(), start.clone().into());
+            properties.insert("end_datetime".to_string(), end.clone().into());
+        }
+        Some((start, _)) => {
+            properties.insert("datetime".to_string(), start.clone().into());
+        }
+        None => {
+            properties.insert("datetime".to_string(), JsonValue::Null);
+        }
+    }
+}
+
+/// A file and the Item written for it.
+struct StacItem {
+    id: String,
+    item: JsonValue,
+    bbox: Option<[f64; 4]>,
+    time_range: Option<(String, String)>,
+}
+
+fn stac_item(
+    feature: &Feature,
+    file_path: &str,
+    id: String,
+    collection_id: &str,
+) -> Result<StacItem, LasPolyError> {
+    let reader = Reader::from_path(file_path)?;
+    let header = reader.header();
+    let mut properties = feature.properties.clone().unwrap_or_default();
+    let time_range = time_range(&properties);
+    insert_datetime(&mut properties, time_range.as_ref());
+
+    let compressed = header.point_format().is_compressed;
+    properties.insert("pc:count".to_string(), header.number_of_points().into());
+    properties.insert("pc:type".to_string(), "lidar".into());
+    let encoding = if compressed { "laszip" } else { "binary" };
+    properties.insert("pc:encoding".to_string(), encoding.into());
+    properties.insert("pc:schemas".to_string(), point_schemas(header).into());
+    properties.insert(
+        "pc:statistics".to_string(),
+        point_statistics(header, &properties).into(),
+    );
+    if let Some(density) = properties.get("point_density").cloned() {
+        properties.insert("pc:density".to_string(), density);
+    }
+
+    let bounds = header.bounds();
+    let epsg = file_crs(file_path).as_deref().and_then(epsg_code);
+    properties.insert("proj:epsg".to_string(), epsg.into());
+    properties.insert(
+        "proj:bbox".to_string(),
+        json!([bounds.min.x, bounds.min.y, bounds.max.x, bounds.max.y]),
+    );
+    properties.insert(
+        "proj:geometry".to_string(),
+        json!({
+            "type": "Polygon",
+            "coordinates": [[
+                [bounds.min.x, bounds.min.y],
+                [bounds.max.x, bounds.min.y],
+                [bounds.max.x, bounds.max.y],
+                [bounds.min.x, bounds.max.y],
+                [bounds.min.x, bounds.min.y],
+            ]],
+        }),
+    );
+
+    let href = fs::canonicalize(file_path)
+        .unwrap_or_else(|_| PathBuf::from(file_path))
+        .to_string_lossy()
+        .into_owned();
+    let media_type = if compressed {
+        "application/vnd.laszip"
+    } else {
+        "application/vnd.las"
+    };
+    let title = Path::new(file_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    let geometry = feature.geometry.as_ref().map(|geometry| &geometry.value);
+    let bbox = geometry.and_then(geometry_bbox);
+    let item = json!({
+        "type": "Feature",
+        "stac_version": STAC_VERSION,
+        "stac_extensions": [POINTCLOUD_EXTENSION, PROJECTION_EXTENSION],
+        "id": id,
+        "collection": collection_id,
+        "geometry": feature.geometry,
+        "bbox": bbox,
+        "properties": properties,
+        "links": [
+            { "rel": "root", "href": "../../catalog.json", "type": "application/json" },
+            { "rel": "parent", "href": "../collection.json", "type": "application/json" },
+            { "rel": "collection", "href": "../collection.json", "type": "application/json" },
+        ],
+        "assets": {
+            "data": { "href": href, "type": media_type, "title": title, "roles": ["data"] },
+        },
+    });
+    Ok(StacItem {
+        id,
+        item,
+        bbox,
+        time_range,
+    })
+}
+
+pub(crate) fn write_json(path: &Path, value: &JsonValue) -> Result<(), LasPolyError> {
+    let mut file = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut file, value).map_err(std::io::Error::from)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Returns the path of the source file of a feature, from `SourceFile` or, for the tile
+/// index schema, `location`.
+pub(crate) fn source_file(feature: &Feature) -> Option<&str> {
+    let properties = feature.properties.as_ref()?;
+    properties
+        .get("SourceFile")
+        .or_else(|| properties.get("location"))
+        .and_then(JsonValue::as_str)
+}
+
+/// Writes a static STAC catalog of the files to `catalog_dir`, with a Collection for each
+/// directory and an Item for each file, see the module documentation.
+pub(crate) fn write_stac_catalog(
+    catalog_dir: &str,
+    features: &[Feature],
+) -> Result<(), LasPolyError> {
+    let catalog_path = Path::new(catalog_dir);
+    fs::create_dir_all(catalog_path)?;
+
+    // Group the files by directory, naming each Collection after its path below the
+    // deepest directory shared by all files
+    let mut directories: BTreeMap<Vec<String>, Vec<(&Feature, &str)>> = BTreeMap::new();
+    for feature in features {
+        if let Some(file_path) = source_file(feature) {
+            let dir = Path::new(file_path).parent().unwrap_or(Path::new(""));
+            directories
+                .entry(directory_components(&dir.to_string_lossy()))
+                .or_default()
+                .push((feature, file_path));
+        }
+    }
+    // Sort the files so that ids do not depend on the order the files were processed in
+    for files in directories.values_mut() {
+        files.sort_by(|a, b| a.1.cmp(b.1));
+    }
+    let mut common: Vec<String> = directories.keys().next().cloned().unwrap_or_default();
+    for components in directories.keys() {
+        let shared = common
+            .iter()
+            .zip(components)
+            .take_while(|(a, b)| a == b)
+            .count();
+        common.truncate(shared);
+    }
+
+    let mut collection_ids = HashSet::new();
+    let mut catalog_links = vec![json!({
+        "rel": "root", "href": "./catalog.json", "type": "application/json",
+    })];
+    for (components, files) in &directories {
+        let name = if components.len() > common.len() {
+            components[common.len()..].join("-")
+        } else {
+            common.last().cloned().unwrap_or_else(|| "root".to_string())
+        };
+        let collection_id = unique_id(sanitize_id(&name), &mut collection_ids);
+        let collection_path = catalog_path.join(&collection_id);
+        fs::create_dir_all(&collection_path)?;
+
+        let mut item_ids = HashSet::new();
+        let mut items = Vec::new();
+        for (feature, file_path) in files {
+            let stem = Path::new(file_path)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy();
+            let id = unique_id(sanitize_id(&stem), &mut item_ids);
+            items.push(stac_item(feature, file_path, id, &collection_id)?);
+        }
+
+        let mut collection_links = vec![
+            json!({ "rel": "root", "href": "../catalog.json", "type": "application/json" }),
+            json!({ "rel": "parent", "href": "../catalog.json", "type": "application/json" }),
+        ];
+        for item in &items {
+            let item_dir = collection_path.join(&item.id);
+            fs::create_dir_all(&item_dir)?;
+            write_json(&item_dir.join(format!("{}.json", item.id)), &item.item)?;
+            collection_links.push(json!({
+                "rel": "item",
+                "href": format!("./{}/{}.json", item.id, item.id),
+                "type": "application/geo+json",
+            }));
+        }
+
+        let bbox = items.iter().filter_map(|item| item.bbox).reduce(|a, b| {
+            [
+                a[0].min(b[0]),
+                a[1].min(b[1]),
+                a[2].max(b[2]),
+                a[3].max(b[3]),
+            ]
+        });
+        let start = items
+            .iter()
+            .filter_map(|item| item.time_range.as_ref().map(|range| &range.0))
+            .min();
+        let end = items
+            .iter()
+            .filter_map(|item| item.time_range.as_ref().map(|range| &range.1))
+            .max();
+        let directory = format!("/{}", components.join("/"));
+        let collection = json!({
+            "type": "Collection",
+            "stac_version": STAC_VERSION,
+            "stac_extensions": [POINTCLOUD_EXTENSION, PROJECTION_EXTENSION],
+            "id": collection_id,
+            "description": format!("LAS/LAZ files in {}", directory),
+            "license": "other",
+            "extent": {
+                "spatial": { "bbox": [bbox.unwrap_or([-180.0, -90.0, 180.0, 90.0])] },
+                "temporal": { "interval": [[start, end]] },
+            },
+            "summaries": {
+                "pc:type": ["lidar"],
+            },
+            "links": collection_links,
+        });
+        write_json(&collection_path.join("collection.json"), &collection)?;
+        catalog_links.push(json!({
+            "rel": "child",
+            "href": format!("./{}/collection.json", collection_id),
+            "type": "application/json",
+        }));
+    }
+
+    let catalog_name = catalog_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "catalog".to_string());
+    let catalog = json!({
+        "type": "Catalog",
+        "stac_version": STAC_VERSION,
+        "id": sanitize_id(&catalog_name),
+        "description": format!("Point cloud files catalogued by las-poly {}", env!("CARGO_PKG_VERSION")),
+        "links": catalog_links,
+    });
+    write_json(&catalog_path.join("catalog.json"), &catalog)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epsg_code() {
+        assert_eq!(epsg_code("EPSG:2193"), Some(2193));
+        let wkt1 = r#"PROJCS["NZGD2000 / New Zealand Transverse Mercator 2000",GEOGCS["NZGD2000",AUTHORITY["EPSG","4167"]],UNIT["metre",1,AUTHORITY["EPSG","9001"]],AUTHORITY["EPSG","2193"]]"#;
+        assert_eq!(epsg_code(wkt1), Some(2193));
+        let wkt2 = r#"PROJCRS["NZGD2000 / NZTM 2000",BASEGEOGCRS["NZGD2000",ID["EPSG",4167]],ID["EPSG",2193]]"#;
+        assert_eq!(epsg_code(wkt2), Some(2193));
+        assert_eq!(epsg_code(r#"LOCAL_CS["site",UNIT["metre",1]]"#), None);
+    }
+
+    #[test]
+    fn test_unique_id() {
+        let mut taken = HashSet::new();
+        assert_eq!(unique_id(sanitize_id("tile 1"), &mut taken), "tile_1");
+        assert_eq!(unique_id(sanitize_id("tile 1"), &mut taken), "tile_1-2");
+        // Ids are compared ignoring case, for case-insensitive file systems
+        assert_eq!(unique_id(sanitize_id("TILE_1"), &mut taken), "TILE_1-3");
+    }
+
+    #[test]
+    fn test_time_range() {
+        let properties = |value: JsonValue| value.as_object().cloned().unwrap();
+        assert_eq!(
+            time_range(&properties(json!({ "date": "2020-05-01" }))),
+            Some((
+                "2020-05-01T00:00:00Z".to_string(),
+                "2020-05-01T23:59:59Z".to_string()
+            ))
+        );
+        assert_eq!(
+            time_range(&properties(json!({
+                "date": "2020-05-01",
+                "acquisition_start": "2020-05-01T01:00:00Z",
+                "acquisition_end": "2020-05-01T02:00:00Z",
+            }))),
+            Some((
+                "2020-05-01T01:00:00Z".to_string(),
+                "2020-05-01T02:00:00Z".to_string()
+            ))
+        );
+        assert_eq!(time_range(&Map::new()), None);
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::HashSet;
+
+use las::{Header, Point};
+use serde_json::{Map, Value};
+
+/// Groups of per-file statistics that can be added to the output properties.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum Statistic {
+    /// `z_min`, `z_max` and, when points are read, `z_mean`.
+    Z,
+    /// `intensity_min` and `intensity_max`. Needs the points.
+    Intensity,
+    /// `gps_time_min` and `gps_time_max`. Needs the points.
+    GpsTime,
+    /// `points_by_return`, the number of points for each return number.
+    Returns,
+    /// `has_rgb`, `has_nir` and `has_waveform`, from the point format.
+    Attributes,
+    /// `classification_counts`, the number of points for each classification (including
+    /// the LAS 1.4 extended classes), and `flag_counts`, the number of withheld, synthetic,
+    /// key point and overlap points. Needs every point, so it counts the points the point
+    /// filter drops and is left out of sampled outlines.
+    Classification,
+}
+
+/// Per-point statistics gathered while reading the points of a file.
+///
+/// Summaries of different point ranges of a file can be merged.
+#[derive(Clone, Debug)]
+pub(crate) struct PointSummary {
+    count: u64,
+    z_min: f64,
+    z_max: f64,
+    z_sum: f64,
+    intensity_min: u16,
+    intensity_max: u16,
+    gps_time_min: f64,
+    gps_time_max: f64,
+    points_by_return: [u64; 16],
+    classification_counts: [u64; 256],
+    withheld: u64,
+    synthetic: u64,
+    key_point: u64,
+    overlap: u64,
+    cell_size: Option<f64>,
+    occupied_cells: HashSet<(i64, i64)>,
+}
+
+impl Default for PointSummary {
+    fn default() -> Self {
+        PointSummary {
+            count: 0,
+            z_min: f64::INFINITY,
+            z_max: f64::NEG_INFINITY,
+            z_sum: 0.0,
+            intensity_min: u16::MAX,
+            intensity_max: 0,
+            gps_time_min: f64::INFINITY,
+            gps_time_max: f64::NEG_INFINITY,
+            points_by_return: [0; 16],
+            classification_counts: [0; 256],
+            withheld: 0,
+            synthetic: 0,
+            key_point: 0,
+            overlap: 0,
+            cell_size: None,
+            occupied_cells: HashSet::new(),
+        }
+    }
+}
+
+impl PointSummary {
+    /// Creates a summary that also records the square cells of `cell_size` (in native
+    /// units) holding at least one point.
+    pub fn with_cell_size(cell_size: Option<f64>) -> Self {
+        PointSummary {
+            cell_size,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, point: &Point) {
+        self.count += 1;
+        self.z_min = self.z_min.min(point.z);
+        self.z_max = self.z_max.max(point.z);
+        self.z_sum += point.z;
+        self.intensity_min = self.intensity_min.min(point.intensity);
+        self.intensity_max = self.intensity_max.max(point.intensity);
+        if let Some(gps_time) = point.gps_time {
+            self.gps_time_min = self.gps_time_min.min(gps_time);
+            self.gps_time_max = self.gps_time_max.max(gps_time);
+        }
+        self.points_by_return[usize::from(point.return_number & 0x0f)] += 1;
+        self.classification_counts[usize::from(u8::from(point.classification))] += 1;
+        self.withheld += u64::from(point.is_withheld);
+        self.synthetic += u64::from(point.is_synthetic);
+        self.key_point += u64::from(point.is_key_point);
+        self.overlap += u64::from(point.is_overlap);
+    }
+
+    /// Records the cell of a point, if cells are recorded. Kept apart from `add` because
+    /// the statistics describe every point of a file, and the cells only the points of
+    /// its outline.
+    pub fn add_cell(&mut self, point: &Point) {
+        if let Some(cell_size) = self.cell_size {
+            self.occupied_cells.insert((
+                (point.x / cell_size).floor() as i64,
+                (point.y / cell_size).floor() as i64,
+            ));
+        }
+    }
+
+    pub fn merge(&mut self, other: &PointSummary) {
+        self.count += other.count;
+        self.z_min = self.z_min.min(other.z_min);
+        self.z_max = self.z_max.max(other.z_max);
+        self.z_sum += other.z_sum;
+        self.intensity_min = self.intensity_min.min(other.intensity_min);
+        self.intensity_max = self.intensity_max.max(other.intensity_max);
+        self.gps_time_min = self.gps_time_min.min(other.gps_time_min);
+        self.gps_time_max = self.gps_time_max.max(other.gps_time_max);
+        for (total, count) in self.points_by_return.iter_mut().zip(other.points_by_return) {
+            *total += count;
+        }
+        for (total, count) in self
+            .classification_counts
+            .iter_mut()
+            .zip(other.classification_counts)
+        {
+            *total += count;
+        }
+        self.withheld += other.withheld;
+        self.synthetic += other.synthetic;
+        self.key_point += other.key_point;
+        self.overlap += other.overlap;
+        self.occupied_cells.extend(&other.occupied_cells);
+    }
+
+    /// Returns the area covered by occupied cells, in native units, if cells are recorded.
+    pub fn occupied_area(&self) -> Option<f64> {
+        self.cell_size
+            .map(|cell_size| self.occupied_cells.len() as f64 * cell_size * cell_size)
+    }
+
+    /// Returns the GPS time range of the points, if they have GPS times.
+    pub fn gps_time_range(&self) -> Option<(f64, f64)> {
+        (self.gps_time_min <= self.gps_time_max).then_some((self.gps_time_min, self.gps_time_max))
+    }
+}
+
+/// Builds the properties for the `selected` statistics.
+///
+/// Values come from `summary` when the points were read, and from the header otherwise.
+/// Return counts are taken from the header whenever it has them.
+pub(crate) fn statistics_properties(
+    header: &Header,
+    summary: Option<&PointSummary>,
+    selected: &[Statistic],
+) -> Map<String, Value> {
+    let mut map = Map::new();
+    let summary = summary.filter(|summary| summary.count > 0);
+    if selected.contains(&Statistic::Z) {
+        match summary {
+            Some(summary) => {
+                map.insert("z_min".to_string(), summary.z_min.into());
+                map.insert("z_max".to_string(), summary.z_max.into());
+                map.insert(
+                    "z_mean".to_string(),
+                    (summary.z_sum / summary.count as f64).into(),
+                );
+            }
+            None => {
+                let bounds = header.bounds();
+                map.insert("z_min".to_string(), bounds.min.z.into());
+                map.insert("z_max".to_string(), bounds.max.z.into());
+            }
+        }
+    }
+    if let (true, Some(summary)) = (selected.contains(&Statistic::Intensity), summary) {
+        map.insert("intensity_min".to_string(), summary.intensity_min.into());
+        map.insert("intensity_max".to_string(), summary.intensity_max.into());
+    }
+    if let (true, Some((gps_time_min, gps_time_max))) = (
+        selected.contains(&Statistic::GpsTime),
+        summary.and_then(PointSummary::gps_time_range),
+    ) {
+        map.insert("gps_time_min".to_string(), gps_time_min.into());
+        map.insert("gps_time_max".to_string(), gps_time_max.into());
+    }
+    if selected.contains(&Statistic::Returns) {
+        let mut points_by_return = Map::new();
+        for n in 1..16u8 {
+            if let Some(count) = header.number_of_points_by_return(n).filter(|&c| c > 0) {
+                points_by_return.insert(n.to_string(), count.into());
+            }
+        }
+        if points_by_return.is_empty() {
+            if let Some(summary) = summary {
+                for (n, &count) in summary.points_by_return.iter().enumerate() {
+                    if count > 0 {
+                        points_by_return.insert(n.to_string(), count.into());
+                    }
+                }
+            }
+        }
+        if !points_by_return.is_empty() {
+            map.insert(
+                "points_by_return".to_string(),
+                Value::Object(points_by_return),
+            );
+        }
+    }
+    if selected.contains(&Statistic::Attributes) {
+        let format = header.point_format();
+        map.insert("has_rgb".to_string(), format.has_color.into());
+        map.insert("has_nir".to_string(), format.has_nir.into());
+        map.insert("has_waveform".to_string(), format.has_waveform.into());
+    }
+    if let (true, Some(summary)) = (selected.contains(&Statistic::Classification), summary) {
+        let mut classification_counts = Map::new();
+        for (class, &count) in summary.classification_counts.iter().enumerate() {
+            if count > 0 {
+                classification_counts.insert(class.to_string(), count.into());
+            }
+        }
+        map.insert(
+            "classification_counts".to_string(),
+            Value::Object(classification_counts),
+        );
+        let mut flag_counts = Map::new();
+        flag_counts.insert("withheld".to_string(), summary.withheld.into());
+        flag_counts.insert("synthetic".to_string(), summary.synthetic.into());
+        flag_counts.insert("key_point".to_string(), summary.key_point.into());
+        flag_counts.insert("overlap".to_string(), summary.overlap.into());
+        map.insert("flag_counts".to_string(), Value::Object(flag_counts));
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(z: f64, intensity: u16, return_number: u8) -> Point {
+        Point {
+            z,
+            intensity,
+            return_number,
+            gps_time: Some(z * 10.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_matches_single_summary() {
+        let points = [point(1.0, 10, 1), point(5.0, 3, 2), point(-2.0, 300, 1)];
+        let mut whole = PointSummary::default();
+        points.iter().for_each(|p| whole.add(p));
+
+        let mut first = PointSummary::default();
+        first.add(&points[0]);
+        let mut second = PointSummary::default();
+        points[1..].iter().for_each(|p| second.add(p));
+        first.merge(&second);
+
+        let all = [
+            Statistic::Z,
+            Statistic::Intensity,
+            Statistic::GpsTime,
+            Statistic::Returns,
+        ];
+        let header = Header::default();
+        assert_eq!(
+            statistics_properties(&header, Some(&whole), &all),
+            statistics_properties(&header, Some(&first), &all)
+        );
+    }
+
+    #[test]
+    fn test_statistics_properties() {
+        let mut summary = PointSummary::default();
+        for p in [point(1.0, 10, 1), point(5.0, 3, 2), point(-3.0, 300, 1)] {
+            summary.add(&p);
+        }
+        let map = statistics_properties(
+            &Header::default(),
+            Some(&summary),
+            &[Statistic::Z, Statistic::Intensity, Statistic::Returns],
+        );
+        assert_eq!(map["z_min"], -3.0);
+        assert_eq!(map["z_max"], 5.0);
+        assert_eq!(map["z_mean"], 1.0);
+        assert_eq!(map["intensity_min"], 3);
+        assert_eq!(map["intensity_max"], 300);
+        assert_eq!(map["points_by_return"]["1"], 2);
+        assert_eq!(map["points_by_return"]["2"], 1);
+        assert!(!map.contains_key("gps_time_min"));
+    }
+
+    #[test]
+    fn test_classification_counts() {
+        let mut summary = PointSummary::default();
+        for class in [2, 2, 6, 64] {
+            let mut p = point(0.0, 0, 1);
+            p.classification = las::point::Classification::new(class).unwrap();
+            p.is_withheld = class == 6;
+            summary.add(&p);
+        }
+        let map = statistics_properties(
+            &Header::default(),
+            Some(&summary),
+            &[Statistic::Classification],
+        );
+        assert_eq!(map["classification_counts"]["2"], 2);
+        assert_eq!(map["classification_counts"]["6"], 1);
+        assert_eq!(map["classification_counts"]["64"], 1);
+        assert_eq!(map["flag_counts"]["withheld"], 1);
+        assert_eq!(map["flag_counts"]["overlap"], 0);
+    }
+
+    #[test]
+    fn test_occupied_area() {
+        let mut first = PointSummary::with_cell_size(Some(2.0));
+        let mut second = PointSummary::with_cell_size(Some(2.0));
+        for (x, y) in [(0.5, 0.5), (1.5, 1.5), (3.0, 0.0)] {
+            first.add_cell(&Point {
+                x,
+                y,
+                ..Default::default()
+            });
+        }
+        second.add_cell(&Point {
+            x: 3.5,
+            y: 1.0,
+            ..Default::default()
+        });
+        second.add_cell(&Point {
+            x: -1.0,
+            y: 0.0,
+            ..Default::default()
+        });
+        first.merge(&second);
+        assert_eq!(first.occupied_area(), Some(12.0));
+        assert_eq!(PointSummary::default().occupied_area(), None);
+    }
+}
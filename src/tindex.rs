@@ -0,0 +1,53 @@
+//! Properties following the PDAL `tindex` schema.
+//!
+//! `pdal tindex create` writes one feature per file with the path in `location`, the
+//! spatial reference of the file in `srs` and, as date-times, the `created` and `modified`
+//! times of the file. Outlines with these properties can be used by `pdal tindex merge`
+//! and other tile index readers in place of an index built by PDAL.
+
+use geojson::{Feature, Geometry, Value};
+use log::warn;
+use serde_json::Map;
+
+use crate::crs_utils::crs_wkt;
+use crate::file_info::{created_time, modified_time};
+
+/// Returns the WKT of the CRS a file was read in. Files with a WKT CRS keep it as it is,
+/// the CRS found from GeoTIFF keys or guessed is exported as WKT by PROJ. The identifier
+/// is only kept if PROJ cannot export it.
+fn srs_wkt(crs: &str) -> String {
+    if crs.contains('[') {
+        return crs.to_string();
+    }
+    crs_wkt(crs).unwrap_or_else(|| {
+        warn!("Could not export {} as WKT, srs holds the identifier", crs);
+        crs.trim().to_string()
+    })
+}
+
+/// Creates the tile index feature of a file, with `srs` holding the WKT of the CRS the
+/// file was read in, see `srs_wkt`.
+pub(crate) fn tindex_feature(
+    file_path: &str,
+    crs: &str,
+    timestamps: bool,
+    outline: Value,
+) -> Feature {
+    let mut properties = Map::new();
+    properties.insert("location".to_string(), file_path.into());
+    properties.insert("srs".to_string(), srs_wkt(crs).into());
+    if timestamps {
+        let metadata = std::fs::metadata(file_path).ok();
+        let created = metadata.as_ref().and_then(created_time);
+        let modified = metadata.as_ref().and_then(modified_time);
+        properties.insert("created".to_string(), created.into());
+        properties.insert("modified".to_string(), modified.into());
+    }
+    Feature {
+        geometry: Some(Geometry::new(outline)),
+        properties: Some(properties),
+        id: None,
+        bbox: None,
+        foreign_members: None,
+    }
+}
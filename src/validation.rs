@@ -0,0 +1,160 @@
+//! Checking LAS headers against the points they describe.
+//!
+//! The header outline is only as good as the header bounds, so files whose header does
+//! not match their points are flagged. The header the file should have is computed by
+//! reading every point into a cleared copy of its header.
+
+use std::fs::File;
+use std::io::BufReader;
+
+use las::{Header, Reader};
+use serde_json::{Map, Value};
+
+/// Reads every point of a file into a copy of its header, giving the bounds, point count
+/// and return counts the header should have.
+pub(crate) fn header_from_points(reader: &mut Reader) -> Result<Header, las::Error> {
+    let mut header = reader.header().clone();
+    header.clear();
+    for point in reader.points() {
+        header.add_point(&point?);
+    }
+    Ok(header)
+}
+
+/// Lists the differences between a header and the header computed from its points.
+///
+/// Bounds may differ by `tolerance`, or by one step of the scale factor of the axis if
+/// that is larger, since writers often round the header bounds. Return counts are only
+/// compared when the header has them.
+pub(crate) fn header_issues(header: &Header, actual: &Header, tolerance: f64) -> Vec<String> {
+    let mut issues = Vec::new();
+    if header.number_of_points() != actual.number_of_points() {
+        issues.push(format!(
+            "number_of_points is {} in the header but {} in the data",
+            header.number_of_points(),
+            actual.number_of_points()
+        ));
+    }
+
+    let (b, a, t) = (header.bounds(), actual.bounds(), header.transforms());
+    for (name, value, actual_value, scale) in [
+        ("min_x", b.min.x, a.min.x, t.x.scale),
+        ("min_y", b.min.y, a.min.y, t.y.scale),
+        ("min_z", b.min.z, a.min.z, t.z.scale),
+        ("max_x", b.max.x, a.max.x, t.x.scale),
+        ("max_y", b.max.y, a.max.y, t.y.scale),
+        ("max_z", b.max.z, a.max.z, t.z.scale),
+    ] {
+        // Both are close to multiples of the scale, so one and a half steps accepts a
+        // rounding of one step despite floating point error
+        if actual.number_of_points() > 0
+            && (value - actual_value).abs() > tolerance.max(1.5 * scale)
+        {
+            issues.push(format!(
+                "{} is {} in the header but {} in the data",
+                name, value, actual_value
+            ));
+        }
+    }
+
+    if (1..16).any(|n| header.number_of_points_by_return(n).is_some_and(|c| c > 0)) {
+        for n in 1..16 {
+            let count = header.number_of_points_by_return(n).unwrap_or(0);
+            let actual_count = actual.number_of_points_by_return(n).unwrap_or(0);
+            if count != actual_count {
+                issues.push(format!(
+                    "return {} has {} points in the header but {} in the data",
+                    n, count, actual_count
+                ));
+            }
+        }
+    }
+    issues
+}
+
+/// Checks the header of a file against its points, returning the `validation_status`
+/// property (`valid` or `invalid`) and, for invalid files, the `validation_issues`.
+///
+/// Uncompressed files are also checked for points stored after the last point the
+/// header counts, which the reader cannot see.
+pub(crate) fn validation_properties(
+    file_path: &str,
+    tolerance: f64,
+) -> Result<Map<String, Value>, las::Error> {
+    let mut reader = Reader::from_path(file_path)?;
+    let header = reader.header().clone();
+    let mut issues = match header_from_points(&mut reader) {
+        Ok(actual) => header_issues(&header, &actual, tolerance),
+        Err(e) => vec![format!("the points could not be read: {}", e)],
+    };
+
+    let format = header.point_format();
+    if !format.is_compressed && header.evlrs().is_empty() {
+        let raw_header = las::raw::Header::read_from(BufReader::new(File::open(file_path)?))?;
+        let point_bytes = std::fs::metadata(file_path)?
+            .len()
+            .saturating_sub(u64::from(raw_header.offset_to_point_data));
+        let extra_points =
+            (point_bytes / u64::from(format.len())).saturating_sub(header.number_of_points());
+        if extra_points > 0 {
+            issues.push(format!(
+                "the file holds {} more points than the header counts",
+                extra_points
+            ));
+        }
+    }
+
+    let mut map = Map::new();
+    if issues.is_empty() {
+        map.insert("validation_status".to_string(), "valid".into());
+    } else {
+        map.insert("validation_status".to_string(), "invalid".into());
+        map.insert("validation_issues".to_string(), issues.join("; ").into());
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use las::Point;
+
+    fn point(x: f64, return_number: u8) -> Point {
+        Point {
+            x,
+            y: 1.0,
+            z: 2.0,
+            return_number,
+            number_of_returns: 2,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_matching_header_has_no_issues() {
+        let mut header = Header::default();
+        for p in [point(0.0, 1), point(10.0, 2)] {
+            header.add_point(&p);
+        }
+        assert!(header_issues(&header, &header.clone(), 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_header_issues() {
+        let mut header = Header::default();
+        header.add_point(&point(0.0, 1));
+        let mut actual = header.clone();
+        actual.add_point(&point(10.0, 2));
+        let issues = header_issues(&header, &actual, 0.01);
+        assert_eq!(
+            issues,
+            vec![
+                "number_of_points is 1 in the header but 2 in the data",
+                "max_x is 0 in the header but 10 in the data",
+                "return 2 has 0 points in the header but 1 in the data",
+            ]
+        );
+        // Within the tolerance
+        assert_eq!(header_issues(&header, &actual, 20.0).len(), 2);
+    }
+}
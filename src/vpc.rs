@@ -0,0 +1,224 @@
+//! Writing a QGIS virtual point cloud.
+//!
+//! A `.vpc` file is a STAC ItemCollection with an Item for each file, as written by
+//! `pdal_wrench build_vpc`. QGIS 3.32 and later open it as a single point cloud layer,
+//! using the footprint of each Item to load only the files in view. Items have the
+//! outline as geometry, the point count, the native bbox (`proj:bbox`) and the CRS
+//! (`proj:epsg` or `proj:wkt2`), and link to the source file with a path relative to the
+//! `.vpc` file when it shares a root with it.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use geojson::Feature;
+use las::Reader;
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::stac::{
+    epsg_code, file_crs, insert_datetime, point_schemas, source_file, time_range, write_json,
+    POINTCLOUD_EXTENSION, PROJECTION_EXTENSION, STAC_VERSION,
+};
+use crate::wkb::geometry_bbox;
+use crate::LasPolyError;
+
+/// Returns the path of `file_path` relative to `base_dir`, as a `./` or `../` path with
+/// forward slashes, or the absolute path if they have no common root.
+fn relative_href(file_path: &Path, base_dir: &Path) -> String {
+    let file = fs::canonicalize(file_path).unwrap_or_else(|_| file_path.to_path_buf());
+    let base = fs::canonicalize(base_dir).unwrap_or_else(|_| base_dir.to_path_buf());
+    let file_components: Vec<Component> = file.components().collect();
+    let base_components: Vec<Component> = base.components().collect();
+    let shared = file_components
+        .iter()
+        .zip(&base_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let has_root = |components: &[Component]| {
+        components[..shared]
+            .iter()
+            .any(|c| matches!(c, Component::RootDir | Component::Normal(_)))
+    };
+    if !has_root(&file_components) {
+        return file.to_string_lossy().into_owned();
+    }
+    let mut parts: Vec<String> = vec![".".to_string()];
+    parts.extend((shared..base_components.len()).map(|_| "..".to_string()));
+    if parts.len() > 1 {
+        parts.remove(0);
+    }
+    parts.extend(
+        file_components[shared..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
+
+/// Sets the CRS of an Item: `proj:epsg` when the code is known, and the WKT of the file
+/// in `proj:wkt2` when it is WKT 2 or there is no code. QGIS reads WKT 1 there as well.
+fn insert_crs(properties: &mut Map<String, JsonValue>, crs: Option<&str>) {
+    let epsg = crs.and_then(epsg_code);
+    properties.insert("proj:epsg".to_string(), epsg.into());
+    if let Some(wkt) = crs.filter(|crs| !crs.starts_with("EPSG:")) {
+        let keyword = wkt.trim_start().split('[').next().unwrap_or_default();
+        if keyword.trim().ends_with("CRS") || epsg.is_none() {
+            properties.insert("proj:wkt2".to_string(), wkt.into());
+        }
+    }
+}
+
+fn vpc_item(
+    feature: &Feature,
+    file_path: &str,
+    id: String,
+    vpc_dir: &Path,
+) -> Result<JsonValue, LasPolyError> {
+    let reader = Reader::from_path(file_path)?;
+    let header = reader.header();
+    let bounds = header.bounds();
+    let mut properties = Map::new();
+    let feature_properties = feature.properties.clone().unwrap_or_default();
+    insert_datetime(&mut properties, time_range(&feature_properties).as_ref());
+    properties.insert("pc:count".to_string(), header.number_of_points().into());
+    properties.insert("pc:type".to_string(), "lidar".into());
+    let encoding = if header.point_format().is_compressed {
+        "laszip"
+    } else {
+        "binary"
+    };
+    properties.insert("pc:encoding".to_string(), encoding.into());
+    properties.insert("pc:schemas".to_string(), point_schemas(header).into());
+    properties.insert(
+        "proj:bbox".to_string(),
+        json!([
+            bounds.min.x,
+            bounds.min.y,
+            bounds.min.z,
+            bounds.max.x,
+            bounds.max.y,
+            bounds.max.z
+        ]),
+    );
+    properties.insert(
+        "proj:geometry".to_string(),
+        json!({
+            "type": "Polygon",
+            "coordinates": [[
+                [bounds.min.x, bounds.min.y],
+                [bounds.max.x, bounds.min.y],
+                [bounds.max.x, bounds.max.y],
+                [bounds.min.x, bounds.max.y],
+                [bounds.min.x, bounds.min.y],
+            ]],
+        }),
+    );
+    insert_crs(&mut properties, file_crs(file_path).as_deref());
+
+    let bbox = feature
+        .geometry
+        .as_ref()
+        .and_then(|geometry| geometry_bbox(&geometry.value))
+        .map(|bbox| {
+            [
+                bbox[0],
+                bbox[1],
+                bounds.min.z,
+                bbox[2],
+                bbox[3],
+                bounds.max.z,
+            ]
+        });
+    Ok(json!({
+        "type": "Feature",
+        "stac_version": STAC_VERSION,
+        "stac_extensions": [POINTCLOUD_EXTENSION, PROJECTION_EXTENSION],
+        "id": id,
+        "geometry": feature.geometry,
+        "bbox": bbox,
+        "properties": properties,
+        "links": [],
+        "assets": {
+            "data": {
+                "href": relative_href(Path::new(file_path), vpc_dir),
+                "roles": ["data"],
+            },
+        },
+    }))
+}
+
+/// Writes a QGIS virtual point cloud of the files of the features, see the module
+/// documentation. Features must be per file, so outlines are not merged for this format.
+pub(crate) fn write_vpc(output_file_name: &str, features: &[Feature]) -> Result<(), LasPolyError> {
+    let output_path = Path::new(output_file_name);
+    let vpc_dir = match output_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let mut files: Vec<(&Feature, &str)> = features
+        .iter()
+        .filter_map(|feature| Some((feature, source_file(feature)?)))
+        .collect();
+    files.sort_by(|a, b| a.1.cmp(b.1));
+
+    let mut ids = HashSet::new();
+    let mut items = Vec::with_capacity(files.len());
+    for (feature, file_path) in files {
+        let stem = Path::new(file_path)
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        // Ids only need to be unique, files keep their names
+        let mut id = stem.clone();
+        let mut n = 2;
+        while !ids.insert(id.clone()) {
+            id = format!("{}-{}", stem, n);
+            n += 1;
+        }
+        items.push(vpc_item(feature, file_path, id, &vpc_dir)?);
+    }
+    write_json(
+        output_path,
+        &json!({ "type": "FeatureCollection", "features": items }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_href() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let tiles = temp_dir.path().join("tiles");
+        let other = temp_dir.path().join("other");
+        fs::create_dir_all(&tiles).unwrap();
+        fs::create_dir_all(&other).unwrap();
+        let file = tiles.join("a.las");
+        fs::write(&file, b"").unwrap();
+        assert_eq!(relative_href(&file, temp_dir.path()), "./tiles/a.las");
+        assert_eq!(relative_href(&file, &tiles), "./a.las");
+        assert_eq!(relative_href(&file, &other), "../tiles/a.las");
+    }
+
+    #[test]
+    fn test_insert_crs() {
+        let mut properties = Map::new();
+        insert_crs(&mut properties, Some("EPSG:2193"));
+        assert_eq!(properties["proj:epsg"], 2193);
+        assert!(!properties.contains_key("proj:wkt2"));
+
+        let mut properties = Map::new();
+        insert_crs(
+            &mut properties,
+            Some(r#"PROJCRS["NZTM",BASEGEOGCRS["NZGD2000"],ID["EPSG",2193]]"#),
+        );
+        assert_eq!(properties["proj:epsg"], 2193);
+        assert!(properties.contains_key("proj:wkt2"));
+
+        let mut properties = Map::new();
+        insert_crs(&mut properties, None);
+        assert_eq!(properties["proj:epsg"], JsonValue::Null);
+    }
+}
@@ -0,0 +1,93 @@
+//! Well-known binary encoding of the outline geometries, for the binary output formats.
+
+use geojson::{PolygonType, Value};
+
+const WKB_POLYGON: u32 = 3;
+const WKB_MULTIPOLYGON: u32 = 6;
+
+/// Encodes a Polygon or MultiPolygon as little endian 2D WKB. Other geometry types are
+/// not produced by the outlines and return `None`.
+pub(crate) fn geometry_wkb(value: &Value) -> Option<Vec<u8>> {
+    let mut wkb = Vec::new();
+    match value {
+        Value::Polygon(rings) => write_polygon(&mut wkb, rings),
+        Value::MultiPolygon(polygons) => {
+            write_header(&mut wkb, WKB_MULTIPOLYGON, polygons.len());
+            for rings in polygons {
+                write_polygon(&mut wkb, rings);
+            }
+        }
+        _ => return None,
+    }
+    Some(wkb)
+}
+
+/// Returns the bounding box of a Polygon or MultiPolygon as `[min_x, min_y, max_x, max_y]`.
+pub(crate) fn geometry_bbox(value: &Value) -> Option<[f64; 4]> {
+    let positions: Vec<&Vec<f64>> = match value {
+        Value::Polygon(rings) => rings.iter().flatten().collect(),
+        Value::MultiPolygon(polygons) => polygons.iter().flatten().flatten().collect(),
+        _ => return None,
+    };
+    positions.iter().fold(None, |bbox, position| {
+        let (x, y) = (position[0], position[1]);
+        Some(match bbox {
+            None => [x, y, x, y],
+            Some([min_x, min_y, max_x, max_y]) => {
+                [min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)]
+            }
+        })
+    })
+}
+
+/// Returns the WKB name of the geometry type of a Polygon or MultiPolygon.
+pub(crate) fn geometry_type_name(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Polygon(_) => Some("POLYGON"),
+        Value::MultiPolygon(_) => Some("MULTIPOLYGON"),
+        _ => None,
+    }
+}
+
+fn write_header(wkb: &mut Vec<u8>, geometry_type: u32, count: usize) {
+    wkb.push(1);
+    wkb.extend_from_slice(&geometry_type.to_le_bytes());
+    wkb.extend_from_slice(&(count as u32).to_le_bytes());
+}
+
+fn write_polygon(wkb: &mut Vec<u8>, rings: &PolygonType) {
+    write_header(wkb, WKB_POLYGON, rings.len());
+    for ring in rings {
+        wkb.extend_from_slice(&(ring.len() as u32).to_le_bytes());
+        for position in ring {
+            wkb.extend_from_slice(&position[0].to_le_bytes());
+            wkb.extend_from_slice(&position[1].to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polygon_wkb() {
+        let square = Value::Polygon(vec![vec![
+            vec![0.0, 0.0],
+            vec![2.0, 0.0],
+            vec![2.0, 1.0],
+            vec![0.0, 0.0],
+        ]]);
+        let wkb = geometry_wkb(&square).unwrap();
+        assert_eq!(wkb.len(), 1 + 4 + 4 + 4 + 4 * 16);
+        assert_eq!(&wkb[..13], &[1, 3, 0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0]);
+        assert_eq!(&wkb[29..37], &2.0f64.to_le_bytes());
+        assert_eq!(geometry_bbox(&square), Some([0.0, 0.0, 2.0, 1.0]));
+
+        let multi = Value::MultiPolygon(vec![vec![vec![vec![0.0, 0.0]; 4]]; 2]);
+        let wkb = geometry_wkb(&multi).unwrap();
+        assert_eq!(&wkb[..9], &[1, 6, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(wkb.len(), 9 + 2 * (13 + 4 * 16));
+        assert_eq!(geometry_wkb(&Value::Point(vec![0.0, 0.0])), None);
+    }
+}
@@ -540,3 +540,415 @@ fn test_merge_geometries_with_overlap_and_all_properties() {
         panic!("Expected properties");
     }
 }
+
+#[test]
+fn test_merge_geometries_sums_classification_counts() {
+    let mut collection = LasOutlineFeatureCollection::new();
+    for (i, counts) in [json!({"2": 10, "6": 5}), json!({"2": 1, "7": 3})]
+        .into_iter()
+        .enumerate()
+    {
+        let offset = i as f64;
+        let mut properties = Map::new();
+        properties.insert("SourceFileDir".to_string(), json!("folder1"));
+        properties.insert("number_of_points".to_string(), json!(18));
+        properties.insert("classification_counts".to_string(), counts);
+        collection.add_feature(Feature {
+            geometry: Some(Geometry::new(Value::Polygon(vec![vec![
+                vec![offset, 0.0],
+                vec![offset + 1.0, 0.0],
+                vec![offset + 1.0, 1.0],
+                vec![offset, 1.0],
+                vec![offset, 0.0],
+            ]]))),
+            properties: Some(properties),
+            id: None,
+            bbox: None,
+            foreign_members: None,
+        });
+    }
+    collection.merge_geometries(false, false);
+
+    assert_eq!(collection.features().len(), 1);
+    let properties = collection.features()[0].properties.as_ref().unwrap();
+    assert_eq!(
+        properties.get("classification_counts").unwrap(),
+        &json!({"2": 11, "6": 5, "7": 3})
+    );
+}
+
+#[test]
+fn test_merge_geometries_recomputes_area_and_density() {
+    let mut collection = LasOutlineFeatureCollection::new();
+    for offset in [0.0, 0.5] {
+        let mut properties = Map::new();
+        properties.insert("SourceFileDir".to_string(), json!("folder1"));
+        properties.insert("number_of_points".to_string(), json!(18));
+        properties.insert("area_km2".to_string(), json!(1000.0));
+        properties.insert("occupied_area_m2".to_string(), json!(1e9));
+        collection.add_feature(Feature {
+            geometry: Some(Geometry::new(Value::Polygon(vec![vec![
+                vec![offset, 0.0],
+                vec![offset + 1.0, 0.0],
+                vec![offset + 1.0, 1.0],
+                vec![offset, 1.0],
+                vec![offset, 0.0],
+            ]]))),
+            properties: Some(properties),
+            id: None,
+            bbox: None,
+            foreign_members: None,
+        });
+    }
+    collection.merge_geometries(false, true);
+
+    assert_eq!(collection.features().len(), 1);
+    let properties = collection.features()[0].properties.as_ref().unwrap();
+    // A 1.5 by 1 degree rectangle on the equator covers about 18 460 km²
+    let area_km2 = properties["area_km2"].as_f64().unwrap();
+    assert!((18_300.0..18_600.0).contains(&area_km2), "{}", area_km2);
+    assert!(properties["perimeter_m"].as_f64().unwrap() > 500_000.0);
+    let point_density = properties["point_density"].as_f64().unwrap();
+    assert!((point_density - 36.0 / (area_km2 * 1e6)).abs() < 1e-12);
+    assert_eq!(properties["occupied_area_m2"], json!(2e9));
+    assert_eq!(properties["cell_density"], json!(36.0 / 2e9));
+}
+
+#[test]
+fn test_merge_geometries_widens_acquisition_range() {
+    let mut collection = LasOutlineFeatureCollection::new();
+    for (offset, start, end) in [
+        (0.0, "2023-03-02T01:00:00Z", "2023-03-02T02:00:00Z"),
+        (0.5, "2023-03-01T23:00:00Z", "2023-03-02T01:30:00Z"),
+    ] {
+        let mut properties = Map::new();
+        properties.insert("SourceFileDir".to_string(), json!("folder1"));
+        properties.insert("acquisition_start".to_string(), json!(start));
+        properties.insert("acquisition_end".to_string(), json!(end));
+        collection.add_feature(Feature {
+            geometry: Some(Geometry::new(Value::Polygon(vec![vec![
+                vec![offset, 0.0],
+                vec![offset + 1.0, 0.0],
+                vec![offset + 1.0, 1.0],
+                vec![offset, 1.0],
+                vec![offset, 0.0],
+            ]]))),
+            properties: Some(properties),
+            id: None,
+            bbox: None,
+            foreign_members: None,
+        });
+    }
+    collection.merge_geometries(false, true);
+
+    let properties = collection.features()[0].properties.as_ref().unwrap();
+    assert_eq!(properties["acquisition_start"], "2023-03-01T23:00:00Z");
+    assert_eq!(properties["acquisition_end"], "2023-03-02T02:00:00Z");
+}
+
+#[test]
+fn test_find_duplicates() {
+    let mut collection = LasOutlineFeatureCollection::new();
+    for (file, offset, points, hash) in [
+        ("a/tile.las", 0.0, 100, "sha256:1"),
+        ("b/tile.las", 0.0, 100, "sha256:1"),
+        ("a/other.las", 5.0, 100, "sha256:2"),
+        ("a/other.laz", 5.0, 100, "sha256:3"),
+        ("a/unique.las", 5.0, 99, "sha256:4"),
+    ] {
+        let mut properties = Map::new();
+        properties.insert("SourceFile".to_string(), json!(file));
+        properties.insert("number_of_points".to_string(), json!(points));
+        properties.insert("content_hash".to_string(), json!(hash));
+        collection.add_feature(Feature {
+            geometry: Some(Geometry::new(Value::Polygon(vec![vec![
+                vec![offset, 0.0],
+                vec![offset + 1.0, 0.0],
+                vec![offset + 1.0, 1.0],
+                vec![offset, 1.0],
+                vec![offset, 0.0],
+            ]]))),
+            properties: Some(properties),
+            id: None,
+            bbox: None,
+            foreign_members: None,
+        });
+    }
+
+    let duplicates = collection.find_duplicates();
+    assert_eq!(duplicates.features().len(), 2);
+    let geometric = duplicates.features()[0].properties.as_ref().unwrap();
+    assert_eq!(geometric["duplicate_type"], "geometric");
+    assert_eq!(geometric["files"], "a/other.las,a/other.laz");
+    let exact = duplicates.features()[1].properties.as_ref().unwrap();
+    assert_eq!(exact["duplicate_type"], "exact");
+    assert_eq!(exact["number_of_files"], 2);
+    assert_eq!(exact["files"], "a/tile.las,b/tile.las");
+}
+
+#[test]
+fn test_save_to_flatgeobuf() {
+    let mut collection = LasOutlineFeatureCollection::new();
+    for i in 0..20 {
+        let x = i as f64;
+        let mut properties = Map::new();
+        properties.insert("SourceFile".to_string(), json!(format!("tile_{}.las", i)));
+        properties.insert("number_of_points".to_string(), json!(100 + i));
+        collection.add_feature(Feature {
+            geometry: Some(Geometry::new(Value::Polygon(vec![vec![
+                vec![x, 0.0],
+                vec![x + 1.0, 0.0],
+                vec![x + 1.0, 1.0],
+                vec![x, 1.0],
+                vec![x, 0.0],
+            ]]))),
+            properties: Some(properties),
+            id: None,
+            bbox: None,
+            foreign_members: None,
+        });
+    }
+    let temp_dir = tempfile::tempdir().unwrap();
+    let output_path = temp_dir.path().join("tiles.fgb");
+    collection
+        .save_to_flatgeobuf(output_path.to_str().unwrap())
+        .unwrap();
+
+    let bytes = fs::read(&output_path).unwrap();
+    assert_eq!(&bytes[..8], b"fgb\x03fgb\x00");
+    let header_len = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize;
+    let index = 12 + header_len;
+    let read_f64 = |pos: usize| f64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap());
+    // The root node of the 23 node index covers all tiles
+    assert_eq!(
+        [
+            read_f64(index),
+            read_f64(index + 8),
+            read_f64(index + 16),
+            read_f64(index + 24)
+        ],
+        [0.0, 0.0, 20.0, 1.0]
+    );
+
+    // Walk the size prefixed features after the index
+    let mut pos = index + 23 * 40;
+    let mut count = 0;
+    while pos < bytes.len() {
+        pos += 4 + u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        count += 1;
+    }
+    assert_eq!(pos, bytes.len());
+    assert_eq!(count, 20);
+}
+
+#[test]
+fn test_save_to_shapefile() {
+    let mut collection = LasOutlineFeatureCollection::new();
+    let long_value = "x".repeat(300);
+    for (i, value) in ["short", long_value.as_str()].iter().enumerate() {
+        let mut properties = Map::new();
+        properties.insert(
+            "acquisition_start".to_string(),
+            json!("2020-01-01T00:00:00Z"),
+        );
+        properties.insert("acquisition_end".to_string(), json!("2020-01-02T00:00:00Z"));
+        properties.insert("number_of_points".to_string(), json!(100 * (i + 1)));
+        properties.insert("notes".to_string(), json!(value));
+        collection.add_feature(Feature {
+            geometry: Some(Geometry::new(Value::Polygon(vec![vec![
+                vec![0.0, 0.0],
+                vec![1.0, 0.0],
+                vec![1.0, 1.0],
+                vec![0.0, 0.0],
+            ]]))),
+            properties: Some(properties),
+            id: None,
+            bbox: None,
+            foreign_members: None,
+        });
+    }
+    let temp_dir = tempfile::tempdir().unwrap();
+    let output_path = temp_dir.path().join("tiles.shp");
+    collection
+        .save_to_shapefile(output_path.to_str().unwrap())
+        .unwrap();
+
+    for extension in ["shx", "prj", "cpg"] {
+        assert!(output_path.with_extension(extension).exists());
+    }
+    let shp = fs::read(&output_path).unwrap();
+    assert_eq!(&shp[..4], &9994i32.to_be_bytes());
+    // File length in 16 bit words: the header and two records of 8 + 4 + 32 + 8 + 4 + 64 bytes
+    assert_eq!(&shp[24..28], &((100 + 2 * 120) / 2i32).to_be_bytes());
+    assert_eq!(shp.len(), 340);
+
+    let dbf = fs::read(output_path.with_extension("dbf")).unwrap();
+    assert_eq!(u32::from_le_bytes(dbf[4..8].try_into().unwrap()), 2);
+    let field_names: Vec<String> = dbf[32..]
+        .chunks(32)
+        .take_while(|descriptor| descriptor[0] != 0x0D)
+        .map(|descriptor| {
+            String::from_utf8_lossy(&descriptor[..11])
+                .trim_end_matches('\0')
+                .to_string()
+        })
+        .collect();
+    assert_eq!(
+        field_names,
+        vec!["acquisitio", "acquisit_1", "notes", "number_of_"]
+    );
+
+    let sidecar: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(output_path.with_extension("fields.json")).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(sidecar["fields"][1]["property"], "acquisition_start");
+    assert_eq!(sidecar["fields"][1]["field"], "acquisit_1");
+    assert_eq!(sidecar["fields"][2]["length"], 254);
+    assert_eq!(sidecar["truncated_values"][0]["record"], 2);
+    assert_eq!(sidecar["truncated_values"][0]["value"], long_value.as_str());
+}
+
+#[test]
+fn test_save_to_geoparquet() {
+    let mut collection = LasOutlineFeatureCollection::new();
+    for i in 0..3 {
+        let x = i as f64;
+        let mut properties = Map::new();
+        properties.insert("SourceFile".to_string(), json!(format!("tile_{}.las", i)));
+        properties.insert("number_of_points".to_string(), json!(100 + i));
+        collection.add_feature(Feature {
+            geometry: Some(Geometry::new(Value::Polygon(vec![vec![
+                vec![x, 0.0],
+                vec![x + 1.0, 0.0],
+                vec![x + 1.0, 1.0],
+                vec![x, 1.0],
+                vec![x, 0.0],
+            ]]))),
+            properties: Some(properties),
+            id: None,
+            bbox: None,
+            foreign_members: None,
+        });
+    }
+    let temp_dir = tempfile::tempdir().unwrap();
+    let output_path = temp_dir.path().join("tiles.parquet");
+    collection
+        .save_to_geoparquet(output_path.to_str().unwrap())
+        .unwrap();
+
+    let bytes = fs::read(&output_path).unwrap();
+    assert_eq!(&bytes[..4], b"PAR1");
+    assert_eq!(&bytes[bytes.len() - 4..], b"PAR1");
+    let footer_len =
+        u32::from_le_bytes(bytes[bytes.len() - 8..bytes.len() - 4].try_into().unwrap()) as usize;
+    let footer = String::from_utf8_lossy(&bytes[bytes.len() - 8 - footer_len..bytes.len() - 8]);
+    assert!(footer.contains("number_of_points"));
+    assert!(footer.contains(r#""primary_column":"geometry""#));
+    assert!(footer.contains(r#""bbox":[0.0,0.0,3.0,1.0]"#));
+    assert!(footer.contains(r#""xmin":["bbox","xmin"]"#));
+}
+
+#[test]
+fn test_save_to_kml_and_kmz() {
+    let mut collection = LasOutlineFeatureCollection::new();
+    for (i, dir) in ["/data/2020/north", "/data/2020/south", "/data/2021"]
+        .iter()
+        .enumerate()
+    {
+        let x = i as f64;
+        let mut properties = Map::new();
+        properties.insert("SourceFile".to_string(), json!(format!("tile_{}.las", i)));
+        properties.insert("SourceFileDir".to_string(), json!(dir));
+        properties.insert("date".to_string(), json!(format!("202{}-01-01", i)));
+        properties.insert("notes".to_string(), json!("<b>&</b>"));
+        collection.add_feature(Feature {
+            geometry: Some(Geometry::new(Value::Polygon(vec![vec![
+                vec![x, 0.0],
+                vec![x + 1.0, 0.0],
+                vec![x + 1.0, 1.0],
+                vec![x, 0.0],
+            ]]))),
+            properties: Some(properties),
+            id: None,
+            bbox: None,
+            foreign_members: None,
+        });
+    }
+    let temp_dir = tempfile::tempdir().unwrap();
+    let kml_path = temp_dir.path().join("coverage.kml");
+    collection
+        .save_to_kml(kml_path.to_str().unwrap(), Some("date"))
+        .unwrap();
+    let kml = fs::read_to_string(&kml_path).unwrap();
+    assert!(kml.contains("<Document><name>coverage</name>"));
+    assert!(kml.contains(
+        "<Folder><name>data</name>\n<Folder><name>2020</name>\n<Folder><name>north</name>"
+    ));
+    assert!(kml.contains("<styleUrl>#class2</styleUrl>"));
+    assert!(kml.contains("<coordinates>0,0 1,0 1,1 0,0</coordinates>"));
+    assert!(kml.contains("&lt;td&gt;&amp;lt;b&amp;gt;&amp;amp;&amp;lt;/b&amp;gt;&lt;/td&gt;"));
+
+    let kmz_path = temp_dir.path().join("coverage.kmz");
+    collection
+        .save_to_kmz(kmz_path.to_str().unwrap(), Some("date"))
+        .unwrap();
+    let mut archive = zip::ZipArchive::new(fs::File::open(&kmz_path).unwrap()).unwrap();
+    let mut doc = String::new();
+    std::io::Read::read_to_string(&mut archive.by_name("doc.kml").unwrap(), &mut doc).unwrap();
+    assert_eq!(doc, kml);
+}
+
+#[test]
+fn test_save_to_sql() {
+    let mut collection = LasOutlineFeatureCollection::new();
+    for i in 0..2 {
+        let x = i as f64;
+        let mut properties = Map::new();
+        properties.insert(
+            "SourceFile".to_string(),
+            json!(format!("/data/tile_{}.las", i)),
+        );
+        properties.insert("number_of_points".to_string(), json!(100 + i));
+        collection.add_feature(Feature {
+            geometry: Some(Geometry::new(Value::Polygon(vec![vec![
+                vec![x, 0.0],
+                vec![x + 1.0, 0.0],
+                vec![x + 1.0, 1.0],
+                vec![x, 0.0],
+            ]]))),
+            properties: Some(properties),
+            id: None,
+            bbox: None,
+            foreign_members: None,
+        });
+    }
+    let temp_dir = tempfile::tempdir().unwrap();
+    let output_path = temp_dir.path().join("Tiles.sql");
+    collection
+        .save_to_sql(
+            output_path.to_str().unwrap(),
+            &las_poly::SqlOptions::default(),
+        )
+        .unwrap();
+    let sql = fs::read_to_string(&output_path).unwrap();
+    assert!(sql.contains("CREATE TABLE IF NOT EXISTS \"tiles\""));
+    assert!(sql.contains("ALTER TABLE \"tiles\" ADD COLUMN IF NOT EXISTS \"SourceFile\" text;"));
+    assert!(sql.contains("('/data/tile_1.las', 101, '0103000020E6100000"));
+    assert!(!sql.contains("ON CONFLICT"));
+
+    let options = las_poly::SqlOptions {
+        table: Some("lidar.catalogue".to_string()),
+        geometry: las_poly::SqlGeometry::Wkt,
+        upsert: true,
+    };
+    collection
+        .save_to_sql(output_path.to_str().unwrap(), &options)
+        .unwrap();
+    let sql = fs::read_to_string(&output_path).unwrap();
+    assert!(sql.contains(
+        "INSERT INTO \"lidar\".\"catalogue\" (\"SourceFile\", \"number_of_points\", geom) VALUES"
+    ));
+    assert!(sql.contains("ST_GeomFromText('POLYGON((1 0,2 0,2 1,1 0))', 4326)"));
+    assert!(sql.contains("ON CONFLICT (\"SourceFile\") DO UPDATE SET"));
+}
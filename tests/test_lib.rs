@@ -1,7 +1,10 @@
 use approx::assert_abs_diff_eq;
 use geojson::{GeoJson, Value};
-use las::{Header, Point, Writer};
-use las_poly::{create_polygon, process_folder, ProcessConfig};
+use las::{point::Classification, Header, Point, Writer};
+use las_poly::{
+    create_polygon, create_polygon_with_config, process_folder, repair_file, HashAlgorithm,
+    OutlierFilter, OutputFormat, PointBudget, PointFilter, ProcessConfig, Statistic,
+};
 use proj::Proj;
 use std::fs::{self, File};
 use std::io::Write;
@@ -153,6 +156,7 @@ fn test_process_folder_no_group_by_folder() {
         recurse: true,
         guess_crs: true,
         output_file: Some(output_path.to_str().unwrap().to_string()),
+        ..Default::default()
     };
 
     let result = process_folder(config);
@@ -239,6 +243,7 @@ fn test_integration_workflow_group_by_folder() {
         recurse: true,
         guess_crs: true,
         output_file: Some(output_path.to_str().unwrap().to_string()),
+        ..Default::default()
     };
 
     let result = process_folder(config);
@@ -335,6 +340,7 @@ fn test_process_folder_group_by_folder_missing_sourcefiledir() {
         recurse: true,
         guess_crs: true,
         output_file: Some(output_path.to_str().unwrap().to_string()),
+        ..Default::default()
     };
 
     let result = process_folder(config);
@@ -536,6 +542,7 @@ fn test_process_folder_with_merge_if_shared_vertex() {
         recurse: true,
         guess_crs: true,
         output_file: Some(output_path.to_str().unwrap().to_string()),
+        ..Default::default()
     };
 
     let result = process_folder(config);
@@ -672,6 +679,7 @@ fn test_process_folder_with_various_scenarios() {
                 .unwrap()
                 .to_string(),
         ),
+        ..Default::default()
     };
     process_folder(config).unwrap();
     let output_path = temp_dir.path().join("output_shared_vertex.geojson");
@@ -701,6 +709,7 @@ fn test_process_folder_with_various_scenarios() {
                 .unwrap()
                 .to_string(),
         ),
+        ..Default::default()
     };
     process_folder(config).unwrap();
     let output_path = temp_dir.path().join("output_overlap.geojson");
@@ -730,6 +739,7 @@ fn test_process_folder_with_various_scenarios() {
                 .unwrap()
                 .to_string(),
         ),
+        ..Default::default()
     };
     process_folder(config).unwrap();
     let output_path = temp_dir.path().join("output_shared_vertex_overlap.geojson");
@@ -759,6 +769,7 @@ fn test_process_folder_with_various_scenarios() {
                 .unwrap()
                 .to_string(),
         ),
+        ..Default::default()
     };
     process_folder(config).unwrap();
     let output_path = temp_dir.path().join("output_no_merge.geojson");
@@ -805,6 +816,7 @@ fn test_process_folder_with_single_point_las() {
                 .unwrap()
                 .to_string(),
         ),
+        ..Default::default()
     };
     process_folder(config).unwrap();
     let output_path = temp_dir.path().join("output_single_point.geojson");
@@ -922,6 +934,7 @@ fn test_process_folder_with_laz_files() {
                 .unwrap()
                 .to_string(),
         ),
+        ..Default::default()
     };
     process_folder(config).unwrap();
     let output_path = temp_dir.path().join("output_laz.geojson");
@@ -934,3 +947,944 @@ fn test_process_folder_with_laz_files() {
         panic!("Expected a FeatureCollection");
     }
 }
+
+fn grid_points(size: usize) -> Vec<Point> {
+    let mut points = Vec::with_capacity(size * size);
+    for i in 0..size {
+        for j in 0..size {
+            points.push(Point {
+                x: 170.0 + i as f64 * 0.01,
+                y: -40.0 + j as f64 * 0.01,
+                z: 0.0,
+                ..Default::default()
+            });
+        }
+    }
+    points
+}
+
+#[test]
+fn test_create_polygon_sampled_outline() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("grid.las");
+    create_las_file(file_path.to_str().unwrap(), grid_points(40));
+
+    let config = ProcessConfig {
+        guess_crs: true,
+        sample_points: Some(PointBudget::Count(100)),
+        ..Default::default()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties.get("sampled_points").unwrap(), 100);
+    assert_eq!(properties.get("number_of_points").unwrap(), 1600);
+
+    if let Some(geojson::Geometry {
+        value: Value::Polygon(polygon),
+        ..
+    }) = feature.geometry
+    {
+        // The sampled hull lies within the full hull
+        for coord in &polygon[0] {
+            assert!(coord[0] >= 170.0 - 1e-6 && coord[0] <= 170.39 + 1e-6);
+            assert!(coord[1] >= -40.0 - 1e-6 && coord[1] <= -39.61 + 1e-6);
+        }
+    } else {
+        panic!("Expected a Polygon geometry");
+    }
+}
+
+#[test]
+fn test_create_polygon_sampled_outline_laz() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("grid.laz");
+    create_laz_file(file_path.to_str().unwrap(), grid_points(40));
+
+    let config = ProcessConfig {
+        guess_crs: true,
+        sample_points: Some(PointBudget::Fraction(0.1)),
+        ..Default::default()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    // LAZ files are sampled in whole chunks, and this file fits in a single chunk
+    assert_eq!(properties.get("sampled_points").unwrap(), 1600);
+}
+
+#[test]
+fn test_process_folder_split_large_files() {
+    let temp_dir = setup();
+    let folder_path = temp_dir.path().join("tiles");
+    fs::create_dir_all(&folder_path).unwrap();
+    create_las_file(
+        folder_path.join("grid.las").to_str().unwrap(),
+        grid_points(40),
+    );
+    create_laz_file(
+        folder_path.join("grid.laz").to_str().unwrap(),
+        grid_points(40),
+    );
+
+    let read_outlines = |split_points: Option<u64>, name: &str| {
+        let output_path = temp_dir.path().join(name);
+        let config = ProcessConfig {
+            folder_path: folder_path.to_str().unwrap().to_string(),
+            use_detailed_outline: true,
+            guess_crs: true,
+            output_file: Some(output_path.to_str().unwrap().to_string()),
+            split_points,
+            ..Default::default()
+        };
+        process_folder(config).unwrap();
+        let geojson: GeoJson = fs::read_to_string(&output_path).unwrap().parse().unwrap();
+        let GeoJson::FeatureCollection(fc) = geojson else {
+            panic!("Expected a FeatureCollection");
+        };
+        let mut outlines: Vec<(String, Value)> = fc
+            .features
+            .into_iter()
+            .map(|f| {
+                let source_file = f.properties.as_ref().unwrap()["SourceFile"]
+                    .as_str()
+                    .unwrap()
+                    .to_string();
+                (source_file, f.geometry.unwrap().value)
+            })
+            .collect();
+        outlines.sort_by(|a, b| a.0.cmp(&b.0));
+        outlines
+    };
+
+    let whole = read_outlines(None, "whole.geojson");
+    let split = read_outlines(Some(100), "split.geojson");
+    assert_eq!(whole.len(), 2);
+    assert_eq!(whole, split);
+}
+
+#[test]
+fn test_process_folder_split_file_whole_file_properties() {
+    let temp_dir = setup();
+    let folder_path = temp_dir.path().join("tiles");
+    fs::create_dir_all(&folder_path).unwrap();
+    create_las_file(
+        folder_path.join("grid.las").to_str().unwrap(),
+        grid_points(40),
+    );
+
+    let read_properties = |split_points: Option<u64>, name: &str| {
+        let output_path = temp_dir.path().join(name);
+        let config = ProcessConfig {
+            folder_path: folder_path.to_str().unwrap().to_string(),
+            use_detailed_outline: true,
+            guess_crs: true,
+            output_file: Some(output_path.to_str().unwrap().to_string()),
+            split_points,
+            full_header: true,
+            hash: Some(HashAlgorithm::Sha256),
+            validate: Some(0.0),
+            ..Default::default()
+        };
+        process_folder(config).unwrap();
+        let geojson: GeoJson = fs::read_to_string(&output_path).unwrap().parse().unwrap();
+        let GeoJson::FeatureCollection(fc) = geojson else {
+            panic!("Expected a FeatureCollection");
+        };
+        fc.features[0].properties.clone().unwrap()
+    };
+
+    let whole = read_properties(None, "whole.geojson");
+    let split = read_properties(Some(100), "split.geojson");
+    assert_eq!(split["validation_status"], "valid");
+    assert_eq!(split["point_format"], 0);
+    assert!(split["content_hash"]
+        .as_str()
+        .unwrap()
+        .starts_with("sha256:"));
+    for key in ["validation_status", "point_format", "content_hash"] {
+        assert_eq!(whole[key], split[key], "{key}");
+    }
+}
+
+#[test]
+fn test_create_polygon_excludes_noise_points() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("noisy.las");
+    let mut points = grid_points(10);
+    points.push(Point {
+        x: 175.0,
+        y: -35.0,
+        z: 0.0,
+        classification: Classification::LowPoint,
+        ..Default::default()
+    });
+    create_las_file(file_path.to_str().unwrap(), points);
+
+    let outline_max_x = |point_filter: PointFilter| {
+        let config = ProcessConfig {
+            use_detailed_outline: true,
+            guess_crs: true,
+            point_filter,
+            ..Default::default()
+        };
+        let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+        let Some(geojson::Geometry {
+            value: Value::Polygon(polygon),
+            ..
+        }) = feature.geometry
+        else {
+            panic!("Expected a Polygon geometry");
+        };
+        polygon[0].iter().map(|c| c[0]).fold(f64::MIN, f64::max)
+    };
+
+    assert_abs_diff_eq!(outline_max_x(PointFilter::default()), 175.0, epsilon = 1e-6);
+    let without_noise = outline_max_x(PointFilter {
+        exclude_classes: vec![7, 18],
+        ..Default::default()
+    });
+    assert_abs_diff_eq!(without_noise, 170.09, epsilon = 1e-6);
+}
+
+#[test]
+fn test_create_polygon_removes_outliers() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("spike.las");
+    let mut points = grid_points(10);
+    points.push(Point {
+        x: 172.0,
+        y: -40.0,
+        z: 0.0,
+        ..Default::default()
+    });
+    create_las_file(file_path.to_str().unwrap(), points);
+
+    let config = ProcessConfig {
+        use_detailed_outline: true,
+        guess_crs: true,
+        outlier_filter: Some(OutlierFilter::Density {
+            cell_size: 0.05,
+            min_points: 2,
+        }),
+        ..Default::default()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties.get("outliers_removed").unwrap(), 1);
+    let Some(geojson::Geometry {
+        value: Value::Polygon(polygon),
+        ..
+    }) = feature.geometry
+    else {
+        panic!("Expected a Polygon geometry");
+    };
+    assert!(polygon[0].iter().all(|c| c[0] < 170.1));
+}
+
+#[test]
+fn test_create_polygon_statistics() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("stats.las");
+    let points = (0..10)
+        .map(|i| Point {
+            x: 170.0 + i as f64 * 0.01,
+            y: -40.0 + (i % 3) as f64 * 0.01,
+            z: i as f64,
+            intensity: 100 + i,
+            return_number: 1,
+            number_of_returns: 1,
+            // The highest point is noise
+            classification: las::point::Classification::new(if i == 9 { 7 } else { 2 }).unwrap(),
+            ..Default::default()
+        })
+        .collect();
+    create_las_file(file_path.to_str().unwrap(), points);
+
+    let statistics = vec![Statistic::Z, Statistic::Intensity, Statistic::Attributes];
+    let config = ProcessConfig {
+        use_detailed_outline: true,
+        guess_crs: true,
+        statistics: statistics.clone(),
+        ..Default::default()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties["z_min"], 0.0);
+    assert_eq!(properties["z_max"], 9.0);
+    assert_eq!(properties["z_mean"], 4.5);
+    assert_eq!(properties["intensity_min"], 100);
+    assert_eq!(properties["intensity_max"], 109);
+    assert_eq!(properties["has_rgb"], false);
+    assert!(!properties.contains_key("gps_time_min"));
+
+    // Statistics describe the whole file, not just the filtered points of the outline
+    let filtered = ProcessConfig {
+        point_filter: PointFilter {
+            exclude_classes: vec![7],
+            ..Default::default()
+        },
+        ..config.clone()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &filtered).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties["z_max"], 9.0);
+    assert_eq!(properties["z_mean"], 4.5);
+    assert_eq!(properties["intensity_max"], 109);
+
+    // A sample falls back to the header statistics
+    let sampled = ProcessConfig {
+        sample_points: Some(PointBudget::Count(3)),
+        ..config.clone()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &sampled).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties["sampled_points"], 3);
+    assert_eq!(properties["z_min"], 0.0);
+    assert_eq!(properties["z_max"], 9.0);
+    assert!(!properties.contains_key("z_mean"));
+    assert!(!properties.contains_key("intensity_min"));
+
+    // Without reading the points, only the header statistics are available
+    let config = ProcessConfig {
+        use_detailed_outline: false,
+        ..config
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties["z_max"], 9.0);
+    assert!(!properties.contains_key("z_mean"));
+    assert!(!properties.contains_key("intensity_min"));
+}
+
+#[test]
+fn test_create_polygon_classification_counts() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("classes.las");
+    let points = grid_points(10)
+        .into_iter()
+        .enumerate()
+        .map(|(i, point)| Point {
+            classification: las::point::Classification::new(if i < 5 { 7 } else { 2 }).unwrap(),
+            is_withheld: i < 5,
+            ..point
+        })
+        .collect();
+    create_las_file(file_path.to_str().unwrap(), points);
+
+    // The noise is counted even though the outline leaves it out
+    let config = ProcessConfig {
+        use_detailed_outline: true,
+        guess_crs: true,
+        statistics: vec![Statistic::Classification],
+        point_filter: PointFilter {
+            exclude_classes: vec![7],
+            exclude_withheld: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties["classification_counts"]["7"], 5);
+    assert_eq!(properties["classification_counts"]["2"], 95);
+    assert_eq!(properties["flag_counts"]["withheld"], 5);
+
+    // A sample does not give file counts
+    let config = ProcessConfig {
+        sample_points: Some(PointBudget::Count(10)),
+        ..config
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert!(!properties.contains_key("classification_counts"));
+    assert!(!properties.contains_key("flag_counts"));
+}
+
+#[test]
+fn test_create_polygon_area_and_density() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("grid.las");
+    create_las_file(file_path.to_str().unwrap(), grid_points(40));
+
+    let config = ProcessConfig {
+        use_detailed_outline: true,
+        guess_crs: true,
+        density_cell_size: Some(0.1),
+        ..Default::default()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    let area_km2 = properties["area_km2"].as_f64().unwrap();
+    assert!(area_km2 > 0.0);
+    assert!(properties["perimeter_m"].as_f64().unwrap() > 0.0);
+    let point_density = properties["point_density"].as_f64().unwrap();
+    assert!((point_density - 1600.0 / (area_km2 * 1e6)).abs() < 1e-9);
+    // The occupied cells cover at least the hull, so the cell density is no higher
+    assert!(properties["occupied_area_m2"].as_f64().unwrap() >= area_km2 * 1e6);
+    assert!(properties["cell_density"].as_f64().unwrap() <= point_density);
+
+    // The header outline has no occupied cells
+    let config = ProcessConfig {
+        use_detailed_outline: false,
+        ..config
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert!(properties.contains_key("point_density"));
+    assert!(!properties.contains_key("cell_density"));
+}
+
+#[test]
+fn test_create_polygon_point_spacing() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("grid.las");
+    create_las_file(file_path.to_str().unwrap(), grid_points(40));
+
+    let config = ProcessConfig {
+        guess_crs: true,
+        point_spacing: Some(5),
+        ..Default::default()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert!((properties["point_spacing"].as_f64().unwrap() - 0.01).abs() < 1e-6);
+    assert!((properties["point_spacing_p95"].as_f64().unwrap() - 0.01).abs() < 1e-6);
+    assert_eq!(properties["point_spacing_samples"], 800);
+}
+
+#[test]
+fn test_create_polygon_full_header() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("grid.las");
+    create_las_file(file_path.to_str().unwrap(), grid_points(10));
+
+    let config = ProcessConfig {
+        guess_crs: true,
+        full_header: true,
+        ..Default::default()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties["point_format"], 0);
+    assert_eq!(properties["point_record_length"], 20);
+    assert_eq!(properties["x_scale"], 0.001);
+    assert_eq!(properties["min_x"], 170.0);
+    assert!((properties["max_y"].as_f64().unwrap() + 39.91).abs() < 0.002);
+    assert_eq!(properties["vlr_count"], 0);
+    assert_eq!(properties["vlrs"], serde_json::json!([]));
+    assert!(properties["guid"].is_string());
+    assert!(!properties.contains_key("extra_bytes"));
+
+    // The full header is opt-in
+    let feature = create_polygon(file_path.to_str().unwrap(), false, true).unwrap();
+    assert!(!feature.properties.unwrap().contains_key("point_format"));
+}
+
+#[test]
+fn test_create_polygon_file_properties() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("grid.las");
+    create_las_file(file_path.to_str().unwrap(), grid_points(10));
+    let file_size = std::fs::metadata(&file_path).unwrap().len();
+
+    let feature = create_polygon(file_path.to_str().unwrap(), false, true).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties["file_size"], file_size);
+    assert!(properties["modified"].as_str().unwrap().ends_with('Z'));
+    assert!(!properties.contains_key("content_hash"));
+
+    let config = ProcessConfig {
+        guess_crs: true,
+        hash: Some(HashAlgorithm::Sha256),
+        ..Default::default()
+    };
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    let content_hash = properties["content_hash"].as_str().unwrap();
+    assert!(content_hash.starts_with("sha256:"));
+    assert_eq!(content_hash.len(), 7 + 64);
+}
+
+#[test]
+fn test_create_polygon_validate_header() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("grid.las");
+    create_las_file(file_path.to_str().unwrap(), grid_points(10));
+    let config = ProcessConfig {
+        guess_crs: true,
+        validate: Some(0.0),
+        ..Default::default()
+    };
+
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties["validation_status"], "valid");
+    assert!(!properties.contains_key("validation_issues"));
+
+    // Overwrite the max X of the header, at byte 179 of a LAS 1.2 header
+    let mut bytes = fs::read(&file_path).unwrap();
+    bytes[179..187].copy_from_slice(&171.0f64.to_le_bytes());
+    fs::write(&file_path, bytes).unwrap();
+
+    let feature = create_polygon_with_config(file_path.to_str().unwrap(), &config).unwrap();
+    let properties = feature.properties.unwrap();
+    assert_eq!(properties["validation_status"], "invalid");
+    assert!(properties["validation_issues"]
+        .as_str()
+        .unwrap()
+        .starts_with("max_x is 171 in the header"));
+}
+
+#[test]
+fn test_repair_file() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("grid.las");
+    let file = file_path.to_str().unwrap();
+    create_las_file(file, grid_points(10));
+    let max_x = las::Reader::from_path(file)
+        .unwrap()
+        .header()
+        .bounds()
+        .max
+        .x;
+
+    // Overwrite the max X of the header, at byte 179 of a LAS 1.2 header
+    let mut bytes = fs::read(&file_path).unwrap();
+    bytes[179..187].copy_from_slice(&171.0f64.to_le_bytes());
+    fs::write(&file_path, bytes).unwrap();
+
+    let repaired_path = temp_dir.path().join("grid_repaired.las");
+    let repaired = repaired_path.to_str().unwrap();
+    let issues = repair_file(file, repaired).unwrap();
+    assert_eq!(issues.len(), 1);
+    assert!(issues[0].starts_with("max_x is 171 in the header"));
+    let reader = las::Reader::from_path(repaired).unwrap();
+    assert_abs_diff_eq!(reader.header().bounds().max.x, max_x, epsilon = 0.01);
+    assert_eq!(reader.header().number_of_points(), 100);
+    assert!(repair_file(repaired, repaired).unwrap().is_empty());
+
+    // In place
+    assert_eq!(repair_file(file, file).unwrap().len(), 1);
+    // A correct file is left untouched in place, and copied as is otherwise
+    let modified = fs::metadata(&file_path).unwrap().modified().unwrap();
+    assert!(repair_file(file, file).unwrap().is_empty());
+    assert_eq!(
+        fs::metadata(&file_path).unwrap().modified().unwrap(),
+        modified
+    );
+    assert!(!temp_dir.path().join("grid.repairing.las").exists());
+    let copy_path = temp_dir.path().join("grid_copy.las");
+    assert!(repair_file(file, copy_path.to_str().unwrap())
+        .unwrap()
+        .is_empty());
+    assert_eq!(fs::read(&copy_path).unwrap(), fs::read(&file_path).unwrap());
+
+    // The same file spelled differently is still repaired in place
+    let dotted = temp_dir.path().join(".").join("grid.las");
+    assert!(repair_file(file, dotted.to_str().unwrap())
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        las::Reader::from_path(file)
+            .unwrap()
+            .header()
+            .number_of_points(),
+        100
+    );
+}
+
+#[test]
+fn test_repair_file_truncated() {
+    let temp_dir = setup();
+    let file_path = temp_dir.path().join("grid.las");
+    let file = file_path.to_str().unwrap();
+    create_las_file(file, grid_points(10));
+    // Cut off the last points, the header still counts all of them
+    let mut bytes = fs::read(&file_path).unwrap();
+    bytes.truncate(bytes.len() - 100);
+    fs::write(&file_path, &bytes).unwrap();
+
+    assert!(repair_file(file, file).is_err());
+    assert_eq!(fs::read(&file_path).unwrap(), bytes);
+    assert!(!temp_dir.path().join("grid.repairing.las").exists());
+
+    let repaired_path = temp_dir.path().join("grid_repaired.las");
+    assert!(repair_file(file, repaired_path.to_str().unwrap()).is_err());
+    assert!(!repaired_path.exists());
+}
+
+#[test]
+fn test_process_folder_geopackage() {
+    let temp_dir = setup();
+    let folder_path = temp_dir.path().join("tiles");
+    fs::create_dir_all(&folder_path).unwrap();
+    for (name, offset) in [("a.las", 0.0), ("b.las", 0.09)] {
+        let points = grid_points(10)
+            .into_iter()
+            .map(|p| Point {
+                x: p.x + offset,
+                ..p
+            })
+            .collect();
+        create_las_file(folder_path.join(name).to_str().unwrap(), points);
+    }
+    let output_path = temp_dir.path().join("tiles.gpkg");
+    let config = ProcessConfig {
+        folder_path: folder_path.to_str().unwrap().to_string(),
+        guess_crs: true,
+        output_file: Some(output_path.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+    process_folder(config).unwrap();
+
+    let connection = rusqlite::Connection::open(&output_path).unwrap();
+    let count = |sql: &str| -> i64 { connection.query_row(sql, [], |row| row.get(0)).unwrap() };
+    assert_eq!(count("PRAGMA application_id"), 0x4750_4B47);
+    assert_eq!(count("SELECT COUNT(*) FROM footprints"), 2);
+    assert_eq!(count("SELECT COUNT(*) FROM merged"), 1);
+    assert_eq!(count("SELECT COUNT(*) FROM rtree_footprints_geom"), 2);
+    assert_eq!(count("SELECT COUNT(*) FROM gpkg_contents"), 2);
+    assert_eq!(
+        count("SELECT SUM(number_of_points) FROM footprints WHERE typeof(number_of_points) = 'integer'"),
+        200
+    );
+    let column_type: String = connection
+        .query_row(
+            "SELECT type FROM pragma_table_info('footprints') WHERE name = 'area_km2'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(column_type, "DOUBLE");
+    let geometry: Vec<u8> = connection
+        .query_row("SELECT geom FROM merged", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(&geometry[..2], b"GP");
+    let max_x: f64 = connection
+        .query_row(
+            "SELECT max_x FROM gpkg_contents WHERE table_name = 'merged'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_abs_diff_eq!(max_x, 170.18, epsilon = 0.01);
+
+    // An explicit format wins over the extension
+    let output_path = temp_dir.path().join("tiles.out");
+    let config = ProcessConfig {
+        folder_path: folder_path.to_str().unwrap().to_string(),
+        guess_crs: true,
+        output_file: Some(output_path.to_str().unwrap().to_string()),
+        format: Some(OutputFormat::GeoPackage),
+        ..Default::default()
+    };
+    process_folder(config).unwrap();
+    assert_eq!(&fs::read(&output_path).unwrap()[..6], b"SQLite");
+}
+
+#[test]
+fn test_process_folder_stream_file() {
+    let temp_dir = setup();
+    let folder_path = temp_dir.path().join("tiles");
+    fs::create_dir_all(&folder_path).unwrap();
+    for (name, offset) in [("a.las", 0.0), ("b.las", 0.09)] {
+        let points = grid_points(10)
+            .into_iter()
+            .map(|p| Point {
+                x: p.x + offset,
+                ..p
+            })
+            .collect();
+        create_las_file(folder_path.join(name).to_str().unwrap(), points);
+    }
+    let output_path = temp_dir.path().join("tiles.geojson");
+    let stream_path = temp_dir.path().join("tiles.geojsonl");
+    let config = ProcessConfig {
+        folder_path: folder_path.to_str().unwrap().to_string(),
+        guess_crs: true,
+        merge_tiled: true,
+        output_file: Some(output_path.to_str().unwrap().to_string()),
+        stream_file: Some(stream_path.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+    process_folder(config).unwrap();
+
+    // Every file is streamed, while the output holds the merged outline
+    let stream = fs::read_to_string(&stream_path).unwrap();
+    let mut source_files: Vec<String> = stream
+        .lines()
+        .map(|line| {
+            let feature: geojson::Feature = line.parse().unwrap();
+            let source_file = feature.property("SourceFile").unwrap().as_str().unwrap();
+            Path::new(source_file)
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect();
+    source_files.sort();
+    assert_eq!(source_files, vec!["a.las", "b.las"]);
+    let output = fs::read_to_string(&output_path).unwrap();
+    let merged: geojson::FeatureCollection = output.parse().unwrap();
+    assert_eq!(merged.features.len(), 1);
+}
+
+#[test]
+fn test_process_folder_merged_statistics() {
+    let temp_dir = setup();
+    let folder_path = temp_dir.path().join("tiles");
+    fs::create_dir_all(&folder_path).unwrap();
+    for (name, size, offset, z) in [("a.las", 10, 0.0, 0.0), ("b.las", 5, 0.05, 6.0)] {
+        let points = grid_points(size)
+            .into_iter()
+            .map(|p| Point {
+                x: p.x + offset,
+                z,
+                ..p
+            })
+            .collect();
+        create_las_file(folder_path.join(name).to_str().unwrap(), points);
+    }
+    let output_path = temp_dir.path().join("tiles.geojson");
+    let config = ProcessConfig {
+        folder_path: folder_path.to_str().unwrap().to_string(),
+        use_detailed_outline: true,
+        guess_crs: true,
+        merge_if_overlap: true,
+        output_file: Some(output_path.to_str().unwrap().to_string()),
+        statistics: vec![Statistic::Z],
+        full_header: true,
+        validate: Some(0.0),
+        ..Default::default()
+    };
+    process_folder(config).unwrap();
+
+    let output = fs::read_to_string(&output_path).unwrap();
+    let merged: geojson::FeatureCollection = output.parse().unwrap();
+    assert_eq!(merged.features.len(), 1);
+    let properties = merged.features[0].properties.as_ref().unwrap();
+    assert_eq!(properties["number_of_points"], 125);
+    assert_eq!(properties["z_min"], 0.0);
+    assert_eq!(properties["z_max"], 6.0);
+    // Weighted by the number of points of each file
+    assert_abs_diff_eq!(properties["z_mean"].as_f64().unwrap(), 1.2, epsilon = 1e-9);
+    assert_eq!(properties["validation_status"], "valid");
+    assert!(properties["vlrs"].is_array());
+}
+
+#[test]
+fn test_process_folder_bad_stream_file() {
+    let temp_dir = setup();
+    let folder_path = temp_dir.path().join("tiles");
+    fs::create_dir_all(&folder_path).unwrap();
+    create_las_file(folder_path.join("a.las").to_str().unwrap(), grid_points(10));
+    let output_path = temp_dir.path().join("tiles.geojson");
+    let config = ProcessConfig {
+        folder_path: folder_path.to_str().unwrap().to_string(),
+        guess_crs: true,
+        output_file: Some(output_path.to_str().unwrap().to_string()),
+        stream_file: Some(
+            temp_dir
+                .path()
+                .join("missing/tiles.geojsonl")
+                .to_str()
+                .unwrap()
+                .to_string(),
+        ),
+        ..Default::default()
+    };
+    assert!(process_folder(config).is_err());
+    assert!(!output_path.exists());
+}
+
+#[test]
+fn test_process_folder_tindex() {
+    let temp_dir = setup();
+    let folder_path = temp_dir.path().join("tiles");
+    fs::create_dir_all(&folder_path).unwrap();
+    for (name, offset) in [("a.las", 0.0), ("b.las", 0.09)] {
+        let points = grid_points(10)
+            .into_iter()
+            .map(|p| Point {
+                x: p.x + offset,
+                ..p
+            })
+            .collect();
+        create_las_file(folder_path.join(name).to_str().unwrap(), points);
+    }
+    let output_path = temp_dir.path().join("tiles.geojson");
+    let config = ProcessConfig {
+        folder_path: folder_path.to_str().unwrap().to_string(),
+        guess_crs: true,
+        output_file: Some(output_path.to_str().unwrap().to_string()),
+        tindex: true,
+        tindex_timestamps: true,
+        ..Default::default()
+    };
+    process_folder(config).unwrap();
+
+    let output = fs::read_to_string(&output_path).unwrap();
+    let collection: geojson::FeatureCollection = output.parse().unwrap();
+    assert_eq!(collection.features.len(), 2);
+    for feature in &collection.features {
+        let properties = feature.properties.as_ref().unwrap();
+        let mut keys: Vec<&str> = properties.keys().map(String::as_str).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["created", "location", "modified", "srs"]);
+        let location = properties["location"].as_str().unwrap();
+        assert!(Path::new(location).exists());
+        assert!(!properties["srs"].as_str().unwrap().is_empty());
+        assert!(properties["modified"].as_str().unwrap().ends_with('Z'));
+    }
+
+    // A GeoPackage tile index has the single layer of pdal tindex
+    let output_path = temp_dir.path().join("tiles.gpkg");
+    let config = ProcessConfig {
+        folder_path: folder_path.to_str().unwrap().to_string(),
+        guess_crs: true,
+        output_file: Some(output_path.to_str().unwrap().to_string()),
+        tindex: true,
+        ..Default::default()
+    };
+    process_folder(config).unwrap();
+    let connection = rusqlite::Connection::open(&output_path).unwrap();
+    let count: i64 = connection
+        .query_row("SELECT COUNT(*) FROM pdal", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(count, 2);
+}
+
+#[test]
+fn test_create_polygon_tindex_geotiff_srs() {
+    let config = ProcessConfig {
+        tindex: true,
+        ..Default::default()
+    };
+    let feature = create_polygon_with_config("tests/crs/merged.las", &config).unwrap();
+    // The CRS of the GeoTIFF keys is written as WKT, not as an identifier
+    let srs = feature.property("srs").unwrap().as_str().unwrap();
+    assert!(
+        srs.starts_with("PROJCS[") || srs.starts_with("GEOGCS["),
+        "srs is not WKT: {}",
+        srs
+    );
+}
+
+#[test]
+fn test_process_folder_stac_dir() {
+    let temp_dir = setup();
+    for (folder, name) in [("north", "a.las"), ("north", "a.laz"), ("south", "b.las")] {
+        let folder_path = temp_dir.path().join("tiles").join(folder);
+        fs::create_dir_all(&folder_path).unwrap();
+        create_las_file(folder_path.join(name).to_str().unwrap(), grid_points(10));
+    }
+    let stac_dir = temp_dir.path().join("stac");
+    let config = ProcessConfig {
+        folder_path: temp_dir.path().join("tiles").to_str().unwrap().to_string(),
+        recurse: true,
+        guess_crs: true,
+        output_file: Some(
+            temp_dir
+                .path()
+                .join("tiles.geojson")
+                .to_str()
+                .unwrap()
+                .to_string(),
+        ),
+        stac_dir: Some(stac_dir.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+    process_folder(config).unwrap();
+
+    let read_json = |path: PathBuf| -> serde_json::Value {
+        serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+    };
+    let catalog = read_json(stac_dir.join("catalog.json"));
+    assert_eq!(catalog["type"], "Catalog");
+    let children: Vec<&str> = catalog["links"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|link| link["rel"] == "child")
+        .map(|link| link["href"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        children,
+        vec!["./north/collection.json", "./south/collection.json"]
+    );
+
+    let collection = read_json(stac_dir.join("north").join("collection.json"));
+    let items: Vec<&str> = collection["links"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|link| link["rel"] == "item")
+        .map(|link| link["href"].as_str().unwrap())
+        .collect();
+    // Files with the same stem get unique ids
+    assert_eq!(items.len(), 2);
+    assert!(items.contains(&"./a-2/a-2.json"));
+    assert_eq!(
+        collection["extent"]["spatial"]["bbox"][0]
+            .as_array()
+            .unwrap()
+            .len(),
+        4
+    );
+
+    let item = read_json(stac_dir.join("south").join("b").join("b.json"));
+    assert_eq!(item["type"], "Feature");
+    assert_eq!(item["collection"], "south");
+    assert_eq!(item["properties"]["pc:count"], 100);
+    assert_eq!(item["properties"]["pc:type"], "lidar");
+    assert_eq!(item["properties"]["pc:schemas"][0]["name"], "X");
+    assert_eq!(item["properties"]["proj:bbox"].as_array().unwrap().len(), 4);
+    let href = item["assets"]["data"]["href"].as_str().unwrap();
+    assert!(Path::new(href).is_absolute());
+    assert!(href.ends_with("b.las"));
+}
+
+#[test]
+fn test_process_folder_vpc() {
+    let temp_dir = setup();
+    let folder_path = temp_dir.path().join("tiles");
+    fs::create_dir_all(&folder_path).unwrap();
+    for (name, offset) in [("a.las", 0.0), ("b.las", 0.09)] {
+        let points = grid_points(10)
+            .into_iter()
+            .map(|p| Point {
+                x: p.x + offset,
+                ..p
+            })
+            .collect();
+        create_las_file(folder_path.join(name).to_str().unwrap(), points);
+    }
+    let output_path = temp_dir.path().join("tiles.vpc");
+    let config = ProcessConfig {
+        folder_path: folder_path.to_str().unwrap().to_string(),
+        guess_crs: true,
+        // Ignored, a virtual point cloud needs the outline of each file
+        merge_tiled: true,
+        output_file: Some(output_path.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+    process_folder(config).unwrap();
+
+    let vpc: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&output_path).unwrap()).unwrap();
+    assert_eq!(vpc["type"], "FeatureCollection");
+    let features = vpc["features"].as_array().unwrap();
+    assert_eq!(features.len(), 2);
+    let item = &features[0];
+    assert_eq!(item["id"], "a");
+    assert_eq!(item["stac_version"], "1.0.0");
+    assert_eq!(item["assets"]["data"]["href"], "./tiles/a.las");
+    assert_eq!(item["properties"]["pc:count"], 100);
+    assert_eq!(item["bbox"].as_array().unwrap().len(), 6);
+    let native_bbox = item["properties"]["proj:bbox"].as_array().unwrap();
+    assert_eq!(native_bbox.len(), 6);
+    assert_abs_diff_eq!(native_bbox[3].as_f64().unwrap(), 170.09, epsilon = 1e-6);
+    // The day of the header date
+    assert!(item["properties"]["datetime"].is_null());
+    assert!(item["properties"]["start_datetime"].is_string());
+}
@@ -41,6 +41,20 @@ fn test_process_folder() {
     // Check if the output file is created
     assert!(Path::new(&output_path).exists());
 }
+#[test]
+fn test_folder_named_repair() {
+    let tempdir = setup();
+    fs::create_dir(tempdir.path().join("repair")).unwrap();
+
+    let mut cmd = Command::cargo_bin("las-poly").unwrap();
+    cmd.current_dir(tempdir.path())
+        .arg("./repair")
+        .arg("output.geojson")
+        .assert()
+        .success();
+    assert!(tempdir.path().join("output.geojson").exists());
+}
+
 #[test]
 fn test_invalid_folder() {
     let invalid_folder = "invalid_folder";
@@ -52,3 +66,18 @@ fn test_invalid_folder() {
         .stderr(predicate::str::contains("Error"));
 }
 
+#[test]
+fn test_optional_values_need_equals() {
+    // A bare flag before the folder must not take the folder as its value
+    for flag in ["--point-spacing", "--validate"] {
+        let tempdir = setup();
+        let output_path = tempdir.path().join("output.geojson");
+        let mut cmd = Command::cargo_bin("las-poly").unwrap();
+        cmd.arg(flag)
+            .arg(tempdir.path())
+            .arg(&output_path)
+            .assert()
+            .success();
+        assert!(output_path.exists());
+    }
+}